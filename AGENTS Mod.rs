@@ -1,6 +1,7 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use crate::llm::ToolDefinition;
 
 pub mod tools;
 pub mod executor;
@@ -11,6 +12,14 @@ pub trait Tool: Send + Sync {
     fn name(&self) -> &str;
     fn description(&self) -> &str;
     fn parameters(&self) -> ToolParameters;
+    
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: self.name().to_string(),
+            description: self.description().to_string(),
+            parameters: self.parameters().to_json_schema(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +29,34 @@ pub struct ToolParameters {
     pub schema: serde_json::Value,
 }
 
+impl ToolParameters {
+    /// JSON Schema for the tool arguments, as expected by function calling APIs.
+    ///
+    /// `schema` is either a full JSON Schema object or a shorthand map of
+    /// parameter names to type names, e.g. `{"query": "string"}`.
+    pub fn to_json_schema(&self) -> serde_json::Value {
+        if self.schema.get("type").is_some() {
+            return self.schema.clone();
+        }
+        
+        let mut properties = serde_json::Map::new();
+        
+        for name in self.required.iter().chain(&self.optional) {
+            let type_name = self.schema
+                .get(name)
+                .and_then(|t| t.as_str())
+                .unwrap_or("string");
+            properties.insert(name.clone(), serde_json::json!({ "type": type_name }));
+        }
+        
+        serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": self.required,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolOutput {
     pub result: String,
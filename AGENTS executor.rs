@@ -1,5 +1,5 @@
 use super::{Tool, ToolOutput};
-use crate::llm::{LLMProvider, LLMRequest};
+use crate::llm::{LLMProvider, LLMRequest, ToolCall, ToolDefinition};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -33,11 +33,19 @@ impl AgentExecutor {
             
             // Ask LLM to decide what to do
             let reasoning_prompt = self.build_reasoning_prompt(&current_input, iteration);
-            let request = LLMRequest::new(reasoning_prompt);
+            let request = LLMRequest::new(reasoning_prompt)
+                .with_tools(self.tool_definitions());
             let response = self.llm.generate(&request).await?;
             
-            // Parse LLM response to extract action
-            let action = self.parse_action(&response.text)?;
+            // Prefer structured tool calls; fall back to the text format
+            let action = match response.tool_calls.first() {
+                Some(call) => AgentAction {
+                    thought: response.text.trim().to_string(),
+                    action_type: call.name.clone(),
+                    action_input: tool_input(call),
+                },
+                None => self.parse_action(&response.text)?,
+            };
             
             let step_result = if action.action_type == "final_answer" {
                 // Agent has finished
@@ -78,6 +86,10 @@ impl AgentExecutor {
         Err(anyhow::anyhow!("Agent exceeded maximum iterations"))
     }
     
+    fn tool_definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.values().map(|t| t.definition()).collect()
+    }
+    
     fn build_reasoning_prompt(&self, input: &str, iteration: usize) -> String {
         let tools_desc = self.tools
             .values()
@@ -134,6 +146,20 @@ Begin!"#,
     }
 }
 
+// Tools take a single string input; unwrap single-argument calls and pass
+// anything else through as JSON
+fn tool_input(call: &ToolCall) -> String {
+    match &call.arguments {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Object(args) if args.len() == 1 => match args.values().next() {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+            None => String::new(),
+        },
+        other => other.to_string(),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentAction {
     pub thought: String,
@@ -1,13 +1,23 @@
 use super::AppState;
 use super::routes::*;
 use axum::{
-    extract::{State, Path},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
-use crate::llm::LLMRequest;
-use crate::chains::ChainInput;
+use crate::llm::structured::{self, StructuredOutputError};
+use crate::llm::{error::LLMError, semantic_cache::SemanticCache, LLMProvider, LLMRequest, StreamChunk, TokenUsage};
+use crate::chains::{prompt::PromptError, ChainEvent, ChainEvents, ChainInput, ChainSpend};
+use crate::monitoring::budget::{BudgetError, BudgetManager, LimitKind, Tenant, ANONYMOUS_TENANT};
+use crate::monitoring::MetricsCollector;
+use futures::{Stream, StreamExt};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 
 // Health Check
 pub async fn health_check() -> impl IntoResponse {
@@ -34,6 +44,7 @@ pub async fn status(State(state): State<AppState>) -> impl IntoResponse {
 // LLM Generate
 pub async fn llm_generate(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<GenerateRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     state.metrics.record_request();
@@ -42,27 +53,138 @@ pub async fn llm_generate(
         .get_provider(req.provider.as_deref())
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
     
-    let llm_request = LLMRequest::new(req.prompt)
+    let billing = check_budget(&state, &headers, None).await?;
+    
+    let mut llm_request = LLMRequest::new(req.prompt)
+        .with_messages(req.messages.unwrap_or_default())
         .with_temperature(req.temperature.unwrap_or(0.7))
         .with_max_tokens(req.max_tokens.unwrap_or(2048));
     
-    let response = provider
-        .generate(&llm_request)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Some(model) = req.model {
+        llm_request = llm_request.with_model(model);
+    }
+    
+    let (response, parsed) = match req.response_format {
+        Some(format) => {
+            let structured = structured::generate(
+                provider.as_ref(),
+                &llm_request.with_response_format(format),
+                structured::DEFAULT_MAX_REPAIRS,
+            )
+            .await
+            .map_err(|e| {
+                record_failed_spend(&state.metrics, billing.as_ref(), &e);
+                llm_error_response(e)
+            })?;
+            
+            (structured.response, Some(structured.value))
+        }
+        None => (
+            provider.generate(&llm_request).await.map_err(llm_error_response)?,
+            None,
+        ),
+    };
     
     state.metrics.record_llm_latency(response.latency_ms);
-    state.metrics.record_token_usage(response.tokens_used.total_tokens);
+    state.metrics.record_token_usage(response.billed_tokens());
+    
+    let cost = response.cost;
+    
+    if let Some(billing) = &billing {
+        billing.record(response.billed_tokens(), cost);
+    }
     
     Ok(Json(GenerateResponse {
         text: response.text,
-        model: response.model.clone(),
+        model: response.model,
         tokens_used: response.tokens_used.total_tokens,
         latency_ms: response.latency_ms,
-        cost: response.tokens_used.estimate_cost(&response.model),
+        cost,
+        parsed,
     }))
 }
 
+// LLM Generate (SSE)
+pub async fn llm_generate_stream(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<GenerateRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    state.metrics.record_request();
+    
+    let provider = state.provider_manager
+        .get_provider(req.provider.as_deref())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    
+    let billing = check_budget(&state, &headers, None).await?;
+    
+    let mut llm_request = LLMRequest::new(req.prompt)
+        .with_messages(req.messages.unwrap_or_default())
+        .with_temperature(req.temperature.unwrap_or(0.7))
+        .with_max_tokens(req.max_tokens.unwrap_or(2048));
+    
+    if let Some(model) = req.model {
+        llm_request = llm_request.with_model(model);
+    }
+    
+    // Streamed replies are constrained natively where supported, but can't be validated
+    if let Some(format) = req.response_format {
+        llm_request = llm_request.with_response_format(format);
+    }
+    
+    let start = std::time::Instant::now();
+    
+    // Dropping the SSE body on disconnect drops the provider stream with it
+    let stream = provider
+        .stream_generate(&llm_request)
+        .await
+        .map_err(llm_error_response)?;
+    
+    // Moved into the closure, so it is dropped with the SSE body
+    let mut spend = StreamSpend {
+        provider,
+        request: llm_request,
+        text: String::new(),
+        metrics: state.metrics.clone(),
+        billing,
+        finished: false,
+    };
+    
+    let events = stream.map(move |chunk| {
+        let event = match chunk {
+            Ok(StreamChunk::Delta(text)) => {
+                spend.text.push_str(&text);
+                sse_event("token", serde_json::json!({ "text": text }))
+            }
+            Ok(StreamChunk::Done { model, tokens_used, finish_reason, cost, cached, .. }) => {
+                spend.finished = true;
+                
+                let latency_ms = start.elapsed().as_millis() as u64;
+                let billed_tokens = if cached { 0 } else { tokens_used.total_tokens };
+                spend.metrics.record_llm_latency(latency_ms);
+                spend.metrics.record_token_usage(billed_tokens);
+                
+                if let Some(billing) = &spend.billing {
+                    billing.record(billed_tokens, cost);
+                }
+                
+                sse_event("done", GenerateStreamDone {
+                    cost,
+                    model,
+                    tokens_used: tokens_used.total_tokens,
+                    finish_reason,
+                    latency_ms,
+                })
+            }
+            Err(e) => sse_error(e),
+        };
+        
+        Ok(event)
+    });
+    
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 // List Providers
 pub async fn list_providers(State(state): State<AppState>) -> impl IntoResponse {
     let providers = state.provider_manager.list_providers();
@@ -79,10 +201,34 @@ pub async fn list_chains(State(state): State<AppState>) -> impl IntoResponse {
     }))
 }
 
+// Chain Graph
+pub async fn chain_graph(
+    State(state): State<AppState>,
+    Path(chain_id): Path<String>,
+    Query(query): Query<ChainGraphQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    if !state.config.chains.enable_graph_view {
+        return Err((StatusCode::NOT_FOUND, "Graph view is disabled".to_string()));
+    }
+    
+    let chain = state.chain_manager
+        .get_chain(&chain_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Chain not found".to_string()))?;
+    
+    let structure = chain.structure();
+    
+    Ok(match query.format {
+        GraphFormat::Json => Json(structure).into_response(),
+        GraphFormat::Dot => ([(header::CONTENT_TYPE, "text/vnd.graphviz")], structure.to_dot()).into_response(),
+        GraphFormat::Mermaid => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], structure.to_mermaid()).into_response(),
+    })
+}
+
 // Execute Chain
 pub async fn execute_chain(
     State(state): State<AppState>,
     Path(chain_id): Path<String>,
+    headers: HeaderMap,
     Json(req): Json<ExecuteChainRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     state.metrics.record_request();
@@ -92,6 +238,8 @@ pub async fn execute_chain(
         .get_chain(&chain_id)
         .ok_or_else(|| (StatusCode::NOT_FOUND, "Chain not found".to_string()))?;
     
+    let billing = check_budget(&state, &headers, Some(&chain_id)).await?;
+    
     let input = ChainInput {
         variables: req.variables,
     };
@@ -99,10 +247,17 @@ pub async fn execute_chain(
     let output = chain
         .execute(input)
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .map_err(|e| {
+            record_failed_spend(&state.metrics, billing.as_ref(), &e);
+            llm_error_response(e)
+        })?;
     
     state.metrics.record_token_usage(output.metadata.total_tokens);
     
+    if let Some(billing) = &billing {
+        billing.record(output.metadata.total_tokens, output.metadata.total_cost);
+    }
+    
     Ok(Json(ExecuteChainResponse {
         result: output.result,
         execution_time_ms: output.metadata.execution_time_ms,
@@ -111,6 +266,81 @@ pub async fn execute_chain(
     }))
 }
 
+// Execute Chain (SSE)
+pub async fn execute_chain_stream(
+    State(state): State<AppState>,
+    Path(chain_id): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<ExecuteChainRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    state.metrics.record_request();
+    state.metrics.record_chain_execution();
+    
+    let chain = state.chain_manager
+        .get_chain(&chain_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Chain not found".to_string()))?;
+    
+    let billing = check_budget(&state, &headers, Some(&chain_id)).await?;
+    
+    let input = ChainInput {
+        variables: req.variables,
+    };
+    
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let cancel = CancellationToken::new();
+    
+    let task = tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    tracing::info!("Client disconnected, cancelled chain {}", chain_id);
+                    Err(anyhow::anyhow!("Chain execution cancelled"))
+                }
+                result = chain.execute_streaming(input, ChainEvents::new(sender)) => result,
+            }
+        }
+    });
+    
+    // Cancels the chain task when the client goes away and the body is dropped
+    let guard = cancel.drop_guard();
+    let metrics = state.metrics.clone();
+    
+    let progress = futures::stream::poll_fn(move |cx| receiver.poll_recv(cx)).map(|event| match event {
+        ChainEvent::StepStart { .. } => sse_event("step_start", event),
+        ChainEvent::Token { text } => sse_event("token", serde_json::json!({ "text": text })),
+        ChainEvent::StepEnd { step } => sse_event("step_end", step),
+    });
+    
+    let done = futures::stream::once(async move {
+        let _guard = guard;
+        
+        match task.await {
+            Ok(Ok(output)) => {
+                metrics.record_token_usage(output.metadata.total_tokens);
+                
+                if let Some(billing) = &billing {
+                    billing.record(output.metadata.total_tokens, output.metadata.total_cost);
+                }
+                
+                sse_event("done", ExecuteChainResponse {
+                    result: output.result,
+                    execution_time_ms: output.metadata.execution_time_ms,
+                    total_tokens: output.metadata.total_tokens,
+                    total_cost: output.metadata.total_cost,
+                })
+            }
+            Ok(Err(e)) => {
+                record_failed_spend(&metrics, billing.as_ref(), &e);
+                sse_error(e)
+            }
+            Err(e) => sse_error(e),
+        }
+    });
+    
+    Ok(Sse::new(progress.chain(done).map(Ok)).keep_alive(KeepAlive::default()))
+}
+
 // Index Document (RAG)
 pub async fn index_document(
     State(_state): State<AppState>,
@@ -190,3 +420,233 @@ pub async fn clear_session(
 pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
     state.metrics.get_metrics()
 }
+
+// Invalidate Semantic Cache (entries similar to a prompt)
+pub async fn invalidate_semantic_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<InvalidateSemanticCacheRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    check_admin(&state, &headers)?;
+    let cache = semantic_cache(&state)?;
+    
+    let removed = cache
+        .invalidate_similar(&req.prompt, req.scope.as_deref())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    
+    Ok(Json(InvalidateSemanticCacheResponse { removed }))
+}
+
+// Delete Semantic Cache Entry
+pub async fn delete_semantic_cache_entry(
+    State(state): State<AppState>,
+    Path(entry_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    check_admin(&state, &headers)?;
+    let cache = semantic_cache(&state)?;
+    
+    cache
+        .invalidate(&entry_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    
+    Ok(Json(serde_json::json!({
+        "id": entry_id,
+        "success": true
+    })))
+}
+
+// Budget Spend (for the calling tenant)
+pub async fn budget_spend(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let budgets = state
+        .budgets
+        .as_ref()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Budgets are disabled".to_string()))?;
+    
+    let tenant = budgets
+        .resolve(api_key(budgets, &headers))
+        .map_err(|e| budget_error_response(e.into()))?;
+    
+    let report = budgets
+        .report(&tenant)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    
+    Ok(Json(report))
+}
+
+fn semantic_cache(state: &AppState) -> Result<&SemanticCache, (StatusCode, String)> {
+    state
+        .semantic_cache
+        .as_deref()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Semantic cache is disabled".to_string()))
+}
+
+/// Who pays for a request, resolved when budgets are enabled.
+struct Billing {
+    budgets: Arc<BudgetManager>,
+    tenant: Tenant,
+    chain: Option<String>,
+}
+
+impl Billing {
+    // Recorded in the background; a failed write loses accounting, not the response
+    fn record(&self, tokens: usize, cost: f64) {
+        let budgets = self.budgets.clone();
+        let tenant = self.tenant.clone();
+        let chain = self.chain.clone();
+        
+        tokio::spawn(async move {
+            if let Err(e) = budgets.record(&tenant, chain.as_deref(), tokens, cost).await {
+                tracing::warn!("Failed to record spend for tenant '{}': {}", tenant.id, e);
+            }
+        });
+    }
+}
+
+/// Bills a generation stream that ends without its final chunk, because the
+/// client went away or the provider failed, for the prompt and the text
+/// streamed so far.
+struct StreamSpend {
+    provider: Arc<dyn LLMProvider>,
+    request: LLMRequest,
+    text: String,
+    metrics: Arc<MetricsCollector>,
+    billing: Option<Billing>,
+    finished: bool,
+}
+
+impl Drop for StreamSpend {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        
+        let prompt_tokens = self.provider.count_prompt_tokens(&self.request).unwrap_or(0);
+        let completion_tokens = self.provider.count_tokens(&self.text).unwrap_or(0);
+        let usage = TokenUsage::new(prompt_tokens, completion_tokens);
+        
+        self.metrics.record_token_usage(usage.total_tokens);
+        
+        if let Some(billing) = &self.billing {
+            billing.record(usage.total_tokens, self.provider.estimate_cost(&self.request, &usage));
+        }
+    }
+}
+
+// Failed runs still paid for the LLM calls they made: a structured-output
+// call's attempts, or the steps a chain finished before failing
+fn record_failed_spend(metrics: &MetricsCollector, billing: Option<&Billing>, error: &anyhow::Error) {
+    let spend = ChainSpend::of(error);
+    if spend == ChainSpend::default() {
+        return;
+    }
+    
+    metrics.record_token_usage(spend.tokens);
+    
+    if let Some(billing) = billing {
+        billing.record(spend.tokens, spend.cost);
+    }
+}
+
+// Rejects the request before it reaches a provider if a hard limit is reached
+async fn check_budget(
+    state: &AppState,
+    headers: &HeaderMap,
+    chain: Option<&str>,
+) -> Result<Option<Billing>, (StatusCode, String)> {
+    let Some(budgets) = &state.budgets else {
+        return Ok(None);
+    };
+    
+    let tenant = budgets
+        .resolve(api_key(budgets, headers))
+        .map_err(|e| budget_error_response(e.into()))?;
+    
+    budgets
+        .check(&tenant, chain)
+        .await
+        .map_err(budget_error_response)?;
+    
+    Ok(Some(Billing {
+        budgets: budgets.clone(),
+        tenant,
+        chain: chain.map(str::to_string),
+    }))
+}
+
+// Admin routes need a known API key whenever keys are configured, even where
+// anonymous callers may generate
+fn check_admin(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    let Some(budgets) = &state.budgets else {
+        return Ok(());
+    };
+    
+    let tenant = budgets
+        .resolve(api_key(budgets, headers))
+        .map_err(|e| budget_error_response(e.into()))?;
+    
+    if tenant.id == ANONYMOUS_TENANT {
+        return Err(budget_error_response(BudgetError::UnknownApiKey.into()));
+    }
+    
+    Ok(())
+}
+
+fn api_key<'a>(budgets: &BudgetManager, headers: &'a HeaderMap) -> Option<&'a str> {
+    headers
+        .get(budgets.api_key_header())
+        .and_then(|value| value.to_str().ok())
+}
+
+fn budget_error_response(error: anyhow::Error) -> (StatusCode, String) {
+    let status = match error.downcast_ref::<BudgetError>() {
+        Some(BudgetError::UnknownApiKey) => StatusCode::UNAUTHORIZED,
+        Some(BudgetError::Exceeded { kind: LimitKind::Cost, .. }) => StatusCode::PAYMENT_REQUIRED,
+        Some(BudgetError::Exceeded { kind: LimitKind::Tokens, .. }) => StatusCode::TOO_MANY_REQUESTS,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    
+    (status, error.to_string())
+}
+
+fn sse_event(name: &str, data: impl serde::Serialize) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .unwrap_or_else(sse_error)
+}
+
+fn sse_error(error: impl std::fmt::Display) -> Event {
+    Event::default()
+        .event("error")
+        .data(error.to_string())
+}
+
+// Maps provider failures to a status the client can act on
+fn llm_error_response(error: anyhow::Error) -> (StatusCode, String) {
+    if error.chain().any(|e| e.is::<StructuredOutputError>()) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, error.to_string());
+    }
+    
+    // Missing or unexpected variables are the caller's mistake, even when a
+    // chain step wrapped the error
+    if let Some(prompt_error) = error.chain().find_map(|e| e.downcast_ref::<PromptError>()) {
+        return (StatusCode::BAD_REQUEST, prompt_error.to_string());
+    }
+    
+    let status = match LLMError::find(&error) {
+        Some(LLMError::RateLimited { .. }) => StatusCode::TOO_MANY_REQUESTS,
+        Some(LLMError::ContextLength { .. }) => StatusCode::BAD_REQUEST,
+        Some(LLMError::Timeout { .. }) => StatusCode::GATEWAY_TIMEOUT,
+        Some(_) => StatusCode::BAD_GATEWAY,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    
+    (status, error.to_string())
+}
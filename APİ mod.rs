@@ -1,5 +1,5 @@
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 use std::sync::Arc;
@@ -9,9 +9,11 @@ pub mod handlers;
 
 use crate::config::AppConfig;
 use crate::llm::provider::ProviderManager;
+use crate::llm::semantic_cache::SemanticCache;
 use crate::chains::manager::ChainManager;
 use crate::agents::executor::AgentExecutor;
 use crate::monitoring::MetricsCollector;
+use crate::monitoring::budget::BudgetManager;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -19,6 +21,8 @@ pub struct AppState {
     pub provider_manager: Arc<ProviderManager>,
     pub chain_manager: Arc<ChainManager>,
     pub metrics: Arc<MetricsCollector>,
+    pub semantic_cache: Option<Arc<SemanticCache>>,
+    pub budgets: Option<Arc<BudgetManager>>,
 }
 
 pub fn create_router(state: AppState) -> Router {
@@ -29,11 +33,14 @@ pub fn create_router(state: AppState) -> Router {
         
         // LLM Endpoints
         .route("/llm/generate", post(handlers::llm_generate))
+        .route("/llm/generate/stream", post(handlers::llm_generate_stream))
         .route("/llm/providers", get(handlers::list_providers))
         
         // Chain Endpoints
         .route("/chains", get(handlers::list_chains))
         .route("/chains/:id/execute", post(handlers::execute_chain))
+        .route("/chains/:id/stream", post(handlers::execute_chain_stream))
+        .route("/chains/:id/graph", get(handlers::chain_graph))
         
         // RAG Endpoints
         .route("/rag/index", post(handlers::index_document))
@@ -49,6 +56,11 @@ pub fn create_router(state: AppState) -> Router {
         
         // Monitoring
         .route("/metrics", get(handlers::metrics))
+        .route("/budget/spend", get(handlers::budget_spend))
+        
+        // Admin
+        .route("/admin/semantic-cache/invalidate", post(handlers::invalidate_semantic_cache))
+        .route("/admin/semantic-cache/:id", delete(handlers::delete_semantic_cache_entry))
         
         .with_state(state)
 }
@@ -1,13 +1,19 @@
 use serde::{Deserialize, Serialize};
+use crate::llm::structured::ResponseFormat;
+use crate::llm::ChatMessage;
 
 // LLM Requests/Responses
 #[derive(Debug, Deserialize)]
 pub struct GenerateRequest {
+    #[serde(default)]
     pub prompt: String,
+    pub messages: Option<Vec<ChatMessage>>,
     pub provider: Option<String>,
     pub model: Option<String>,
     pub temperature: Option<f32>,
     pub max_tokens: Option<usize>,
+    /// Ask for JSON matching a schema; the validated value is returned as `parsed`
+    pub response_format: Option<ResponseFormat>,
 }
 
 #[derive(Debug, Serialize)]
@@ -17,6 +23,17 @@ pub struct GenerateResponse {
     pub tokens_used: usize,
     pub latency_ms: u64,
     pub cost: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parsed: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GenerateStreamDone {
+    pub model: String,
+    pub tokens_used: usize,
+    pub finish_reason: String,
+    pub latency_ms: u64,
+    pub cost: f64,
 }
 
 // Chain Requests/Responses
@@ -33,6 +50,21 @@ pub struct ExecuteChainResponse {
     pub total_cost: f64,
 }
 
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    #[default]
+    Json,
+    Dot,
+    Mermaid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChainGraphQuery {
+    #[serde(default)]
+    pub format: GraphFormat,
+}
+
 // RAG Requests/Responses
 #[derive(Debug, Deserialize)]
 pub struct IndexDocumentRequest {
@@ -96,3 +128,15 @@ pub struct StatusResponse {
     pub total_requests: u64,
     pub active_chains: usize,
 }
+
+// Admin Requests/Responses
+#[derive(Debug, Deserialize)]
+pub struct InvalidateSemanticCacheRequest {
+    pub prompt: String,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidateSemanticCacheResponse {
+    pub removed: Vec<String>,
+}
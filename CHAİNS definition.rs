@@ -0,0 +1,529 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use super::manager::ChainManager;
+use super::parser::{EnumOutputParser, JsonOutputParser, ListOutputParser, OutputParser, RegexOutputParser};
+use super::pipeline::RAGPipeline;
+use super::prompt::{ChainPrompt, ChatPromptTemplate, PromptTemplate};
+use super::sequential::{SequentialChain, SequentialStep};
+use super::simple::SimpleChain;
+use super::summarize::{MapReduceChain, RefineChain};
+use super::Chain;
+use crate::llm::structured::ResponseFormat;
+use crate::llm::{GenerationParams, LLMProvider};
+use crate::memory::MessageRole;
+use crate::rag::retriever::Retriever;
+
+/// A chain declared in YAML, e.g.
+///
+/// ```yaml
+/// id: sentiment
+/// type: simple
+/// llm: { provider: openai, model: gpt-4o-mini, temperature: 0.0 }
+/// template: "Classify the sentiment of: {text}"
+/// output_parser: { type: enum, options: [positive, negative, neutral] }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChainDefinition {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(flatten)]
+    pub kind: ChainKind,
+    /// File the definition was read from, for error messages
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainKind {
+    Simple(SimpleChainSpec),
+    Sequential(SequentialChainSpec),
+    Rag(RagPipelineSpec),
+    MapReduce(MapReduceChainSpec),
+    Refine(RefineChainSpec),
+}
+
+/// Unknown keys are rejected so a misspelled setting isn't silently ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LlmSpec {
+    /// Provider name; the default provider when omitted
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+}
+
+impl LlmSpec {
+    pub fn params(&self) -> GenerationParams {
+        GenerationParams {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            top_p: self.top_p,
+            stop_sequences: self.stop_sequences.clone(),
+        }
+    }
+}
+
+/// Either `template` or chat `messages`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimpleChainSpec {
+    #[serde(default)]
+    pub llm: LlmSpec,
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub messages: Vec<MessageSpec>,
+    #[serde(default)]
+    pub output_parser: Option<OutputParserSpec>,
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageSpec {
+    pub role: MessageRole,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SequentialChainSpec {
+    pub steps: Vec<StepSpec>,
+    /// Variables returned as the result; the last step's result when empty
+    #[serde(default)]
+    pub output_variables: Vec<String>,
+}
+
+/// A step of a sequential chain: the id of another chain, defined in YAML or
+/// registered in code.
+///
+/// `inputs` maps the step's variable names to the sequence's variables, and
+/// `outputs` maps variable names to fields of the step's result:
+///
+/// ```yaml
+/// - chain: summarize
+///   inputs: { text: article }
+///   outputs: { summary: output }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct StepSpec {
+    pub chain: String,
+    #[serde(default)]
+    pub output_parser: Option<OutputParserSpec>,
+    #[serde(default)]
+    pub inputs: BTreeMap<String, String>,
+    #[serde(default)]
+    pub outputs: BTreeMap<String, String>,
+}
+
+/// The template gets `{context}` from the retriever and `{query}` from the input.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RagPipelineSpec {
+    #[serde(default)]
+    pub llm: LlmSpec,
+    pub template: String,
+    /// Vector store collection to retrieve from
+    pub retriever: String,
+    #[serde(default)]
+    pub output_parser: Option<OutputParserSpec>,
+}
+
+/// Map-reduce summarization of `{text}`; both templates take `{text}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MapReduceChainSpec {
+    #[serde(default)]
+    pub llm: LlmSpec,
+    pub map_template: String,
+    pub combine_template: String,
+    #[serde(default)]
+    pub token_budget: Option<usize>,
+    #[serde(default)]
+    pub chunk_overlap: Option<usize>,
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+}
+
+/// Refine summarization of `{text}`; `refine_template` also takes `{summary}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RefineChainSpec {
+    #[serde(default)]
+    pub llm: LlmSpec,
+    pub initial_template: String,
+    pub refine_template: String,
+    #[serde(default)]
+    pub token_budget: Option<usize>,
+    #[serde(default)]
+    pub chunk_overlap: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputParserSpec {
+    Json {
+        #[serde(default)]
+        schema: Option<serde_json::Value>,
+    },
+    CommaList,
+    LineList,
+    Enum {
+        options: Vec<String>,
+    },
+    Regex {
+        pattern: String,
+        #[serde(default)]
+        instructions: Option<String>,
+    },
+}
+
+impl OutputParserSpec {
+    pub fn build(&self) -> Result<Arc<dyn OutputParser>> {
+        Ok(match self {
+            OutputParserSpec::Json { schema: None } => Arc::new(JsonOutputParser::new()),
+            OutputParserSpec::Json { schema: Some(schema) } => Arc::new(JsonOutputParser::new().with_schema(schema.clone())),
+            OutputParserSpec::CommaList => Arc::new(ListOutputParser::comma_separated()),
+            OutputParserSpec::LineList => Arc::new(ListOutputParser::line_separated()),
+            OutputParserSpec::Enum { options } => {
+                if options.is_empty() {
+                    anyhow::bail!("enum parser needs at least one option");
+                }
+                Arc::new(EnumOutputParser::new(options.clone()))
+            }
+            OutputParserSpec::Regex { pattern, instructions } => {
+                let regex = Regex::new(pattern).with_context(|| format!("Invalid regex '{}'", pattern))?;
+                let parser = RegexOutputParser::new(regex);
+                Arc::new(match instructions {
+                    Some(instructions) => parser.with_instructions(instructions.clone()),
+                    None => parser,
+                })
+            }
+        })
+    }
+}
+
+impl ChainDefinition {
+    /// Collection of the retriever a RAG chain needs.
+    pub fn retriever(&self) -> Option<&str> {
+        match &self.kind {
+            ChainKind::Rag(spec) => Some(&spec.retriever),
+            _ => None,
+        }
+    }
+    
+    fn location(&self) -> String {
+        match &self.source {
+            Some(path) => format!("chain '{}' ({})", self.id, path.display()),
+            None => format!("chain '{}'", self.id),
+        }
+    }
+}
+
+/// Reads the definitions in every `.yaml`/`.yml` file of `dir`, in file name order.
+pub fn read_dir(dir: &Path) -> Result<Vec<ChainDefinition>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read chain definitions from {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| matches!(path.extension().and_then(|e| e.to_str()), Some("yaml" | "yml")))
+        .collect();
+    paths.sort();
+    
+    let mut definitions = Vec::new();
+    
+    for path in paths {
+        definitions.extend(read_file(&path)?);
+    }
+    
+    Ok(definitions)
+}
+
+pub fn read_file(path: &Path) -> Result<Vec<ChainDefinition>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read chain definition {}", path.display()))?;
+    
+    // A file holds one definition or a list of them
+    let yaml: serde_yaml::Value = serde_yaml::from_str(&content)
+        .with_context(|| format!("Invalid YAML in {}", path.display()))?;
+    
+    let mut definitions = match yaml {
+        serde_yaml::Value::Sequence(items) => items
+            .into_iter()
+            .map(serde_yaml::from_value)
+            .collect::<Result<Vec<ChainDefinition>, _>>(),
+        value => serde_yaml::from_value(value).map(|definition| vec![definition]),
+    }
+    .with_context(|| format!("Invalid chain definition {}", path.display()))?;
+    
+    for definition in &mut definitions {
+        definition.source = Some(path.to_path_buf());
+    }
+    
+    Ok(definitions)
+}
+
+type LlmResolver = dyn Fn(&str, Option<&str>) -> Result<Arc<dyn LLMProvider>> + Send + Sync;
+
+/// Builds chains from definitions and registers them with a `ChainManager`.
+pub struct ChainLoader {
+    // (chain id, provider name) -> provider, so callers can wrap per chain
+    resolve_llm: Box<LlmResolver>,
+    retrievers: HashMap<String, Arc<Retriever>>,
+}
+
+impl ChainLoader {
+    /// `resolve_llm` is called with the chain id and the provider named in
+    /// the definition, if any.
+    pub fn new(resolve_llm: impl Fn(&str, Option<&str>) -> Result<Arc<dyn LLMProvider>> + Send + Sync + 'static) -> Self {
+        Self {
+            resolve_llm: Box::new(resolve_llm),
+            retrievers: HashMap::new(),
+        }
+    }
+    
+    /// Makes a retriever available to RAG chains under `collection`.
+    pub fn with_retriever(mut self, collection: impl Into<String>, retriever: Arc<Retriever>) -> Self {
+        self.retrievers.insert(collection.into(), retriever);
+        self
+    }
+    
+    /// Builds every definition and registers them under their ids. Nothing is
+    /// registered unless all of them are valid and none of the ids is already
+    /// taken in `manager`.
+    pub fn load(&self, definitions: &[ChainDefinition], manager: &ChainManager) -> Result<Vec<String>> {
+        if let Some(taken) = definitions.iter().find(|definition| manager.get_chain(&definition.id).is_some()) {
+            anyhow::bail!("{} uses an id that is already registered", taken.location());
+        }
+        
+        let chains = self.build(definitions, manager)?;
+        let ids = chains.iter().map(|(id, _)| id.clone()).collect();
+        
+        for (id, chain) in chains {
+            manager.register_chain(id, chain);
+        }
+        
+        Ok(ids)
+    }
+    
+    /// Builds every definition. Sequential steps may refer to chains in
+    /// `definitions` or already in `manager`.
+    pub fn build(&self, definitions: &[ChainDefinition], manager: &ChainManager) -> Result<Vec<(String, Arc<dyn Chain>)>> {
+        let mut by_id: HashMap<&str, &ChainDefinition> = HashMap::new();
+        
+        for definition in definitions {
+            if let Some(previous) = by_id.insert(&definition.id, definition) {
+                anyhow::bail!("{} is defined twice, also in {}", definition.location(), previous.location());
+            }
+        }
+        
+        let mut builder = Builder {
+            loader: self,
+            definitions: by_id,
+            manager,
+            built: HashMap::new(),
+            in_progress: Vec::new(),
+        };
+        
+        definitions
+            .iter()
+            .map(|definition| Ok((definition.id.clone(), builder.chain(&definition.id)?)))
+            .collect()
+    }
+}
+
+struct Builder<'a> {
+    loader: &'a ChainLoader,
+    definitions: HashMap<&'a str, &'a ChainDefinition>,
+    manager: &'a ChainManager,
+    built: HashMap<String, Arc<dyn Chain>>,
+    // Ids being built, to report reference cycles
+    in_progress: Vec<String>,
+}
+
+impl Builder<'_> {
+    fn chain(&mut self, id: &str) -> Result<Arc<dyn Chain>> {
+        if let Some(chain) = self.built.get(id) {
+            return Ok(chain.clone());
+        }
+        
+        let Some(definition) = self.definitions.get(id).copied() else {
+            return self.manager
+                .get_chain(id)
+                .ok_or_else(|| anyhow::anyhow!("Unknown chain '{}'", id));
+        };
+        
+        if self.in_progress.iter().any(|building| building == id) {
+            anyhow::bail!("Chains refer to each other in a cycle: {} -> {}", self.in_progress.join(" -> "), id);
+        }
+        
+        self.in_progress.push(id.to_string());
+        let chain = self.build(definition).with_context(|| format!("Invalid {}", definition.location()));
+        self.in_progress.pop();
+        
+        let chain = chain?;
+        self.built.insert(id.to_string(), chain.clone());
+        
+        Ok(chain)
+    }
+    
+    fn build(&mut self, definition: &ChainDefinition) -> Result<Arc<dyn Chain>> {
+        let name = definition.name.clone().unwrap_or_else(|| definition.id.clone());
+        let description = definition.description.clone();
+        
+        Ok(match &definition.kind {
+            ChainKind::Simple(spec) => {
+                let llm = self.llm(&definition.id, &spec.llm)?;
+                
+                let mut chain = SimpleChain::new(name, description, llm, simple_prompt(spec)?)
+                    .with_params(spec.llm.params());
+                
+                if let Some(format) = &spec.response_format {
+                    chain = chain.with_response_format(format.clone());
+                }
+                
+                if let Some(parser) = &spec.output_parser {
+                    chain = chain.with_output_parser(parser.build()?);
+                }
+                
+                Arc::new(chain)
+            }
+            ChainKind::Sequential(spec) => {
+                if spec.steps.is_empty() {
+                    anyhow::bail!("a sequential chain needs at least one step");
+                }
+                
+                let mut chain = SequentialChain::new(name, description)
+                    .with_output_variables(spec.output_variables.iter().cloned());
+                
+                for step in &spec.steps {
+                    let mut sequential_step = SequentialStep::new(self.chain(&step.chain)?);
+                    
+                    if let Some(parser) = &step.output_parser {
+                        sequential_step = sequential_step.with_output_parser(parser.build()?);
+                    }
+                    
+                    for (to, from) in &step.inputs {
+                        sequential_step = sequential_step.with_input(from, to);
+                    }
+                    
+                    for (to, from) in &step.outputs {
+                        sequential_step = sequential_step.with_output(from, to);
+                    }
+                    
+                    chain = chain.add_step(sequential_step);
+                }
+                
+                Arc::new(chain)
+            }
+            ChainKind::Rag(spec) => {
+                let llm = self.llm(&definition.id, &spec.llm)?;
+                
+                let retriever = self.loader.retrievers
+                    .get(&spec.retriever)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Unknown retriever collection '{}'", spec.retriever))?;
+                
+                let template = PromptTemplate::new(spec.template.as_str())?;
+                if !template.has_variable("context") {
+                    anyhow::bail!("a RAG template must use {{context}}");
+                }
+                
+                let mut chain = RAGPipeline::new(name, description, llm, retriever, template)
+                    .with_params(spec.llm.params());
+                
+                if let Some(parser) = &spec.output_parser {
+                    chain = chain.with_output_parser(parser.build()?);
+                }
+                
+                Arc::new(chain)
+            }
+            ChainKind::MapReduce(spec) => {
+                let llm = self.llm(&definition.id, &spec.llm)?;
+                
+                let mut chain = MapReduceChain::new(
+                    name,
+                    description,
+                    llm,
+                    summary_template(&spec.map_template, &["text"])?,
+                    summary_template(&spec.combine_template, &["text"])?,
+                )
+                .with_params(spec.llm.params());
+                
+                if let Some(tokens) = spec.token_budget {
+                    chain = chain.with_token_budget(tokens);
+                }
+                
+                if let Some(overlap) = spec.chunk_overlap {
+                    chain = chain.with_chunk_overlap(overlap);
+                }
+                
+                if let Some(max_concurrency) = spec.max_concurrency {
+                    chain = chain.with_max_concurrency(max_concurrency);
+                }
+                
+                Arc::new(chain)
+            }
+            ChainKind::Refine(spec) => {
+                let llm = self.llm(&definition.id, &spec.llm)?;
+                
+                let mut chain = RefineChain::new(
+                    name,
+                    description,
+                    llm,
+                    summary_template(&spec.initial_template, &["text"])?,
+                    summary_template(&spec.refine_template, &["summary", "text"])?,
+                )
+                .with_params(spec.llm.params());
+                
+                if let Some(tokens) = spec.token_budget {
+                    chain = chain.with_token_budget(tokens);
+                }
+                
+                if let Some(overlap) = spec.chunk_overlap {
+                    chain = chain.with_chunk_overlap(overlap);
+                }
+                
+                Arc::new(chain)
+            }
+        })
+    }
+    
+    fn llm(&self, id: &str, spec: &LlmSpec) -> Result<Arc<dyn LLMProvider>> {
+        (self.loader.resolve_llm)(id, spec.provider.as_deref())
+    }
+}
+
+// Summarization templates are rendered with exactly these variables
+fn summary_template(template: &str, variables: &[&str]) -> Result<PromptTemplate> {
+    let template = PromptTemplate::new(template)?;
+    
+    if let Some(missing) = variables.iter().find(|name| !template.has_variable(name)) {
+        anyhow::bail!("a summarization template must use {{{}}}", missing);
+    }
+    
+    template.with_input_variables(variables.iter().copied())
+}
+
+fn simple_prompt(spec: &SimpleChainSpec) -> Result<ChainPrompt> {
+    match (&spec.template, spec.messages.is_empty()) {
+        (Some(template), true) => Ok(PromptTemplate::new(template.as_str())?.into()),
+        (None, false) => {
+            let messages = spec.messages.iter().map(|m| (m.role, m.content.as_str()));
+            Ok(ChatPromptTemplate::from_messages(messages)?.into())
+        }
+        _ => anyhow::bail!("set exactly one of `template` and `messages`"),
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use super::prompt::{check_variables, PromptTemplate};
+use crate::embeddings::EmbeddingProvider;
+use crate::llm::tokenizer::TokenCounter;
+use crate::llm::ChatMessage;
+use crate::memory::VectorMemory;
+
+/// One few-shot example: the variables of its example template.
+pub type Example = Map<String, Value>;
+
+/// Picks the examples shown to the model for a given set of input variables.
+#[async_trait]
+pub trait ExampleSelector: Send + Sync {
+    async fn select(&self, variables: &Map<String, Value>) -> Result<Vec<Example>>;
+    async fn add_example(&self, example: Example) -> Result<()>;
+}
+
+/// Takes examples in order while they fit in `max_tokens`, counting the
+/// input variables against the same budget, so long inputs get fewer
+/// examples.
+pub struct LengthBasedExampleSelector {
+    examples: RwLock<Vec<Example>>,
+    example_prompt: PromptTemplate,
+    max_tokens: usize,
+    counter: TokenCounter,
+}
+
+impl LengthBasedExampleSelector {
+    pub fn new(examples: Vec<Example>, example_prompt: PromptTemplate, max_tokens: usize) -> Self {
+        Self {
+            examples: RwLock::new(examples),
+            example_prompt,
+            max_tokens,
+            counter: TokenCounter::Estimate,
+        }
+    }
+    
+    /// Counts tokens with the target model's tokenizer instead of estimating.
+    pub fn with_token_counter(mut self, counter: TokenCounter) -> Self {
+        self.counter = counter;
+        self
+    }
+}
+
+#[async_trait]
+impl ExampleSelector for LengthBasedExampleSelector {
+    async fn select(&self, variables: &Map<String, Value>) -> Result<Vec<Example>> {
+        let input_tokens: usize = variables
+            .values()
+            .filter_map(Value::as_str)
+            .map(|text| self.counter.count(text))
+            .sum();
+        
+        let mut remaining = self.max_tokens.saturating_sub(input_tokens);
+        let mut selected = Vec::new();
+        
+        for example in self.examples.read().iter() {
+            let rendered = self.example_prompt.render(&self.example_prompt.select_variables(example))?;
+            let tokens = self.counter.count(&rendered);
+            
+            if tokens > remaining {
+                break;
+            }
+            
+            remaining -= tokens;
+            selected.push(example.clone());
+        }
+        
+        Ok(selected)
+    }
+    
+    async fn add_example(&self, example: Example) -> Result<()> {
+        self.examples.write().push(example);
+        Ok(())
+    }
+}
+
+/// Picks the `k` examples most similar to the input.
+///
+/// Examples are embedded on the values of `input_keys` and stored with the
+/// whole example as metadata; queries embed the same keys of the input.
+pub struct SemanticSimilarityExampleSelector {
+    embeddings: Arc<dyn EmbeddingProvider>,
+    store: Arc<dyn VectorMemory>,
+    input_keys: Vec<String>,
+    k: usize,
+    min_score: f32,
+}
+
+impl SemanticSimilarityExampleSelector {
+    pub fn new(
+        embeddings: Arc<dyn EmbeddingProvider>,
+        store: Arc<dyn VectorMemory>,
+        input_keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            embeddings,
+            store,
+            input_keys: input_keys.into_iter().map(Into::into).collect(),
+            k: 4,
+            min_score: -1.0,
+        }
+    }
+    
+    pub fn with_k(mut self, k: usize) -> Self {
+        self.k = k;
+        self
+    }
+    
+    /// Leaves out examples less similar than `min_score` even if fewer than
+    /// `k` remain.
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = min_score;
+        self
+    }
+    
+    pub async fn add_examples(&self, examples: impl IntoIterator<Item = Example>) -> Result<()> {
+        for example in examples {
+            self.add_example(example).await?;
+        }
+        
+        Ok(())
+    }
+    
+    fn key_text(&self, variables: &Map<String, Value>) -> String {
+        self.input_keys
+            .iter()
+            .filter_map(|key| variables.get(key))
+            .map(|value| match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[async_trait]
+impl ExampleSelector for SemanticSimilarityExampleSelector {
+    async fn select(&self, variables: &Map<String, Value>) -> Result<Vec<Example>> {
+        let query = self.embeddings.embed_query(&self.key_text(variables)).await?;
+        let results = self.store.search(query, self.k, self.min_score).await?;
+        
+        Ok(results
+            .into_iter()
+            .filter_map(|result| match result.metadata {
+                Value::Object(example) => Some(example),
+                _ => None,
+            })
+            .collect())
+    }
+    
+    async fn add_example(&self, example: Example) -> Result<()> {
+        let text = self.key_text(&example);
+        let embedding = self.embeddings.embed_query(&text).await?;
+        let id = uuid::Uuid::new_v4().to_string();
+        
+        self.store.store(&id, &text, embedding, Value::Object(example)).await
+    }
+}
+
+/// A string prompt of `prefix`, the selected examples rendered with
+/// `example_prompt`, then `suffix`, separated by blank lines.
+///
+/// The input variables are those of the prefix and suffix; examples are
+/// selected on the same variables.
+#[derive(Clone)]
+pub struct FewShotPromptTemplate {
+    selector: Arc<dyn ExampleSelector>,
+    example_prompt: PromptTemplate,
+    prefix: Option<PromptTemplate>,
+    suffix: PromptTemplate,
+    separator: String,
+}
+
+impl FewShotPromptTemplate {
+    pub fn new(selector: Arc<dyn ExampleSelector>, example_prompt: PromptTemplate, suffix: PromptTemplate) -> Self {
+        Self {
+            selector,
+            example_prompt,
+            prefix: None,
+            suffix,
+            separator: "\n\n".to_string(),
+        }
+    }
+    
+    pub fn with_prefix(mut self, prefix: PromptTemplate) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+    
+    pub fn with_separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+    
+    pub fn has_variable(&self, name: &str) -> bool {
+        self.suffix.has_variable(name) || self.prefix.as_ref().is_some_and(|prefix| prefix.has_variable(name))
+    }
+    
+    pub fn input_variables(&self) -> Vec<String> {
+        let mut names = self.suffix.input_variables();
+        
+        if let Some(prefix) = &self.prefix {
+            names.extend(prefix.input_variables());
+        }
+        
+        names.sort();
+        names.dedup();
+        names
+    }
+    
+    pub async fn format(&self, variables: &Map<String, Value>) -> Result<String> {
+        check_variables(self.input_variables(), variables)?;
+        
+        let mut parts = Vec::new();
+        
+        if let Some(prefix) = &self.prefix {
+            parts.push(prefix.render(&prefix.select_variables(variables))?);
+        }
+        
+        for example in self.selector.select(variables).await? {
+            parts.push(self.example_prompt.render(&self.example_prompt.select_variables(&example))?);
+        }
+        
+        parts.push(self.suffix.render(&self.suffix.select_variables(variables))?);
+        
+        Ok(parts.join(&self.separator))
+    }
+}
+
+/// Few-shot examples for a `ChatPromptTemplate`: each selected example
+/// becomes a user turn rendered with `input` and an assistant turn
+/// rendered with `output`.
+#[derive(Clone)]
+pub struct FewShotChatMessages {
+    selector: Arc<dyn ExampleSelector>,
+    input: PromptTemplate,
+    output: PromptTemplate,
+}
+
+impl FewShotChatMessages {
+    pub fn new(selector: Arc<dyn ExampleSelector>, input: PromptTemplate, output: PromptTemplate) -> Self {
+        Self { selector, input, output }
+    }
+    
+    pub async fn format_messages(&self, variables: &Map<String, Value>) -> Result<Vec<ChatMessage>> {
+        let mut messages = Vec::new();
+        
+        for example in self.selector.select(variables).await? {
+            messages.push(ChatMessage::user(self.input.render(&self.input.select_variables(&example))?));
+            messages.push(ChatMessage::assistant(self.output.render(&self.output.select_variables(&example))?));
+        }
+        
+        Ok(messages)
+    }
+}
@@ -0,0 +1,334 @@
+use super::{Chain, ChainEvent, ChainEvents, ChainInput, ChainOutput, ChainMetadata, ChainSpend, StepInfo};
+use super::structure::ChainStructure;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::stream::{FuturesUnordered, StreamExt};
+use petgraph::algo::has_path_connecting;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Copies output field `from` of one node into input variable `to` of the
+/// next. `from` may be a dotted path, e.g. `parsed.city`.
+#[derive(Debug, Clone)]
+pub struct FieldMapping {
+    pub from: String,
+    pub to: String,
+}
+
+impl FieldMapping {
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+}
+
+impl<F: Into<String>, T: Into<String>> From<(F, T)> for FieldMapping {
+    fn from((from, to): (F, T)) -> Self {
+        Self::new(from, to)
+    }
+}
+
+struct GraphNode {
+    id: String,
+    chain: Arc<dyn Chain>,
+    // Graph input variables passed to this node besides its edges
+    inputs: Vec<FieldMapping>,
+}
+
+/// Runs chains as a directed acyclic graph.
+///
+/// Nodes without incoming edges receive the graph input variables their
+/// chain reads (see `Chain::input_keys`), or only the ones given with
+/// `map_input`. The others receive the fields mapped along their edges (plus
+/// any graph inputs given with `map_input`) and start as soon as all their
+/// predecessors finish, so independent branches run concurrently. Edges that
+/// would close a cycle, or that repeat an existing edge, are rejected when
+/// added.
+///
+/// Nodes don't stream tokens since branches run at the same time; each one
+/// is reported as a single step named after its id.
+///
+/// The result is the output of the single sink node with every node's
+/// output under `nodes`; with several sinks only `nodes` is set.
+pub struct GraphChain {
+    name: String,
+    description: String,
+    graph: DiGraph<GraphNode, Vec<FieldMapping>>,
+    indices: HashMap<String, NodeIndex>,
+}
+
+impl GraphChain {
+    pub fn new(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            graph: DiGraph::new(),
+            indices: HashMap::new(),
+        }
+    }
+    
+    pub fn add_node(mut self, id: impl Into<String>, chain: Arc<dyn Chain>) -> Result<Self> {
+        let id = id.into();
+        
+        if self.indices.contains_key(&id) {
+            anyhow::bail!("Graph '{}' already has a node '{}'", self.name, id);
+        }
+        
+        let index = self.graph.add_node(GraphNode {
+            id: id.clone(),
+            chain,
+            inputs: Vec::new(),
+        });
+        self.indices.insert(id, index);
+        
+        Ok(self)
+    }
+    
+    pub fn add_edge<M: Into<FieldMapping>>(
+        mut self,
+        from: &str,
+        to: &str,
+        mappings: impl IntoIterator<Item = M>,
+    ) -> Result<Self> {
+        let source = self.index(from)?;
+        let target = self.index(to)?;
+        
+        // A second edge would silently replace the first one's mappings
+        if self.graph.contains_edge(source, target) {
+            anyhow::bail!("Graph '{}' already has an edge {} -> {}", self.name, from, to);
+        }
+        
+        // An edge closes a cycle if the target already reaches the source
+        if has_path_connecting(&self.graph, target, source, None) {
+            anyhow::bail!("Edge {} -> {} would create a cycle in graph '{}'", from, to, self.name);
+        }
+        
+        let mappings = mappings.into_iter().map(Into::into).collect();
+        self.graph.add_edge(source, target, mappings);
+        
+        Ok(self)
+    }
+    
+    /// Passes graph input `variable` to a node as `to`. A root node with
+    /// mappings receives only the mapped variables.
+    pub fn map_input(mut self, node: &str, variable: impl Into<String>, to: impl Into<String>) -> Result<Self> {
+        let index = self.index(node)?;
+        self.graph[index].inputs.push(FieldMapping::new(variable, to));
+        Ok(self)
+    }
+    
+    fn index(&self, id: &str) -> Result<NodeIndex> {
+        self.indices
+            .get(id)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Graph '{}' has no node '{}'", self.name, id))
+    }
+    
+    fn is_root(&self, index: NodeIndex) -> bool {
+        self.graph.neighbors_directed(index, Direction::Incoming).next().is_none()
+    }
+    
+    // Variables for a node whose predecessors have all finished
+    fn node_input(&self, index: NodeIndex, graph_input: &ChainInput, results: &HashMap<NodeIndex, serde_json::Value>) -> Result<ChainInput> {
+        let node = &self.graph[index];
+        let mut input = ChainInput::new();
+        
+        if self.is_root(index) && node.inputs.is_empty() {
+            match node.chain.input_keys() {
+                // Missing keys are left for the node to report
+                Some(keys) => {
+                    for key in keys {
+                        if let Some(value) = graph_input.variables.get(&key) {
+                            input.variables.insert(key, value.clone());
+                        }
+                    }
+                }
+                None => input.variables = graph_input.variables.clone(),
+            }
+            return Ok(input);
+        }
+        
+        for mapping in &node.inputs {
+            let value = graph_input.variables
+                .get(&mapping.from)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Missing graph input '{}' for node '{}'", mapping.from, node.id))?;
+            input.variables.insert(mapping.to.clone(), value);
+        }
+        
+        for edge in self.graph.edges_directed(index, Direction::Incoming) {
+            let source = &self.graph[edge.source()];
+            let result = &results[&edge.source()];
+            
+            for mapping in edge.weight() {
+                let value = field(result, &mapping.from).ok_or_else(|| {
+                    anyhow::anyhow!("Node '{}' has no output field '{}' for node '{}'", source.id, mapping.from, node.id)
+                })?;
+                input.variables.insert(mapping.to.clone(), value.clone());
+            }
+        }
+        
+        Ok(input)
+    }
+    
+    // A failed run carries what the finished nodes spent
+    async fn run(&self, input: ChainInput, events: &ChainEvents) -> Result<ChainOutput> {
+        let mut spent = ChainSpend::default();
+        self.run_nodes(input, events, &mut spent).await.map_err(|e| spent.attach(e))
+    }
+    
+    async fn run_nodes(&self, input: ChainInput, events: &ChainEvents, spent: &mut ChainSpend) -> Result<ChainOutput> {
+        let start = std::time::Instant::now();
+        
+        let mut remaining: HashMap<NodeIndex, usize> = self.graph
+            .node_indices()
+            .map(|index| (index, self.graph.neighbors_directed(index, Direction::Incoming).count()))
+            .collect();
+        
+        let mut results: HashMap<NodeIndex, serde_json::Value> = HashMap::new();
+        let mut steps = Vec::new();
+        let mut running = FuturesUnordered::new();
+        
+        let mut ready: Vec<NodeIndex> = remaining
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(index, _)| *index)
+            .collect();
+        
+        loop {
+            // Node indices are in insertion order, which keeps runs repeatable
+            ready.sort();
+            
+            for index in ready.drain(..) {
+                let node = &self.graph[index];
+                let node_input = self.node_input(index, &input, &results)?;
+                let chain = node.chain.clone();
+                
+                events.send(ChainEvent::StepStart { name: node.id.clone() });
+                
+                running.push(async move {
+                    let node_start = std::time::Instant::now();
+                    let step_input = serde_json::Value::Object(node_input.variables.clone()).to_string();
+                    let output = chain.execute(node_input).await;
+                    (index, step_input, node_start.elapsed().as_millis() as u64, output)
+                });
+            }
+            
+            let Some((index, step_input, duration_ms, output)) = running.next().await else {
+                break;
+            };
+            
+            let node = &self.graph[index];
+            let output = output.with_context(|| format!("Node '{}' of graph '{}' failed", node.id, self.name))?;
+            
+            let step = StepInfo {
+                name: node.id.clone(),
+                duration_ms,
+                input: step_input,
+                output: match &output.result["output"] {
+                    serde_json::Value::String(text) => text.clone(),
+                    _ => output.result.to_string(),
+                },
+            };
+            events.send(ChainEvent::StepEnd { step: step.clone() });
+            
+            steps.push(step);
+            spent.add(output.metadata.total_tokens, output.metadata.total_cost);
+            results.insert(index, output.result);
+            
+            for next in self.graph.neighbors_directed(index, Direction::Outgoing) {
+                let count = remaining.get_mut(&next).expect("every node is counted");
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(next);
+                }
+            }
+        }
+        
+        let sinks: Vec<NodeIndex> = self.graph
+            .node_indices()
+            .filter(|index| self.graph.neighbors_directed(*index, Direction::Outgoing).next().is_none())
+            .collect();
+        
+        let nodes: serde_json::Map<String, serde_json::Value> = self.graph
+            .node_indices()
+            .filter_map(|index| Some((self.graph[index].id.clone(), results.get(&index)?.clone())))
+            .collect();
+        
+        let mut result = match sinks.as_slice() {
+            [sink] => results.get(sink).cloned().unwrap_or_else(|| serde_json::json!({})),
+            _ => serde_json::json!({}),
+        };
+        
+        if let serde_json::Value::Object(fields) = &mut result {
+            fields.insert("nodes".to_string(), serde_json::Value::Object(nodes));
+        }
+        
+        Ok(ChainOutput {
+            result,
+            metadata: ChainMetadata {
+                chain_name: self.name.clone(),
+                execution_time_ms: start.elapsed().as_millis() as u64,
+                steps,
+                total_tokens: spent.tokens,
+                total_cost: spent.cost,
+            },
+        })
+    }
+}
+
+// `a.b.0` -> result["a"]["b"][0]
+pub(crate) fn field<'a>(result: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.').try_fold(result, |value, segment| match value {
+        serde_json::Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        serde_json::Value::Object(fields) => fields.get(segment),
+        _ => None,
+    })
+}
+
+#[async_trait]
+impl Chain for GraphChain {
+    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+        self.run(input, &ChainEvents::disabled()).await
+    }
+    
+    async fn execute_streaming(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        self.run(input, &events).await
+    }
+    
+    fn structure(&self) -> ChainStructure {
+        let mut structure = ChainStructure::new(&self.name, "graph");
+        
+        for index in self.graph.node_indices() {
+            let node = &self.graph[index];
+            structure = structure.with_node(&node.id, node.chain.structure());
+        }
+        
+        for edge in self.graph.edge_references() {
+            let fields = edge.weight().iter().map(|mapping| {
+                if mapping.from == mapping.to {
+                    mapping.to.clone()
+                } else {
+                    format!("{} as {}", mapping.from, mapping.to)
+                }
+            });
+            
+            structure = structure.with_edge(&self.graph[edge.source()].id, &self.graph[edge.target()].id, fields);
+        }
+        
+        structure
+    }
+    
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
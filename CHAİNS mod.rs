@@ -1,15 +1,54 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
+use tokio::sync::mpsc;
+use structure::ChainStructure;
+use crate::llm::structured::StructuredOutputError;
 
 pub mod simple;
 pub mod sequential;
 pub mod pipeline;
+pub mod graph;
+pub mod router;
+pub mod summarize;
 pub mod manager;
+pub mod definition;
+pub mod few_shot;
+pub mod parser;
+pub mod prompt;
+pub mod structure;
 
 #[async_trait]
 pub trait Chain: Send + Sync {
     async fn execute(&self, input: ChainInput) -> Result<ChainOutput>;
+    
+    /// Executes the chain while reporting progress through `events`.
+    ///
+    /// Chains that call an LLM directly override this to forward tokens as
+    /// they arrive; the default replays the finished steps.
+    async fn execute_streaming(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        let output = self.execute(input).await?;
+        
+        for step in &output.metadata.steps {
+            events.send(ChainEvent::StepStart { name: step.name.clone() });
+            events.send(ChainEvent::StepEnd { step: step.clone() });
+        }
+        
+        Ok(output)
+    }
+    
+    /// How the chain is wired, for the graph view. Chains built from other
+    /// chains or with internal steps override this to list them.
+    fn structure(&self) -> ChainStructure {
+        ChainStructure::new(self.name(), "chain")
+    }
+    
+    /// The input variables the chain reads, when it knows them. Sequential
+    /// chains pass such a step only these from their shared variables.
+    fn input_keys(&self) -> Option<Vec<String>> {
+        None
+    }
+    
     fn name(&self) -> &str;
     fn description(&self) -> &str;
 }
@@ -64,3 +103,114 @@ pub struct StepInfo {
     pub input: String,
     pub output: String,
 }
+
+/// What a chain run has spent on LLM calls so far. A run that fails part-way
+/// attaches it to its error, so the calls that finished can still be billed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChainSpend {
+    pub tokens: usize,
+    pub cost: f64,
+}
+
+impl ChainSpend {
+    pub fn new(tokens: usize, cost: f64) -> Self {
+        Self { tokens, cost }
+    }
+    
+    pub fn add(&mut self, tokens: usize, cost: f64) {
+        self.tokens += tokens;
+        self.cost += cost;
+    }
+    
+    /// The spend carried by `error`: the outermost failed chain's total, or the
+    /// attempts of a failed structured-output call.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(failed) = error.chain().find_map(|e| e.downcast_ref::<SpentError>()) {
+            return failed.spend;
+        }
+        
+        error
+            .chain()
+            .find_map(|e| e.downcast_ref::<StructuredOutputError>())
+            .map(|failed| Self::new(failed.tokens_used.total_tokens, failed.cost))
+            .unwrap_or_default()
+    }
+    
+    /// Wraps `error` with this spend added to what it already carries. The
+    /// error's message and causes are unchanged.
+    pub fn attach(self, error: anyhow::Error) -> anyhow::Error {
+        if self == Self::default() {
+            return error;
+        }
+        
+        let carried = Self::of(&error);
+        
+        anyhow::Error::new(SpentError {
+            spend: Self::new(self.tokens + carried.tokens, self.cost + carried.cost),
+            error,
+        })
+    }
+}
+
+// Displays as the error it wraps, which stays next in `chain()` so lookups
+// such as `LLMError::find` still see it
+#[derive(Debug)]
+struct SpentError {
+    spend: ChainSpend,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for SpentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for SpentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainEvent {
+    StepStart { name: String },
+    Token { text: String },
+    StepEnd { step: StepInfo },
+}
+
+/// Sink for `ChainEvent`s; disabled when the caller is not streaming.
+#[derive(Debug, Clone)]
+pub struct ChainEvents {
+    sender: Option<mpsc::UnboundedSender<ChainEvent>>,
+}
+
+impl ChainEvents {
+    pub fn new(sender: mpsc::UnboundedSender<ChainEvent>) -> Self {
+        Self {
+            sender: Some(sender),
+        }
+    }
+    
+    pub fn disabled() -> Self {
+        Self { sender: None }
+    }
+    
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+    
+    pub fn send(&self, event: ChainEvent) {
+        if let Some(sender) = &self.sender {
+            // The receiver is gone once the client disconnects; nothing to do
+            let _ = sender.send(event);
+        }
+    }
+    
+    pub fn token(&self, text: &str) {
+        self.send(ChainEvent::Token {
+            text: text.to_string(),
+        });
+    }
+}
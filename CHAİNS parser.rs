@@ -0,0 +1,293 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use super::prompt::{ChainPrompt, PromptValue};
+use crate::llm::structured;
+
+/// Template variable filled with a parser's format instructions.
+pub const FORMAT_INSTRUCTIONS: &str = "format_instructions";
+
+/// Turns an LLM reply into a structured value.
+///
+/// Chains store the parsed value under `parsed` next to the raw `output`.
+pub trait OutputParser: Send + Sync {
+    fn parse(&self, text: &str) -> Result<serde_json::Value>;
+    
+    /// Tells the model how to format its reply, if the parser needs it.
+    fn format_instructions(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Renders `prompt`, filling `{format_instructions}` with the parser's
+/// instructions, or appending them when the prompt doesn't use it.
+pub async fn render_prompt(
+    prompt: &ChainPrompt,
+    mut variables: serde_json::Map<String, serde_json::Value>,
+    parser: Option<&dyn OutputParser>,
+) -> Result<PromptValue> {
+    let instructions = parser.and_then(|parser| parser.format_instructions());
+    
+    if prompt.has_variable(FORMAT_INSTRUCTIONS) {
+        if let Some(instructions) = &instructions {
+            variables.insert(FORMAT_INSTRUCTIONS.to_string(), serde_json::Value::String(instructions.clone()));
+        }
+        return prompt.format(&variables).await;
+    }
+    
+    let mut value = prompt.format(&variables).await?;
+    
+    if let Some(instructions) = instructions {
+        value.append(&instructions);
+    }
+    
+    Ok(value)
+}
+
+/// The variables a chain's caller must supply for `prompt`: the format
+/// instructions come from `parser` when it has any.
+pub fn prompt_inputs(prompt: &ChainPrompt, parser: Option<&dyn OutputParser>) -> Vec<String> {
+    let instructions = parser.is_some_and(|parser| parser.format_instructions().is_some());
+    
+    prompt
+        .input_variables()
+        .into_iter()
+        .filter(|name| !(instructions && name == FORMAT_INSTRUCTIONS))
+        .collect()
+}
+
+/// JSON anywhere in the reply, including inside a fenced code block,
+/// optionally validated against a JSON Schema.
+#[derive(Debug, Clone, Default)]
+pub struct JsonOutputParser {
+    schema: Option<serde_json::Value>,
+}
+
+impl JsonOutputParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn with_schema(mut self, schema: serde_json::Value) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+}
+
+impl OutputParser for JsonOutputParser {
+    fn parse(&self, text: &str) -> Result<serde_json::Value> {
+        let value = structured::extract_json(text)
+            .ok_or_else(|| anyhow::anyhow!("No JSON found in output: {}", text))?;
+        
+        if let Some(schema) = &self.schema {
+            structured::validate(&value, schema)
+                .map_err(|errors| anyhow::anyhow!("Output does not match schema: {}", errors.join("; ")))?;
+        }
+        
+        Ok(value)
+    }
+    
+    fn format_instructions(&self) -> Option<String> {
+        Some(match &self.schema {
+            Some(schema) => format!("Respond only with JSON that matches this JSON Schema:\n{}", schema),
+            None => "Respond only with valid JSON.".to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListSeparator {
+    Comma,
+    Line,
+}
+
+/// A list of strings, split on commas or on lines (with bullets and
+/// numbering removed).
+#[derive(Debug, Clone)]
+pub struct ListOutputParser {
+    separator: ListSeparator,
+}
+
+impl ListOutputParser {
+    pub fn comma_separated() -> Self {
+        Self { separator: ListSeparator::Comma }
+    }
+    
+    pub fn line_separated() -> Self {
+        Self { separator: ListSeparator::Line }
+    }
+}
+
+impl OutputParser for ListOutputParser {
+    fn parse(&self, text: &str) -> Result<serde_json::Value> {
+        let items: Vec<&str> = match self.separator {
+            ListSeparator::Comma => text.split(',').collect(),
+            ListSeparator::Line => text.lines().map(strip_list_marker).collect(),
+        };
+        
+        Ok(items
+            .into_iter()
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| serde_json::Value::String(item.to_string()))
+            .collect())
+    }
+    
+    fn format_instructions(&self) -> Option<String> {
+        Some(match self.separator {
+            ListSeparator::Comma => "Respond with a comma-separated list, e.g. `foo, bar, baz`.",
+            ListSeparator::Line => "Respond with one item per line and nothing else.",
+        }
+        .to_string())
+    }
+}
+
+// "- item", "* item", "1. item" and "1) item" all become "item"
+fn strip_list_marker(line: &str) -> &str {
+    let line = line.trim_start();
+    
+    if let Some(rest) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+        return rest;
+    }
+    
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 {
+        if let Some(rest) = line[digits..].strip_prefix(". ").or_else(|| line[digits..].strip_prefix(") ")) {
+            return rest;
+        }
+    }
+    
+    line
+}
+
+/// One of a fixed set of values, matched case-insensitively.
+#[derive(Debug, Clone)]
+pub struct EnumOutputParser {
+    options: Vec<String>,
+}
+
+impl EnumOutputParser {
+    pub fn new(options: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            options: options.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl OutputParser for EnumOutputParser {
+    fn parse(&self, text: &str) -> Result<serde_json::Value> {
+        // Models like to add quotes or a trailing full stop
+        let answer = text.trim().trim_matches(|c: char| c == '"' || c == '\'' || c == '`' || c == '.');
+        
+        self.options
+            .iter()
+            .find(|option| option.eq_ignore_ascii_case(answer))
+            .map(|option| serde_json::Value::String(option.clone()))
+            .ok_or_else(|| anyhow::anyhow!("Expected one of [{}], got: {}", self.options.join(", "), text.trim()))
+    }
+    
+    fn format_instructions(&self) -> Option<String> {
+        Some(format!("Respond with exactly one of: {}.", self.options.join(", ")))
+    }
+}
+
+/// Capture groups of a regex: an object of the named groups, or an array
+/// of the numbered ones when the regex has no names.
+#[derive(Debug, Clone)]
+pub struct RegexOutputParser {
+    regex: Regex,
+    instructions: Option<String>,
+}
+
+impl RegexOutputParser {
+    pub fn new(regex: Regex) -> Self {
+        Self {
+            regex,
+            instructions: None,
+        }
+    }
+    
+    pub fn with_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+}
+
+impl OutputParser for RegexOutputParser {
+    fn parse(&self, text: &str) -> Result<serde_json::Value> {
+        let captures = self.regex
+            .captures(text)
+            .ok_or_else(|| anyhow::anyhow!("Output does not match /{}/: {}", self.regex, text))?;
+        
+        let capture = |m: Option<regex::Match>| {
+            m.map(|m| serde_json::Value::String(m.as_str().to_string()))
+                .unwrap_or(serde_json::Value::Null)
+        };
+        
+        let names: Vec<&str> = self.regex.capture_names().flatten().collect();
+        
+        if names.is_empty() {
+            return Ok(captures.iter().skip(1).map(capture).collect());
+        }
+        
+        Ok(names
+            .into_iter()
+            .map(|name| (name.to_string(), capture(captures.name(name))))
+            .collect::<serde_json::Map<_, _>>()
+            .into())
+    }
+    
+    fn format_instructions(&self) -> Option<String> {
+        self.instructions.clone()
+    }
+}
+
+/// JSON deserialized into `T`, so a reply of the wrong shape fails to parse.
+pub struct TypedOutputParser<T> {
+    instructions: Option<String>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned + Serialize> TypedOutputParser<T> {
+    pub fn new() -> Self {
+        Self {
+            instructions: None,
+            _marker: PhantomData,
+        }
+    }
+    
+    /// Usually a description or example of `T`'s JSON shape.
+    pub fn with_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+    
+    pub fn parse_typed(&self, text: &str) -> Result<T> {
+        let value = structured::extract_json(text)
+            .ok_or_else(|| anyhow::anyhow!("No JSON found in output: {}", text))?;
+        
+        serde_json::from_value(value)
+            .with_context(|| format!("Output does not match {}", std::any::type_name::<T>()))
+    }
+}
+
+impl<T: DeserializeOwned + Serialize> Default for TypedOutputParser<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: DeserializeOwned + Serialize> OutputParser for TypedOutputParser<T> {
+    fn parse(&self, text: &str) -> Result<serde_json::Value> {
+        Ok(serde_json::to_value(self.parse_typed(text)?)?)
+    }
+    
+    fn format_instructions(&self) -> Option<String> {
+        Some(match &self.instructions {
+            Some(instructions) => format!("Respond only with JSON. {}", instructions),
+            None => "Respond only with valid JSON.".to_string(),
+        })
+    }
+}
@@ -1,8 +1,11 @@
-use super::{Chain, ChainInput, ChainOutput, ChainMetadata, StepInfo};
-use crate::llm::{LLMProvider, LLMRequest};
+use super::{Chain, ChainEvent, ChainEvents, ChainInput, ChainOutput, ChainMetadata, ChainSpend, StepInfo};
+use super::parser::{self, OutputParser};
+use super::prompt::ChainPrompt;
+use super::structure::ChainStructure;
+use crate::llm::{streaming, GenerationParams, LLMProvider};
 use crate::rag::retriever::Retriever;
 use async_trait::async_trait;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::sync::Arc;
 
 pub struct RAGPipeline {
@@ -10,7 +13,9 @@ pub struct RAGPipeline {
     description: String,
     llm: Arc<dyn LLMProvider>,
     retriever: Arc<Retriever>,
-    prompt_template: String,
+    prompt: ChainPrompt,
+    params: GenerationParams,
+    output_parser: Option<Arc<dyn OutputParser>>,
 }
 
 impl RAGPipeline {
@@ -19,21 +24,32 @@ impl RAGPipeline {
         description: impl Into<String>,
         llm: Arc<dyn LLMProvider>,
         retriever: Arc<Retriever>,
-        prompt_template: impl Into<String>,
+        prompt: impl Into<ChainPrompt>,
     ) -> Self {
         Self {
             name: name.into(),
             description: description.into(),
             llm,
             retriever,
-            prompt_template: prompt_template.into(),
+            prompt: prompt.into(),
+            params: GenerationParams::default(),
+            output_parser: None,
         }
     }
-}
-
-#[async_trait]
-impl Chain for RAGPipeline {
-    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+    
+    pub fn with_params(mut self, params: GenerationParams) -> Self {
+        self.params = params;
+        self
+    }
+    
+    /// Parses the answer into `parsed`; the parser's format instructions are
+    /// added to the prompt.
+    pub fn with_output_parser(mut self, parser: Arc<dyn OutputParser>) -> Self {
+        self.output_parser = Some(parser);
+        self
+    }
+    
+    async fn run(&self, input: ChainInput, events: &ChainEvents) -> Result<ChainOutput> {
         let start = std::time::Instant::now();
         let mut steps = Vec::new();
         
@@ -41,52 +57,104 @@ impl Chain for RAGPipeline {
             .ok_or_else(|| anyhow::anyhow!("Missing 'query' in input"))?;
         
         // Step 1: Retrieve context
+        events.send(ChainEvent::StepStart { name: "retrieve_context".to_string() });
         let retrieve_start = std::time::Instant::now();
         let context = self.retriever.build_context(&query).await?;
         let retrieve_duration = retrieve_start.elapsed().as_millis() as u64;
         
-        steps.push(StepInfo {
+        let step = StepInfo {
             name: "retrieve_context".to_string(),
             duration_ms: retrieve_duration,
             input: query.clone(),
             output: format!("Retrieved {} characters of context", context.len()),
-        });
+        };
+        events.send(ChainEvent::StepEnd { step: step.clone() });
+        steps.push(step);
         
         // Step 2: Build final prompt
-        let prompt = self.prompt_template
-            .replace("{context}", &context)
-            .replace("{query}", &query);
+        let mut variables = input.variables;
+        variables.insert("context".to_string(), serde_json::Value::String(context.clone()));
+        let prompt = parser::render_prompt(&self.prompt, variables, self.output_parser.as_deref()).await?;
         
         // Step 3: Generate response
+        events.send(ChainEvent::StepStart { name: "llm_generate".to_string() });
         let llm_start = std::time::Instant::now();
-        let request = LLMRequest::new(prompt.clone());
-        let response = self.llm.generate(&request).await?;
+        let request = self.params.apply(prompt.to_request());
+        let response = if events.is_enabled() {
+            let stream = self.llm.stream_generate(&request).await?;
+            streaming::collect(stream, llm_start, |delta| events.token(delta)).await?
+        } else {
+            self.llm.generate(&request).await?
+        };
         let llm_duration = llm_start.elapsed().as_millis() as u64;
         
-        steps.push(StepInfo {
+        let step = StepInfo {
             name: "llm_generate".to_string(),
             duration_ms: llm_duration,
-            input: prompt,
+            input: prompt.to_string(),
             output: response.text.clone(),
-        });
+        };
+        events.send(ChainEvent::StepEnd { step: step.clone() });
+        steps.push(step);
         
         let execution_time = start.elapsed().as_millis() as u64;
         
+        let mut result = serde_json::json!({
+            "output": response.text,
+            "context_used": context,
+            "model": response.model,
+        });
+        
+        if let Some(parser) = &self.output_parser {
+            // The answer was paid for even if it can't be parsed
+            result["parsed"] = parser
+                .parse(&response.text)
+                .context("Failed to parse RAG answer")
+                .map_err(|e| ChainSpend::new(response.billed_tokens(), response.cost).attach(e))?;
+        }
+        
         Ok(ChainOutput {
-            result: serde_json::json!({
-                "output": response.text,
-                "context_used": context,
-                "model": response.model,
-            }),
+            result,
             metadata: ChainMetadata {
                 chain_name: self.name.clone(),
                 execution_time_ms: execution_time,
                 steps,
-                total_tokens: response.tokens_used.total_tokens,
-                total_cost: response.tokens_used.estimate_cost(&response.model),
+                total_tokens: response.billed_tokens(),
+                total_cost: response.cost,
             },
         })
     }
+}
+
+#[async_trait]
+impl Chain for RAGPipeline {
+    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+        self.run(input, &ChainEvents::disabled()).await
+    }
+    
+    async fn execute_streaming(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        self.run(input, &events).await
+    }
+    
+    fn input_keys(&self) -> Option<Vec<String>> {
+        let mut keys: Vec<String> = parser::prompt_inputs(&self.prompt, self.output_parser.as_deref())
+            .into_iter()
+            .filter(|name| name != "context")
+            .collect();
+        
+        if !keys.iter().any(|name| name == "query") {
+            keys.push("query".to_string());
+        }
+        
+        Some(keys)
+    }
+    
+    fn structure(&self) -> ChainStructure {
+        ChainStructure::new(&self.name, "rag")
+            .with_node("retrieve_context", ChainStructure::new("retrieve_context", "retrieve"))
+            .with_node("llm_generate", ChainStructure::new("llm_generate", "generate"))
+            .with_edge("retrieve_context", "llm_generate", ["context"])
+    }
     
     fn name(&self) -> &str {
         &self.name
@@ -0,0 +1,704 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::fmt;
+use thiserror::Error;
+use super::few_shot::{FewShotChatMessages, FewShotPromptTemplate};
+use crate::llm::{ChatMessage, LLMRequest};
+use crate::memory::MessageRole;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PromptError {
+    #[error("Invalid prompt template at byte {position}: {message}")]
+    Syntax { position: usize, message: String },
+    
+    #[error("Missing prompt variable(s): {}", .0.join(", "))]
+    MissingVariables(Vec<String>),
+    
+    #[error("Unexpected prompt variable(s): {}", .0.join(", "))]
+    UnexpectedVariables(Vec<String>),
+    
+    #[error("Template uses undeclared variable(s): {}", .0.join(", "))]
+    UndeclaredVariables(Vec<String>),
+    
+    #[error("'{0}' is not a list")]
+    NotAList(String),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Variable(Path),
+    If {
+        condition: Path,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        items: Path,
+        binding: String,
+        body: Vec<Node>,
+    },
+}
+
+// `user.address.city`, `items.0`
+#[derive(Debug, Clone)]
+struct Path {
+    segments: Vec<String>,
+}
+
+impl Path {
+    fn parse(text: &str, position: usize) -> Result<Self, PromptError> {
+        let segments: Vec<String> = text.split('.').map(str::to_string).collect();
+        
+        let valid = |segment: &String| {
+            let body = segment.strip_prefix('@').unwrap_or(segment);
+            !body.is_empty() && body.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        
+        if !segments.iter().all(valid) {
+            return Err(syntax(
+                position,
+                format!("'{{{}}}' is not a variable; write '{{{{' and '}}}}' for literal braces", text),
+            ));
+        }
+        
+        Ok(Self { segments })
+    }
+    
+    fn root(&self) -> &str {
+        &self.segments[0]
+    }
+    
+    fn display(&self) -> String {
+        self.segments.join(".")
+    }
+}
+
+/// A prompt with `{variable}` placeholders.
+///
+/// Besides plain variables the template language has dotted paths
+/// (`{user.name}`, `{items.0}`), conditionals (`{#if notes}...{#else}...{/if}`)
+/// and loops (`{#each docs as doc}{@index}. {doc.title}{/each}`; without `as`
+/// the item is `{this}`). Strings are inserted as-is, other values as JSON.
+/// `{{` and `}}` produce literal braces.
+///
+/// Variables are checked before rendering: every input variable must be
+/// given, either at render time or as a partial. Other variables are ignored
+/// unless the template is `strict`.
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    template: String,
+    nodes: Vec<Node>,
+    input_variables: BTreeSet<String>,
+    partials: Map<String, Value>,
+    strict: bool,
+}
+
+impl PromptTemplate {
+    /// Parses `template`; its input variables are the ones it uses.
+    pub fn new(template: impl Into<String>) -> Result<Self> {
+        let template = template.into();
+        let nodes = parse(&template)?;
+        
+        let mut input_variables = BTreeSet::new();
+        collect_variables(&nodes, &mut Vec::new(), &mut input_variables);
+        
+        Ok(Self {
+            template,
+            nodes,
+            input_variables,
+            partials: Map::new(),
+            strict: false,
+        })
+    }
+    
+    /// Declares the input variables explicitly. Fails if the template uses a
+    /// variable that isn't declared; declared but unused ones are accepted.
+    pub fn with_input_variables(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Result<Self> {
+        let declared: BTreeSet<String> = names.into_iter().map(Into::into).collect();
+        
+        let undeclared: Vec<String> = self.input_variables.difference(&declared).cloned().collect();
+        if !undeclared.is_empty() {
+            return Err(PromptError::UndeclaredVariables(undeclared).into());
+        }
+        
+        self.input_variables = declared;
+        Ok(self)
+    }
+    
+    /// Fills in a variable ahead of time; values given at render time win.
+    pub fn with_partial(mut self, name: impl Into<String>, value: Value) -> Self {
+        self.partials.insert(name.into(), value);
+        self
+    }
+    
+    /// Rejects variables the template doesn't use, e.g. to catch typos.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+    
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+    
+    pub fn has_variable(&self, name: &str) -> bool {
+        self.input_variables.contains(name)
+    }
+    
+    /// Variables still needed at render time, i.e. without a partial.
+    pub fn input_variables(&self) -> Vec<String> {
+        self.input_variables
+            .iter()
+            .filter(|name| !self.partials.contains_key(*name))
+            .cloned()
+            .collect()
+    }
+    
+    pub fn validate(&self, variables: &Map<String, Value>) -> Result<(), PromptError> {
+        check_variables(self.input_variables(), variables)?;
+        
+        if self.strict {
+            check_unexpected(|name| self.has_variable(name), variables)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// The subset of `variables` this template accepts, for rendering it as
+    /// part of a larger prompt.
+    pub fn select_variables(&self, variables: &Map<String, Value>) -> Map<String, Value> {
+        variables
+            .iter()
+            .filter(|(name, _)| self.has_variable(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+    
+    pub fn render(&self, variables: &Map<String, Value>) -> Result<String> {
+        self.validate(variables)?;
+        
+        let mut scope = Scope {
+            variables,
+            partials: &self.partials,
+            locals: Vec::new(),
+        };
+        
+        let mut output = String::with_capacity(self.template.len());
+        render_nodes(&self.nodes, &mut scope, &mut output)?;
+        
+        Ok(output)
+    }
+}
+
+/// A conversation of role-tagged message templates.
+///
+/// Placeholders insert a list of messages held in a variable, such as a
+/// session's history; examples insert few-shot turns chosen per request.
+#[derive(Clone, Default)]
+pub struct ChatPromptTemplate {
+    parts: Vec<ChatPart>,
+    strict: bool,
+}
+
+#[derive(Clone)]
+enum ChatPart {
+    Message(MessageRole, PromptTemplate),
+    Placeholder(String),
+    Examples(FewShotChatMessages),
+}
+
+impl ChatPromptTemplate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Parses one template per message, e.g. `[(MessageRole::System, "You are {persona}.")]`.
+    pub fn from_messages<'a>(messages: impl IntoIterator<Item = (MessageRole, &'a str)>) -> Result<Self> {
+        messages
+            .into_iter()
+            .try_fold(Self::new(), |chat, (role, template)| {
+                Ok(chat.with_message(role, PromptTemplate::new(template)?))
+            })
+    }
+    
+    pub fn with_message(mut self, role: MessageRole, template: PromptTemplate) -> Self {
+        self.parts.push(ChatPart::Message(role, template));
+        self
+    }
+    
+    /// Inserts the messages in variable `name`, a JSON array of `ChatMessage`s.
+    pub fn with_placeholder(mut self, name: impl Into<String>) -> Self {
+        self.parts.push(ChatPart::Placeholder(name.into()));
+        self
+    }
+    
+    /// Rejects variables no message or placeholder uses.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+    
+    pub fn with_examples(mut self, examples: FewShotChatMessages) -> Self {
+        self.parts.push(ChatPart::Examples(examples));
+        self
+    }
+    
+    pub fn has_variable(&self, name: &str) -> bool {
+        self.parts.iter().any(|part| match part {
+            ChatPart::Message(_, template) => template.has_variable(name),
+            ChatPart::Placeholder(placeholder) => placeholder == name,
+            ChatPart::Examples(_) => false,
+        })
+    }
+    
+    /// Variables still needed at render time, i.e. without a partial.
+    pub fn input_variables(&self) -> Vec<String> {
+        let mut names = BTreeSet::new();
+        
+        for part in &self.parts {
+            match part {
+                ChatPart::Message(_, template) => names.extend(template.input_variables()),
+                ChatPart::Placeholder(name) => {
+                    names.insert(name.clone());
+                }
+                ChatPart::Examples(_) => {}
+            }
+        }
+        
+        names.into_iter().collect()
+    }
+    
+    pub fn validate(&self, variables: &Map<String, Value>) -> Result<(), PromptError> {
+        check_variables(self.input_variables(), variables)?;
+        
+        if self.strict {
+            check_unexpected(|name| self.has_variable(name), variables)?;
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn format_messages(&self, variables: &Map<String, Value>) -> Result<Vec<ChatMessage>> {
+        self.validate(variables)?;
+        
+        let mut messages = Vec::new();
+        
+        for part in &self.parts {
+            match part {
+                ChatPart::Message(role, template) => {
+                    let content = template.render(&template.select_variables(variables))?;
+                    messages.push(ChatMessage::new(*role, content));
+                }
+                ChatPart::Placeholder(name) => {
+                    let history: Vec<ChatMessage> = serde_json::from_value(variables[name].clone())
+                        .map_err(|e| anyhow::anyhow!("'{}' is not a list of messages: {}", name, e))?;
+                    messages.extend(history);
+                }
+                ChatPart::Examples(examples) => messages.extend(examples.format_messages(variables).await?),
+            }
+        }
+        
+        Ok(messages)
+    }
+}
+
+/// A rendered prompt: a single prompt or a conversation.
+#[derive(Debug, Clone)]
+pub enum PromptValue {
+    Text(String),
+    Messages(Vec<ChatMessage>),
+}
+
+impl PromptValue {
+    /// Adds a paragraph to the prompt, or to the last message.
+    pub fn append(&mut self, text: &str) {
+        match self {
+            PromptValue::Text(prompt) => {
+                prompt.push_str("\n\n");
+                prompt.push_str(text);
+            }
+            PromptValue::Messages(messages) => match messages.last_mut() {
+                Some(last) => {
+                    last.content.push_str("\n\n");
+                    last.content.push_str(text);
+                }
+                None => messages.push(ChatMessage::user(text)),
+            },
+        }
+    }
+    
+    pub fn to_request(&self) -> LLMRequest {
+        match self {
+            PromptValue::Text(prompt) => LLMRequest::new(prompt.clone()),
+            PromptValue::Messages(messages) => LLMRequest::new(String::new()).with_messages(messages.clone()),
+        }
+    }
+}
+
+impl fmt::Display for PromptValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PromptValue::Text(prompt) => f.write_str(prompt),
+            PromptValue::Messages(messages) => {
+                let lines: Vec<String> = messages
+                    .iter()
+                    .map(|m| format!("{}: {}", m.role.as_str(), m.content))
+                    .collect();
+                f.write_str(&lines.join("\n"))
+            }
+        }
+    }
+}
+
+/// Any of the prompt kinds a chain can be built with.
+#[derive(Clone)]
+pub enum ChainPrompt {
+    Text(PromptTemplate),
+    FewShot(Box<FewShotPromptTemplate>),
+    Chat(ChatPromptTemplate),
+}
+
+impl ChainPrompt {
+    pub fn has_variable(&self, name: &str) -> bool {
+        match self {
+            ChainPrompt::Text(template) => template.has_variable(name),
+            ChainPrompt::FewShot(template) => template.has_variable(name),
+            ChainPrompt::Chat(template) => template.has_variable(name),
+        }
+    }
+    
+    pub fn input_variables(&self) -> Vec<String> {
+        match self {
+            ChainPrompt::Text(template) => template.input_variables(),
+            ChainPrompt::FewShot(template) => template.input_variables(),
+            ChainPrompt::Chat(template) => template.input_variables(),
+        }
+    }
+    
+    pub async fn format(&self, variables: &Map<String, Value>) -> Result<PromptValue> {
+        Ok(match self {
+            ChainPrompt::Text(template) => PromptValue::Text(template.render(variables)?),
+            ChainPrompt::FewShot(template) => PromptValue::Text(template.format(variables).await?),
+            ChainPrompt::Chat(template) => PromptValue::Messages(template.format_messages(variables).await?),
+        })
+    }
+}
+
+impl From<PromptTemplate> for ChainPrompt {
+    fn from(template: PromptTemplate) -> Self {
+        ChainPrompt::Text(template)
+    }
+}
+
+impl From<FewShotPromptTemplate> for ChainPrompt {
+    fn from(template: FewShotPromptTemplate) -> Self {
+        ChainPrompt::FewShot(Box::new(template))
+    }
+}
+
+impl From<ChatPromptTemplate> for ChainPrompt {
+    fn from(template: ChatPromptTemplate) -> Self {
+        ChainPrompt::Chat(template)
+    }
+}
+
+/// Every required variable must be given.
+pub(crate) fn check_variables(required: Vec<String>, variables: &Map<String, Value>) -> Result<(), PromptError> {
+    let missing: Vec<String> = required
+        .into_iter()
+        .filter(|name| !variables.contains_key(name))
+        .collect();
+    
+    if !missing.is_empty() {
+        return Err(PromptError::MissingVariables(missing));
+    }
+    
+    Ok(())
+}
+
+/// Every given variable must be accepted; only strict templates check this.
+fn check_unexpected(accepted: impl Fn(&str) -> bool, variables: &Map<String, Value>) -> Result<(), PromptError> {
+    let unexpected: Vec<String> = variables
+        .keys()
+        .filter(|name| !accepted(name))
+        .cloned()
+        .collect();
+    
+    if !unexpected.is_empty() {
+        return Err(PromptError::UnexpectedVariables(unexpected));
+    }
+    
+    Ok(())
+}
+
+fn syntax(position: usize, message: impl Into<String>) -> PromptError {
+    PromptError::Syntax {
+        position,
+        message: message.into(),
+    }
+}
+
+enum Token {
+    Text(String),
+    Tag { content: String, position: usize },
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token>, PromptError> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.char_indices().peekable();
+    
+    while let Some((position, c)) = chars.next() {
+        match c {
+            '{' if chars.peek().map(|(_, next)| *next) == Some('{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek().map(|(_, next)| *next) == Some('}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let close = template[position..]
+                    .find('}')
+                    .ok_or_else(|| syntax(position, "unclosed '{'; write '{{' for a literal brace"))?;
+                let content = &template[position + 1..position + close];
+                
+                if content.contains('{') {
+                    return Err(syntax(position, "unclosed '{'; write '{{' for a literal brace"));
+                }
+                
+                if !text.is_empty() {
+                    tokens.push(Token::Text(std::mem::take(&mut text)));
+                }
+                tokens.push(Token::Tag {
+                    content: content.trim().to_string(),
+                    position,
+                });
+                
+                while chars.peek().is_some_and(|(i, _)| *i <= position + close) {
+                    chars.next();
+                }
+            }
+            '}' => return Err(syntax(position, "unmatched '}'; write '}}' for a literal brace")),
+            c => text.push(c),
+        }
+    }
+    
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    
+    Ok(tokens)
+}
+
+enum BlockKind {
+    Root,
+    If(Path),
+    Each(Path, String),
+}
+
+struct Block {
+    kind: BlockKind,
+    position: usize,
+    nodes: Vec<Node>,
+    // Set once `{#else}` has been seen
+    otherwise: Option<Vec<Node>>,
+}
+
+impl Block {
+    fn new(kind: BlockKind, position: usize) -> Self {
+        Self {
+            kind,
+            position,
+            nodes: Vec::new(),
+            otherwise: None,
+        }
+    }
+    
+    fn push(&mut self, node: Node) {
+        self.otherwise.as_mut().unwrap_or(&mut self.nodes).push(node);
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Node>, PromptError> {
+    let mut stack = vec![Block::new(BlockKind::Root, 0)];
+    
+    for token in tokenize(template)? {
+        let (content, position) = match token {
+            Token::Text(text) => {
+                stack.last_mut().expect("root block").push(Node::Text(text));
+                continue;
+            }
+            Token::Tag { content, position } => (content, position),
+        };
+        
+        if let Some(rest) = content.strip_prefix("#if ") {
+            stack.push(Block::new(BlockKind::If(Path::parse(rest.trim(), position)?), position));
+        } else if let Some(rest) = content.strip_prefix("#each ") {
+            let (items, binding) = match rest.split_once(" as ") {
+                Some((items, binding)) => (items.trim(), binding.trim()),
+                None => (rest.trim(), "this"),
+            };
+            
+            let binding_path = Path::parse(binding, position)?;
+            if binding_path.segments.len() != 1 || binding.starts_with('@') {
+                return Err(syntax(position, format!("'{}' can't be used as a loop variable", binding)));
+            }
+            
+            stack.push(Block::new(
+                BlockKind::Each(Path::parse(items, position)?, binding.to_string()),
+                position,
+            ));
+        } else if content == "#else" {
+            let block = stack.last_mut().expect("root block");
+            if !matches!(block.kind, BlockKind::If(_)) || block.otherwise.is_some() {
+                return Err(syntax(position, "'{#else}' outside of '{#if}'"));
+            }
+            block.otherwise = Some(Vec::new());
+        } else if let Some(name) = content.strip_prefix('/') {
+            let block = stack.pop().expect("root block");
+            
+            let node = match (block.kind, name) {
+                (BlockKind::If(condition), "if") => Node::If {
+                    condition,
+                    then: block.nodes,
+                    otherwise: block.otherwise.unwrap_or_default(),
+                },
+                (BlockKind::Each(items, binding), "each") => Node::Each {
+                    items,
+                    binding,
+                    body: block.nodes,
+                },
+                _ => return Err(syntax(position, format!("unexpected '{{/{}}}'", name))),
+            };
+            
+            stack.last_mut().expect("root block").push(node);
+        } else {
+            let path = Path::parse(&content, position)?;
+            stack.last_mut().expect("root block").push(Node::Variable(path));
+        }
+    }
+    
+    let root = stack.pop().expect("root block");
+    
+    match root.kind {
+        BlockKind::Root => Ok(root.nodes),
+        _ => Err(syntax(root.position, "block is never closed")),
+    }
+}
+
+// Roots of paths that aren't bound by an enclosing loop
+fn collect_variables(nodes: &[Node], bound: &mut Vec<String>, variables: &mut BTreeSet<String>) {
+    let add = |path: &Path, bound: &[String], variables: &mut BTreeSet<String>| {
+        let root = path.root();
+        if !root.starts_with('@') && !bound.iter().any(|name| name == root) {
+            variables.insert(root.to_string());
+        }
+    };
+    
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Variable(path) => add(path, bound, variables),
+            Node::If { condition, then, otherwise } => {
+                add(condition, bound, variables);
+                collect_variables(then, bound, variables);
+                collect_variables(otherwise, bound, variables);
+            }
+            Node::Each { items, binding, body } => {
+                add(items, bound, variables);
+                bound.push(binding.clone());
+                collect_variables(body, bound, variables);
+                bound.pop();
+            }
+        }
+    }
+}
+
+struct Scope<'a> {
+    variables: &'a Map<String, Value>,
+    partials: &'a Map<String, Value>,
+    // Loop variables, innermost last
+    locals: Vec<(String, Value)>,
+}
+
+impl Scope<'_> {
+    // Paths that lead nowhere resolve to null rather than failing, so
+    // optional fields can be tested with `{#if}`
+    fn lookup(&self, path: &Path) -> Value {
+        let root = path.root();
+        
+        let value = self.locals
+            .iter()
+            .rev()
+            .find(|(name, _)| name == root)
+            .map(|(_, value)| value)
+            .or_else(|| self.variables.get(root))
+            .or_else(|| self.partials.get(root));
+        
+        path.segments[1..]
+            .iter()
+            .fold(value, |value, segment| match value? {
+                Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+                Value::Object(fields) => fields.get(segment),
+                _ => None,
+            })
+            .cloned()
+            .unwrap_or(Value::Null)
+    }
+}
+
+fn render_nodes(nodes: &[Node], scope: &mut Scope<'_>, output: &mut String) -> Result<(), PromptError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable(path) => output.push_str(&format_value(&scope.lookup(path))),
+            Node::If { condition, then, otherwise } => {
+                let branch = if is_truthy(&scope.lookup(condition)) { then } else { otherwise };
+                render_nodes(branch, scope, output)?;
+            }
+            Node::Each { items, binding, body } => {
+                let items_value = match scope.lookup(items) {
+                    Value::Array(values) => values,
+                    Value::Null => Vec::new(),
+                    _ => return Err(PromptError::NotAList(items.display())),
+                };
+                
+                for (index, item) in items_value.into_iter().enumerate() {
+                    scope.locals.push(("@index".to_string(), Value::from(index)));
+                    scope.locals.push((binding.clone(), item));
+                    
+                    let rendered = render_nodes(body, scope, output);
+                    
+                    scope.locals.truncate(scope.locals.len() - 2);
+                    rendered?;
+                }
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        Value::Array(_) | Value::Object(_) => serde_json::to_string_pretty(value).unwrap_or_default(),
+        other => other.to_string(),
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
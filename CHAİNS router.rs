@@ -0,0 +1,382 @@
+use super::{Chain, ChainEvent, ChainEvents, ChainInput, ChainOutput, ChainMetadata, ChainSpend, StepInfo};
+use super::manager::ChainManager;
+use super::prompt::PromptTemplate;
+use super::structure::ChainStructure;
+use crate::embeddings::EmbeddingProvider;
+use crate::llm::{GenerationParams, LLMProvider, LLMRequest};
+use crate::memory::vector::cosine_similarity;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// Default classification prompt; it gets `destinations` (each with `id`,
+/// `name` and `description`) and `input`.
+pub const DEFAULT_ROUTER_PROMPT: &str = "\
+Pick the assistant best suited to handle the input.
+
+Assistants:
+{#each destinations as destination}- {destination.id}: {destination.name}. {destination.description}
+{/each}
+Input: {input}
+
+Reply with the id of one assistant only, or \"none\" if none of them fits.";
+
+enum Strategy {
+    Llm {
+        llm: Arc<dyn LLMProvider>,
+        prompt: PromptTemplate,
+        params: GenerationParams,
+    },
+    Embedding {
+        embeddings: Arc<dyn EmbeddingProvider>,
+        // (chain id, utterance embedding)
+        utterances: RwLock<Vec<(String, Vec<f32>)>>,
+        min_score: f32,
+    },
+}
+
+struct Route {
+    chain_id: String,
+    // Why the destination was picked, for the routing step
+    reason: String,
+    tokens: usize,
+    cost: f64,
+}
+
+/// Dispatches its input to one of the chains registered in a `ChainManager`.
+///
+/// The destination is picked either by an LLM shown each chain's name and
+/// description, or by comparing the input's embedding with example
+/// utterances given per chain. When nothing fits, the default chain runs
+/// instead, or routing fails if there is none.
+///
+/// The text routed on is the `input` variable (see `with_input_key`); the
+/// destination gets the variables named by its `input_keys()`, or the whole
+/// input when it doesn't list them. The routing decision is recorded as a
+/// `route` step and the result carries the chosen chain id as `destination`.
+pub struct RouterChain {
+    name: String,
+    description: String,
+    manager: Arc<ChainManager>,
+    strategy: Strategy,
+    destinations: Vec<String>,
+    default_chain: Option<String>,
+    input_key: String,
+}
+
+impl RouterChain {
+    /// Routes by asking `llm` to classify the input.
+    pub fn llm(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        manager: Arc<ChainManager>,
+        llm: Arc<dyn LLMProvider>,
+    ) -> Self {
+        let prompt = PromptTemplate::new(DEFAULT_ROUTER_PROMPT).expect("the default router prompt is valid");
+        
+        Self::with_strategy(name, description, manager, Strategy::Llm {
+            llm,
+            prompt,
+            params: GenerationParams::default(),
+        })
+    }
+    
+    /// Routes by embedding similarity to the utterances added with
+    /// `add_utterances`.
+    pub fn embedding(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        manager: Arc<ChainManager>,
+        embeddings: Arc<dyn EmbeddingProvider>,
+    ) -> Self {
+        Self::with_strategy(name, description, manager, Strategy::Embedding {
+            embeddings,
+            utterances: RwLock::new(Vec::new()),
+            min_score: 0.0,
+        })
+    }
+    
+    fn with_strategy(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        manager: Arc<ChainManager>,
+        strategy: Strategy,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            manager,
+            strategy,
+            destinations: Vec::new(),
+            default_chain: None,
+            input_key: "input".to_string(),
+        }
+    }
+    
+    /// Limits routing to these chain ids; every other registered chain is a
+    /// candidate otherwise.
+    pub fn with_destinations(mut self, chain_ids: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.destinations = chain_ids.into_iter().map(Into::into).collect();
+        self
+    }
+    
+    /// Chain run when no destination fits.
+    pub fn with_default(mut self, chain_id: impl Into<String>) -> Self {
+        self.default_chain = Some(chain_id.into());
+        self
+    }
+    
+    /// Input variable holding the text to route on.
+    pub fn with_input_key(mut self, key: impl Into<String>) -> Self {
+        self.input_key = key.into();
+        self
+    }
+    
+    /// Replaces the classification prompt of an LLM router. It must take
+    /// `destinations` and `input`, like `DEFAULT_ROUTER_PROMPT`.
+    pub fn with_prompt(mut self, template: PromptTemplate) -> Self {
+        if let Strategy::Llm { prompt, .. } = &mut self.strategy {
+            *prompt = template;
+        }
+        self
+    }
+    
+    pub fn with_params(mut self, generation: GenerationParams) -> Self {
+        if let Strategy::Llm { params, .. } = &mut self.strategy {
+            *params = generation;
+        }
+        self
+    }
+    
+    /// Least similarity an embedding router accepts before falling back to
+    /// the default chain.
+    pub fn with_min_score(mut self, score: f32) -> Self {
+        if let Strategy::Embedding { min_score, .. } = &mut self.strategy {
+            *min_score = score;
+        }
+        self
+    }
+    
+    /// Adds example inputs that should be routed to `chain_id`. Ignored by
+    /// LLM routers.
+    pub async fn add_utterances(
+        &self,
+        chain_id: &str,
+        utterances: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<()> {
+        let Strategy::Embedding { embeddings, utterances: stored, .. } = &self.strategy else {
+            return Ok(());
+        };
+        
+        let texts: Vec<String> = utterances.into_iter().map(Into::into).collect();
+        let vectors = embeddings.embed(&texts).await?;
+        
+        stored
+            .write()
+            .extend(vectors.into_iter().map(|vector| (chain_id.to_string(), vector)));
+        
+        Ok(())
+    }
+    
+    // Candidate chain ids in a stable order, never the router itself. Compared
+    // by address since another chain may share the router's name
+    fn candidates(&self) -> Vec<String> {
+        let itself = self as *const Self as *const ();
+        
+        let mut ids: Vec<String> = if self.destinations.is_empty() {
+            self.manager
+                .list_chains()
+                .into_iter()
+                .map(|info| info.id)
+                .filter(|id| match self.manager.get_chain(id) {
+                    Some(chain) => Arc::as_ptr(&chain) as *const () != itself,
+                    None => false,
+                })
+                .collect()
+        } else {
+            self.destinations.clone()
+        };
+        
+        ids.sort();
+        ids
+    }
+    
+    async fn route(&self, text: &str) -> Result<Option<Route>> {
+        let candidates = self.candidates();
+        
+        match &self.strategy {
+            Strategy::Llm { llm, prompt, params } => {
+                let destinations: Vec<serde_json::Value> = candidates
+                    .iter()
+                    .filter_map(|id| {
+                        let chain = self.manager.get_chain(id)?;
+                        Some(serde_json::json!({
+                            "id": id,
+                            "name": chain.name(),
+                            "description": chain.description(),
+                        }))
+                    })
+                    .collect();
+                
+                let mut variables = serde_json::Map::new();
+                variables.insert("destinations".to_string(), serde_json::Value::Array(destinations));
+                variables.insert("input".to_string(), serde_json::Value::String(text.to_string()));
+                
+                let request = params.apply(LLMRequest::new(prompt.render(&variables)?));
+                let response = llm.generate(&request).await.context("Router classification failed")?;
+                
+                Ok(match_reply(&response.text, &candidates).map(|chain_id| Route {
+                    chain_id,
+                    reason: format!("classified as: {}", response.text.trim()),
+                    tokens: response.billed_tokens(),
+                    cost: response.cost,
+                }))
+            }
+            Strategy::Embedding { embeddings, utterances, min_score } => {
+                let query = embeddings.embed_query(text).await?;
+                
+                let best = utterances
+                    .read()
+                    .iter()
+                    .filter(|(chain_id, _)| candidates.contains(chain_id))
+                    .map(|(chain_id, vector)| (chain_id.clone(), cosine_similarity(&query, vector)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1));
+                
+                Ok(best.filter(|(_, score)| score >= min_score).map(|(chain_id, score)| Route {
+                    chain_id,
+                    reason: format!("similarity {:.3}", score),
+                    tokens: 0,
+                    cost: 0.0,
+                }))
+            }
+        }
+    }
+    
+    async fn run(&self, input: ChainInput, events: &ChainEvents) -> Result<ChainOutput> {
+        let start = std::time::Instant::now();
+        
+        let text = input.get_string(&self.input_key)
+            .ok_or_else(|| anyhow::anyhow!("Missing '{}' in input", self.input_key))?;
+        
+        events.send(ChainEvent::StepStart { name: "route".to_string() });
+        let route_start = std::time::Instant::now();
+        
+        let (chain_id, reason, tokens, cost) = match self.route(&text).await? {
+            Some(route) => (route.chain_id, route.reason, route.tokens, route.cost),
+            None => {
+                let default = self.default_chain.clone().ok_or_else(|| {
+                    anyhow::anyhow!("Router '{}' found no chain for the input and has no default", self.name)
+                })?;
+                (default, "no destination matched, using the default".to_string(), 0, 0.0)
+            }
+        };
+        
+        let chain = self.manager
+            .get_chain(&chain_id)
+            .ok_or_else(|| anyhow::anyhow!("Router '{}' routed to unknown chain '{}'", self.name, chain_id))?;
+        
+        let step = StepInfo {
+            name: "route".to_string(),
+            duration_ms: route_start.elapsed().as_millis() as u64,
+            input: text,
+            output: format!("{} ({})", chain_id, reason),
+        };
+        events.send(ChainEvent::StepEnd { step: step.clone() });
+        
+        // The destination gets what it reads, like a sequential step
+        let input = match chain.input_keys() {
+            Some(keys) => {
+                let mut selected = ChainInput::new();
+                for key in keys {
+                    if let Some(value) = input.variables.get(&key) {
+                        selected.variables.insert(key, value.clone());
+                    }
+                }
+                selected
+            }
+            None => input,
+        };
+        
+        let mut output = chain
+            .execute_streaming(input, events.clone())
+            .await
+            .map_err(|e| ChainSpend::new(tokens, cost).attach(e))?;
+        output.result["destination"] = serde_json::Value::String(chain_id);
+        
+        let mut steps = vec![step];
+        steps.extend(output.metadata.steps);
+        
+        Ok(ChainOutput {
+            result: output.result,
+            metadata: ChainMetadata {
+                chain_name: self.name.clone(),
+                execution_time_ms: start.elapsed().as_millis() as u64,
+                steps,
+                total_tokens: tokens + output.metadata.total_tokens,
+                total_cost: cost + output.metadata.total_cost,
+            },
+        })
+    }
+}
+
+// The candidate the reply names: an exact match, or else the longest id
+// found in the reply, so `sql` doesn't shadow `sql_admin`
+fn match_reply(reply: &str, candidates: &[String]) -> Option<String> {
+    let reply = reply.trim().trim_matches(|c: char| c == '"' || c == '\'' || c == '`' || c == '.').to_lowercase();
+    
+    if let Some(id) = candidates.iter().find(|id| id.to_lowercase() == reply) {
+        return Some(id.clone());
+    }
+    
+    candidates
+        .iter()
+        .filter(|id| reply.contains(&id.to_lowercase()))
+        .max_by_key(|id| id.len())
+        .cloned()
+}
+
+#[async_trait]
+impl Chain for RouterChain {
+    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+        self.run(input, &ChainEvents::disabled()).await
+    }
+    
+    async fn execute_streaming(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        self.run(input, &events).await
+    }
+    
+    fn structure(&self) -> ChainStructure {
+        let mut structure = ChainStructure::new(&self.name, "router")
+            .with_node("route", ChainStructure::new("route", "route"));
+        
+        let mut ids = self.candidates();
+        if let Some(default) = &self.default_chain {
+            if !ids.contains(default) {
+                ids.push(default.clone());
+            }
+        }
+        
+        // Destinations are listed, not expanded, since they may route back here
+        for id in ids {
+            let Some(chain) = self.manager.get_chain(&id) else {
+                continue;
+            };
+            
+            let label = if self.default_chain.as_ref() == Some(&id) { vec!["default"] } else { Vec::new() };
+            structure = structure
+                .with_node(&id, ChainStructure::new(chain.name(), "chain"))
+                .with_edge("route", &id, label);
+        }
+        
+        structure
+    }
+    
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
@@ -1,12 +1,118 @@
-use super::{Chain, ChainInput, ChainOutput, ChainMetadata, StepInfo};
+use super::{Chain, ChainEvents, ChainInput, ChainOutput, ChainMetadata, ChainSpend};
+use super::graph::{field, FieldMapping};
+use super::parser::OutputParser;
+use super::structure::ChainStructure;
 use async_trait::async_trait;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::sync::Arc;
 
+/// Key holding the previous step's whole result.
+pub const PREVIOUS_OUTPUT: &str = "previous_output";
+
+/// One step of a `SequentialChain`.
+pub struct SequentialStep {
+    chain: Arc<dyn Chain>,
+    parser: Option<Arc<dyn OutputParser>>,
+    inputs: Vec<FieldMapping>,
+    outputs: Vec<FieldMapping>,
+}
+
+impl SequentialStep {
+    pub fn new(chain: Arc<dyn Chain>) -> Self {
+        Self {
+            chain,
+            parser: None,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+    
+    /// Parses the step's `output` text into `parsed` before it is stored.
+    /// Format instructions belong in the step's own prompt, since the
+    /// sequence can't see inside it.
+    pub fn with_output_parser(mut self, parser: Arc<dyn OutputParser>) -> Self {
+        self.parser = Some(parser);
+        self
+    }
+    
+    /// Passes variable `from` to the step as `to`. Once an input is mapped
+    /// the step receives only mapped variables.
+    pub fn with_input(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.inputs.push(FieldMapping::new(from, to));
+        self
+    }
+    
+    /// Stores result field `from` (a dotted path, e.g. `parsed.title`) as
+    /// variable `to`. Once an output is mapped only mapped fields are stored.
+    pub fn with_output(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.outputs.push(FieldMapping::new(from, to));
+        self
+    }
+    
+    // Variables the step runs with, taken from the shared ones
+    fn input(&self, variables: &serde_json::Map<String, serde_json::Value>) -> Result<ChainInput> {
+        let mut input = ChainInput::new();
+        
+        if !self.inputs.is_empty() {
+            for mapping in &self.inputs {
+                let value = variables
+                    .get(&mapping.from)
+                    .ok_or_else(|| anyhow::anyhow!("Missing variable '{}' for step '{}'", mapping.from, self.chain.name()))?;
+                input.variables.insert(mapping.to.clone(), value.clone());
+            }
+            return Ok(input);
+        }
+        
+        match self.chain.input_keys() {
+            // Missing keys are left for the step to report
+            Some(keys) => {
+                for key in keys {
+                    if let Some(value) = variables.get(&key) {
+                        input.variables.insert(key, value.clone());
+                    }
+                }
+            }
+            None => input.variables = variables.clone(),
+        }
+        
+        Ok(input)
+    }
+    
+    // Stores the step's result in the shared variables
+    fn store(&self, result: &serde_json::Value, variables: &mut serde_json::Map<String, serde_json::Value>) -> Result<()> {
+        if self.outputs.is_empty() {
+            if let serde_json::Value::Object(fields) = result {
+                variables.extend(fields.clone());
+            }
+        }
+        
+        for mapping in &self.outputs {
+            let value = field(result, &mapping.from)
+                .ok_or_else(|| anyhow::anyhow!("Step '{}' has no output field '{}'", self.chain.name(), mapping.from))?;
+            variables.insert(mapping.to.clone(), value.clone());
+        }
+        
+        variables.insert(PREVIOUS_OUTPUT.to_string(), result.clone());
+        
+        Ok(())
+    }
+}
+
+/// Runs chains one after another over a shared set of variables.
+///
+/// The variables start as the chain's input. Each step reads the variables
+/// it declares (see `SequentialStep::with_input`), or else those its prompt
+/// uses, or else all of them. Its result fields are then added to the
+/// variables, or only those mapped with `SequentialStep::with_output`, and
+/// the whole result is kept under `previous_output`.
+///
+/// The final result is the last step's result, or only the variables named
+/// with `with_output_variables`.
 pub struct SequentialChain {
     name: String,
     description: String,
-    chains: Vec<Arc<dyn Chain>>,
+    steps: Vec<SequentialStep>,
+    output_variables: Vec<String>,
 }
 
 impl SequentialChain {
@@ -14,52 +120,123 @@ impl SequentialChain {
         Self {
             name: name.into(),
             description: description.into(),
-            chains: Vec::new(),
+            steps: Vec::new(),
+            output_variables: Vec::new(),
         }
     }
     
-    pub fn add_chain(mut self, chain: Arc<dyn Chain>) -> Self {
-        self.chains.push(chain);
+    pub fn add_chain(self, chain: Arc<dyn Chain>) -> Self {
+        self.add_step(SequentialStep::new(chain))
+    }
+    
+    /// Adds a step whose `output` text is parsed into `parsed` before it is
+    /// handed to the next step.
+    pub fn add_chain_with_parser(self, chain: Arc<dyn Chain>, parser: Arc<dyn OutputParser>) -> Self {
+        self.add_step(SequentialStep::new(chain).with_output_parser(parser))
+    }
+    
+    pub fn add_step(mut self, step: SequentialStep) -> Self {
+        self.steps.push(step);
         self
     }
-}
-
-#[async_trait]
-impl Chain for SequentialChain {
-    async fn execute(&self, mut input: ChainInput) -> Result<ChainOutput> {
+    
+    /// Returns only these variables as the result.
+    pub fn with_output_variables(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.output_variables = names.into_iter().map(Into::into).collect();
+        self
+    }
+    
+    // A failed run carries what the finished steps spent
+    async fn run(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        let mut spent = ChainSpend::default();
+        self.run_steps(input, events, &mut spent).await.map_err(|e| spent.attach(e))
+    }
+    
+    async fn run_steps(&self, input: ChainInput, events: ChainEvents, spent: &mut ChainSpend) -> Result<ChainOutput> {
         let start = std::time::Instant::now();
+        let mut variables = input.variables;
+        let mut last_result = serde_json::json!({});
         let mut all_steps = Vec::new();
-        let mut total_tokens = 0;
-        let mut total_cost = 0.0;
         
-        for chain in &self.chains {
-            let output = chain.execute(input.clone()).await?;
+        for step in &self.steps {
+            let chain = &step.chain;
+            let mut output = chain.execute_streaming(step.input(&variables)?, events.clone()).await?;
+            spent.add(output.metadata.total_tokens, output.metadata.total_cost);
+            
+            if let Some(parser) = &step.parser {
+                let text = output.result["output"].as_str().unwrap_or_default();
+                output.result["parsed"] = parser
+                    .parse(text)
+                    .with_context(|| format!("Failed to parse output of '{}'", chain.name()))?;
+            }
             
             all_steps.extend(output.metadata.steps);
-            total_tokens += output.metadata.total_tokens;
-            total_cost += output.metadata.total_cost;
             
-            // Pass output to next chain
-            input = ChainInput::new().with_variable("previous_output", output.result.clone());
+            step.store(&output.result, &mut variables)?;
+            last_result = output.result;
         }
         
         let execution_time = start.elapsed().as_millis() as u64;
         
-        // Get final output
-        let final_output = input.get_string("previous_output")
-            .unwrap_or_else(|| "No output".to_string());
+        let result = if self.output_variables.is_empty() {
+            last_result
+        } else {
+            let mut selected = serde_json::Map::new();
+            
+            for name in &self.output_variables {
+                let value = variables
+                    .remove(name)
+                    .ok_or_else(|| anyhow::anyhow!("Output variable '{}' of chain '{}' was never set", name, self.name))?;
+                selected.insert(name.clone(), value);
+            }
+            
+            serde_json::Value::Object(selected)
+        };
         
         Ok(ChainOutput {
-            result: serde_json::json!({"output": final_output}),
+            result,
             metadata: ChainMetadata {
                 chain_name: self.name.clone(),
                 execution_time_ms: execution_time,
                 steps: all_steps,
-                total_tokens,
-                total_cost,
+                total_tokens: spent.tokens,
+                total_cost: spent.cost,
             },
         })
     }
+}
+
+#[async_trait]
+impl Chain for SequentialChain {
+    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+        self.run(input, ChainEvents::disabled()).await
+    }
+    
+    async fn execute_streaming(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        self.run(input, events).await
+    }
+    
+    fn structure(&self) -> ChainStructure {
+        let mut structure = ChainStructure::new(&self.name, "sequential");
+        
+        for (index, step) in self.steps.iter().enumerate() {
+            let id = format!("step_{}", index + 1);
+            structure = structure.with_node(&id, step.chain.structure());
+            
+            if index > 0 {
+                let previous = &self.steps[index - 1];
+                let fields = if previous.outputs.is_empty() {
+                    vec![PREVIOUS_OUTPUT.to_string()]
+                } else {
+                    previous.outputs.iter().map(|mapping| mapping.to.clone()).collect()
+                };
+                
+                structure = structure.with_edge(format!("step_{}", index), id, fields);
+            }
+        }
+        
+        structure
+    }
     
     fn name(&self) -> &str {
         &self.name
@@ -1,14 +1,21 @@
-use super::{Chain, ChainInput, ChainOutput, ChainMetadata, StepInfo};
-use crate::llm::{LLMProvider, LLMRequest};
+use super::{Chain, ChainEvent, ChainEvents, ChainInput, ChainOutput, ChainMetadata, ChainSpend, StepInfo};
+use super::parser::{self, OutputParser};
+use super::prompt::ChainPrompt;
+use super::structure::ChainStructure;
+use crate::llm::structured::{self, ResponseFormat};
+use crate::llm::{streaming, GenerationParams, LLMProvider};
 use async_trait::async_trait;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::sync::Arc;
 
 pub struct SimpleChain {
     name: String,
     description: String,
     llm: Arc<dyn LLMProvider>,
-    prompt_template: String,
+    prompt: ChainPrompt,
+    params: GenerationParams,
+    response_format: Option<ResponseFormat>,
+    output_parser: Option<Arc<dyn OutputParser>>,
 }
 
 impl SimpleChain {
@@ -16,60 +23,123 @@ impl SimpleChain {
         name: impl Into<String>,
         description: impl Into<String>,
         llm: Arc<dyn LLMProvider>,
-        prompt_template: impl Into<String>,
+        prompt: impl Into<ChainPrompt>,
     ) -> Self {
         Self {
             name: name.into(),
             description: description.into(),
             llm,
-            prompt_template: prompt_template.into(),
+            prompt: prompt.into(),
+            params: GenerationParams::default(),
+            response_format: None,
+            output_parser: None,
         }
     }
     
-    fn render_prompt(&self, input: &ChainInput) -> String {
-        let mut prompt = self.prompt_template.clone();
-        
-        for (key, value) in &input.variables {
-            let placeholder = format!("{{{}}}", key);
-            if let Some(value_str) = value.as_str() {
-                prompt = prompt.replace(&placeholder, value_str);
-            }
-        }
-        
-        prompt
+    pub fn with_params(mut self, params: GenerationParams) -> Self {
+        self.params = params;
+        self
     }
-}
-
-#[async_trait]
-impl Chain for SimpleChain {
-    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+    
+    /// Requires the reply to be JSON matching a schema. The validated value is
+    /// returned under `parsed`; replies are not streamed since they may be repaired.
+    pub fn with_response_format(mut self, format: ResponseFormat) -> Self {
+        self.response_format = Some(format);
+        self
+    }
+    
+    /// Parses the reply into `parsed`; the parser's format instructions are
+    /// added to the prompt.
+    pub fn with_output_parser(mut self, parser: Arc<dyn OutputParser>) -> Self {
+        self.output_parser = Some(parser);
+        self
+    }
+    
+    async fn run(&self, input: ChainInput, events: &ChainEvents) -> Result<ChainOutput> {
         let start = std::time::Instant::now();
         
-        let prompt = self.render_prompt(&input);
+        let prompt = parser::render_prompt(&self.prompt, input.variables, self.output_parser.as_deref()).await?;
         
-        let request = LLMRequest::new(prompt.clone());
-        let response = self.llm.generate(&request).await?;
+        let mut request = self.params.apply(prompt.to_request());
+        
+        if let Some(format) = &self.response_format {
+            request = request.with_response_format(format.clone());
+        }
+        
+        events.send(ChainEvent::StepStart { name: "llm_call".to_string() });
+        
+        let (response, parsed) = if self.response_format.is_some() {
+            let structured = structured::generate(self.llm.as_ref(), &request, structured::DEFAULT_MAX_REPAIRS).await?;
+            (structured.response, Some(structured.value))
+        } else if events.is_enabled() {
+            let stream = self.llm.stream_generate(&request).await?;
+            (streaming::collect(stream, start, |delta| events.token(delta)).await?, None)
+        } else {
+            (self.llm.generate(&request).await?, None)
+        };
         
         let execution_time = start.elapsed().as_millis() as u64;
         
-        Ok(ChainOutput {
-            result: serde_json::json!({
-                "output": response.text,
-                "model": response.model,
-            }),
+        // The reply was paid for even if it can't be parsed
+        let parsed = match &self.output_parser {
+            Some(parser) => Some(
+                parser
+                    .parse(&response.text)
+                    .context("Failed to parse chain output")
+                    .map_err(|e| ChainSpend::new(response.billed_tokens(), response.cost).attach(e))?,
+            ),
+            None => parsed,
+        };
+        
+        let mut result = serde_json::json!({
+            "output": response.text,
+            "model": response.model,
+        });
+        
+        if let Some(parsed) = parsed {
+            result["parsed"] = parsed;
+        }
+        
+        let output = ChainOutput {
+            result,
             metadata: ChainMetadata {
                 chain_name: self.name.clone(),
                 execution_time_ms: execution_time,
                 steps: vec![StepInfo {
                     name: "llm_call".to_string(),
                     duration_ms: response.latency_ms,
-                    input: prompt,
+                    input: prompt.to_string(),
                     output: response.text.clone(),
                 }],
-                total_tokens: response.tokens_used.total_tokens,
-                total_cost: response.tokens_used.estimate_cost(&response.model),
+                total_tokens: response.billed_tokens(),
+                total_cost: response.cost,
             },
-        })
+        };
+        
+        for step in &output.metadata.steps {
+            events.send(ChainEvent::StepEnd { step: step.clone() });
+        }
+        
+        Ok(output)
+    }
+}
+
+#[async_trait]
+impl Chain for SimpleChain {
+    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+        self.run(input, &ChainEvents::disabled()).await
+    }
+    
+    async fn execute_streaming(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        self.run(input, &events).await
+    }
+    
+    fn input_keys(&self) -> Option<Vec<String>> {
+        Some(parser::prompt_inputs(&self.prompt, self.output_parser.as_deref()))
+    }
+    
+    fn structure(&self) -> ChainStructure {
+        ChainStructure::new(&self.name, "simple")
     }
     
     fn name(&self) -> &str {
@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// How a chain is wired: its kind and, for chains built from others, the
+/// nested nodes and the edges between them. Served by the graph view and
+/// rendered as Graphviz DOT or Mermaid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainStructure {
+    pub name: String,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<StructureNode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edges: Vec<StructureEdge>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructureNode {
+    /// Unique among its siblings; edges refer to nodes by this id
+    pub id: String,
+    #[serde(flatten)]
+    pub structure: ChainStructure,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructureEdge {
+    pub from: String,
+    pub to: String,
+    /// Variables passed along the edge
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+}
+
+impl ChainStructure {
+    pub fn new(name: impl Into<String>, kind: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            kind: kind.into(),
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+    
+    pub fn with_node(mut self, id: impl Into<String>, structure: ChainStructure) -> Self {
+        self.nodes.push(StructureNode {
+            id: id.into(),
+            structure,
+        });
+        self
+    }
+    
+    pub fn with_edge(
+        mut self,
+        from: impl Into<String>,
+        to: impl Into<String>,
+        fields: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.edges.push(StructureEdge {
+            from: from.into(),
+            to: to.into(),
+            fields: fields.into_iter().map(Into::into).collect(),
+        });
+        self
+    }
+    
+    fn label(&self) -> String {
+        format!("{} ({})", self.name, self.kind)
+    }
+    
+    /// Graphviz DOT; nodes with children become clusters.
+    pub fn to_dot(&self) -> String {
+        let mut out = format!("digraph {} {{\n", dot_quote(&self.name));
+        out.push_str("    compound=true;\n");
+        out.push_str("    node [shape=box];\n");
+        
+        if self.nodes.is_empty() {
+            let _ = writeln!(out, "    \"n\" [label={}];", dot_quote(&self.label()));
+        } else {
+            let _ = writeln!(out, "    label={};", dot_quote(&self.label()));
+            self.write_dot_body(&mut out, "n", 1);
+        }
+        
+        out.push_str("}\n");
+        out
+    }
+    
+    fn write_dot_body(&self, out: &mut String, prefix: &str, depth: usize) {
+        let indent = "    ".repeat(depth);
+        
+        for (index, node) in self.nodes.iter().enumerate() {
+            let id = format!("{}_{}", prefix, index);
+            let child = &node.structure;
+            
+            if child.nodes.is_empty() {
+                let _ = writeln!(out, "{}\"{}\" [label={}];", indent, id, dot_quote(&child.label()));
+            } else {
+                let _ = writeln!(out, "{}subgraph \"cluster_{}\" {{", indent, id);
+                let _ = writeln!(out, "{}    label={};", indent, dot_quote(&child.label()));
+                child.write_dot_body(out, &id, depth + 1);
+                let _ = writeln!(out, "{}}}", indent);
+            }
+        }
+        
+        for edge in &self.edges {
+            let (Some(from), Some(to)) = (self.position(&edge.from), self.position(&edge.to)) else {
+                continue;
+            };
+            
+            let from_id = format!("{}_{}", prefix, from);
+            let to_id = format!("{}_{}", prefix, to);
+            let from_node = &self.nodes[from].structure;
+            let to_node = &self.nodes[to].structure;
+            
+            // Edges between clusters are drawn between nodes inside them and
+            // clipped at the cluster border
+            let mut attributes = Vec::new();
+            if !from_node.nodes.is_empty() {
+                attributes.push(format!("ltail=\"cluster_{}\"", from_id));
+            }
+            if !to_node.nodes.is_empty() {
+                attributes.push(format!("lhead=\"cluster_{}\"", to_id));
+            }
+            if !edge.fields.is_empty() {
+                attributes.push(format!("label={}", dot_quote(&edge.fields.join(", "))));
+            }
+            
+            let _ = write!(
+                out,
+                "{}\"{}\" -> \"{}\"",
+                indent,
+                from_node.anchor(&from_id, false),
+                to_node.anchor(&to_id, true),
+            );
+            if !attributes.is_empty() {
+                let _ = write!(out, " [{}]", attributes.join(", "));
+            }
+            out.push_str(";\n");
+        }
+    }
+    
+    // The DOT node standing in for this structure at the ends of edges
+    fn anchor(&self, id: &str, first: bool) -> String {
+        let node = if first { self.nodes.first() } else { self.nodes.last() };
+        
+        match node {
+            Some(node) => {
+                let index = if first { 0 } else { self.nodes.len() - 1 };
+                node.structure.anchor(&format!("{}_{}", id, index), first)
+            }
+            None => id.to_string(),
+        }
+    }
+    
+    /// Mermaid flowchart; nodes with children become subgraphs.
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart TD\n");
+        
+        if self.nodes.is_empty() {
+            let _ = writeln!(out, "    n[{}]", mermaid_quote(&self.label()));
+        } else {
+            self.write_mermaid_body(&mut out, "n", 1);
+        }
+        
+        out
+    }
+    
+    fn write_mermaid_body(&self, out: &mut String, prefix: &str, depth: usize) {
+        let indent = "    ".repeat(depth);
+        
+        for (index, node) in self.nodes.iter().enumerate() {
+            let id = format!("{}_{}", prefix, index);
+            let child = &node.structure;
+            
+            if child.nodes.is_empty() {
+                let _ = writeln!(out, "{}{}[{}]", indent, id, mermaid_quote(&child.label()));
+            } else {
+                let _ = writeln!(out, "{}subgraph {}[{}]", indent, id, mermaid_quote(&child.label()));
+                child.write_mermaid_body(out, &id, depth + 1);
+                let _ = writeln!(out, "{}end", indent);
+            }
+        }
+        
+        for edge in &self.edges {
+            let (Some(from), Some(to)) = (self.position(&edge.from), self.position(&edge.to)) else {
+                continue;
+            };
+            
+            if edge.fields.is_empty() {
+                let _ = writeln!(out, "{}{}_{} --> {}_{}", indent, prefix, from, prefix, to);
+            } else {
+                let label = mermaid_quote(&edge.fields.join(", "));
+                let _ = writeln!(out, "{}{}_{} -->|{}| {}_{}", indent, prefix, from, label, prefix, to);
+            }
+        }
+    }
+    
+    fn position(&self, id: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.id == id)
+    }
+}
+
+fn dot_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn mermaid_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "#quot;"))
+}
@@ -0,0 +1,470 @@
+use super::{Chain, ChainEvent, ChainEvents, ChainInput, ChainOutput, ChainMetadata, ChainSpend, StepInfo};
+use super::prompt::PromptTemplate;
+use super::structure::ChainStructure;
+use crate::llm::{GenerationParams, LLMProvider, LLMRequest};
+use crate::rag::chunker::TextChunker;
+use crate::rag::Document;
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::sync::Arc;
+
+/// Tokens one summarization call may use, prompt and reply together,
+/// unless set with `with_token_budget`.
+pub const DEFAULT_TOKEN_BUDGET: usize = 4096;
+
+// Room kept for the reply when `max_tokens` isn't set
+const DEFAULT_REPLY_TOKENS: usize = 512;
+
+// Smallest chunk worth splitting a text into, in bytes
+const MIN_CHUNK_SIZE: usize = 64;
+
+/// LLM calls and text splitting shared by the summarization chains.
+struct Summarizer {
+    llm: Arc<dyn LLMProvider>,
+    params: GenerationParams,
+    token_budget: usize,
+    chunk_overlap: usize,
+}
+
+impl Summarizer {
+    fn new(llm: Arc<dyn LLMProvider>) -> Self {
+        Self {
+            llm,
+            params: GenerationParams::default(),
+            token_budget: DEFAULT_TOKEN_BUDGET,
+            chunk_overlap: 200,
+        }
+    }
+    
+    // Tokens left for `{text}` once the prompt's other text and the reply are counted
+    fn text_budget(&self, prompt: &PromptTemplate, other: &[(&str, &str)]) -> Result<usize> {
+        let mut variables = serde_json::Map::new();
+        variables.insert("text".to_string(), serde_json::Value::String(String::new()));
+        for (name, value) in other {
+            variables.insert(name.to_string(), serde_json::Value::String(value.to_string()));
+        }
+        
+        let overhead = self.llm.count_tokens(&prompt.render(&variables)?)?;
+        let reply = self.params.max_tokens.unwrap_or(DEFAULT_REPLY_TOKENS);
+        
+        match self.token_budget.checked_sub(overhead + reply) {
+            Some(budget) if budget > 0 => Ok(budget),
+            _ => anyhow::bail!(
+                "Token budget of {} leaves no room for text after the prompt ({} tokens) and reply ({} tokens)",
+                self.token_budget,
+                overhead,
+                reply,
+            ),
+        }
+    }
+    
+    // Splits `text` into chunks of at most `budget` tokens, shrinking the
+    // chunk size until every chunk fits
+    fn split(&self, text: &str, budget: usize) -> Result<Vec<String>> {
+        let tokens = self.llm.count_tokens(text)?;
+        if tokens <= budget {
+            return Ok(vec![text.to_string()]);
+        }
+        
+        let document = Document::new(text.to_string(), String::new());
+        let mut chunk_size = text.len() * budget / tokens;
+        
+        while chunk_size >= MIN_CHUNK_SIZE {
+            let overlap = self.chunk_overlap.min(chunk_size / 4);
+            let chunks: Vec<String> = TextChunker::new(chunk_size, overlap)
+                .chunk_document(&document)?
+                .into_iter()
+                .map(|chunk| chunk.content)
+                .collect();
+            
+            let mut fits = true;
+            for chunk in &chunks {
+                if self.llm.count_tokens(chunk)? > budget {
+                    fits = false;
+                    break;
+                }
+            }
+            
+            if fits {
+                return Ok(chunks);
+            }
+            
+            chunk_size = chunk_size * 3 / 4;
+        }
+        
+        anyhow::bail!("Can't split the text into chunks of at most {} tokens", budget)
+    }
+    
+    async fn generate(&self, prompt: &PromptTemplate, variables: &[(&str, &str)]) -> Result<(String, usize, f64)> {
+        let variables: serde_json::Map<String, serde_json::Value> = variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), serde_json::Value::String(value.to_string())))
+            .collect();
+        
+        let request = self.params.apply(LLMRequest::new(prompt.render(&variables)?));
+        let response = self.llm.generate(&request).await?;
+        
+        Ok((
+            response.text.trim().to_string(),
+            response.billed_tokens(),
+            response.cost,
+        ))
+    }
+}
+
+fn text_input(input: &ChainInput) -> Result<String> {
+    input.get_string("text").ok_or_else(|| anyhow::anyhow!("Missing 'text' in input"))
+}
+
+fn finish_step(events: &ChainEvents, name: &str, input: String, output: String, start: std::time::Instant) -> StepInfo {
+    let step = StepInfo {
+        name: name.to_string(),
+        duration_ms: start.elapsed().as_millis() as u64,
+        input,
+        output,
+    };
+    events.send(ChainEvent::StepEnd { step: step.clone() });
+    step
+}
+
+/// Summarizes texts longer than the model's context: the `text` input is
+/// split into chunks that fit the token budget, each chunk is summarized
+/// with `map_prompt` (at most `max_concurrency` at a time), and the
+/// summaries are merged with `combine_prompt`. Summaries that together
+/// don't fit are combined in groups, repeatedly, until one remains. A text
+/// that fits in one chunk takes a single call.
+///
+/// Both prompts take the text as `{text}`.
+pub struct MapReduceChain {
+    name: String,
+    description: String,
+    summarizer: Summarizer,
+    map_prompt: PromptTemplate,
+    combine_prompt: PromptTemplate,
+    max_concurrency: usize,
+}
+
+impl MapReduceChain {
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        llm: Arc<dyn LLMProvider>,
+        map_prompt: PromptTemplate,
+        combine_prompt: PromptTemplate,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            summarizer: Summarizer::new(llm),
+            map_prompt,
+            combine_prompt,
+            max_concurrency: 4,
+        }
+    }
+    
+    pub fn with_params(mut self, params: GenerationParams) -> Self {
+        self.summarizer.params = params;
+        self
+    }
+    
+    /// Tokens one call may use, prompt and reply together.
+    pub fn with_token_budget(mut self, tokens: usize) -> Self {
+        self.summarizer.token_budget = tokens;
+        self
+    }
+    
+    /// Bytes repeated between neighbouring chunks.
+    pub fn with_chunk_overlap(mut self, overlap: usize) -> Self {
+        self.summarizer.chunk_overlap = overlap;
+        self
+    }
+    
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+    
+    // Runs `prompt` over every text, keeping their order
+    async fn summarize_all(&self, prompt: &PromptTemplate, texts: &[String]) -> Result<Vec<(String, usize, f64)>> {
+        // Owned texts keep the futures from borrowing the stream's items
+        stream::iter(texts.iter().cloned())
+            .map(|text| async move { self.summarizer.generate(prompt, &[("text", text.as_str())]).await })
+            .buffered(self.max_concurrency)
+            .try_collect()
+            .await
+    }
+    
+    // Groups summaries, in order, into batches whose joined text fits `budget`
+    fn batches(&self, summaries: &[String], budget: usize) -> Result<Vec<String>> {
+        let llm = &self.summarizer.llm;
+        let mut batches = Vec::new();
+        let mut current = String::new();
+        
+        for summary in summaries {
+            if llm.count_tokens(summary)? > budget {
+                anyhow::bail!("A chunk summary alone exceeds the {} token budget of the combine prompt", budget);
+            }
+            
+            if current.is_empty() {
+                current = summary.clone();
+                continue;
+            }
+            
+            let candidate = format!("{}\n\n{}", current, summary);
+            
+            if llm.count_tokens(&candidate)? <= budget {
+                current = candidate;
+            } else {
+                batches.push(std::mem::replace(&mut current, summary.clone()));
+            }
+        }
+        
+        batches.push(current);
+        Ok(batches)
+    }
+    
+    // A failed run carries what the finished calls spent
+    async fn run(&self, input: ChainInput, events: &ChainEvents) -> Result<ChainOutput> {
+        let mut spent = ChainSpend::default();
+        self.run_steps(input, events, &mut spent).await.map_err(|e| spent.attach(e))
+    }
+    
+    async fn run_steps(&self, input: ChainInput, events: &ChainEvents, spent: &mut ChainSpend) -> Result<ChainOutput> {
+        let start = std::time::Instant::now();
+        let text = text_input(&input)?;
+        let mut steps = Vec::new();
+        
+        events.send(ChainEvent::StepStart { name: "map".to_string() });
+        let map_start = std::time::Instant::now();
+        
+        let chunks = self.summarizer.split(&text, self.summarizer.text_budget(&self.map_prompt, &[])?)?;
+        let mut summaries = Vec::new();
+        
+        for (summary, tokens, cost) in self.summarize_all(&self.map_prompt, &chunks).await? {
+            summaries.push(summary);
+            spent.add(tokens, cost);
+        }
+        
+        steps.push(finish_step(
+            events,
+            "map",
+            format!("{} chunks", chunks.len()),
+            summaries.join("\n\n"),
+            map_start,
+        ));
+        
+        let combine_budget = self.summarizer.text_budget(&self.combine_prompt, &[])?;
+        let mut round = 1;
+        
+        while summaries.len() > 1 {
+            let name = format!("reduce_{}", round);
+            events.send(ChainEvent::StepStart { name: name.clone() });
+            let reduce_start = std::time::Instant::now();
+            
+            let batches = self.batches(&summaries, combine_budget)?;
+            let count = summaries.len();
+            
+            if batches.len() == count {
+                anyhow::bail!("Summaries are too long to combine within the {} token budget", combine_budget);
+            }
+            
+            summaries.clear();
+            for (summary, tokens, cost) in self.summarize_all(&self.combine_prompt, &batches).await? {
+                summaries.push(summary);
+                spent.add(tokens, cost);
+            }
+            
+            steps.push(finish_step(
+                events,
+                &name,
+                format!("{} summaries in {} groups", count, batches.len()),
+                summaries.join("\n\n"),
+                reduce_start,
+            ));
+            
+            round += 1;
+        }
+        
+        Ok(ChainOutput {
+            result: serde_json::json!({
+                "output": summaries.remove(0),
+                "chunks": chunks.len(),
+            }),
+            metadata: ChainMetadata {
+                chain_name: self.name.clone(),
+                execution_time_ms: start.elapsed().as_millis() as u64,
+                steps,
+                total_tokens: spent.tokens,
+                total_cost: spent.cost,
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl Chain for MapReduceChain {
+    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+        self.run(input, &ChainEvents::disabled()).await
+    }
+    
+    async fn execute_streaming(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        self.run(input, &events).await
+    }
+    
+    fn input_keys(&self) -> Option<Vec<String>> {
+        Some(vec!["text".to_string()])
+    }
+    
+    fn structure(&self) -> ChainStructure {
+        ChainStructure::new(&self.name, "map_reduce")
+            .with_node("map", ChainStructure::new("map", "map"))
+            .with_node("reduce", ChainStructure::new("reduce", "reduce"))
+            .with_edge("map", "reduce", ["summaries"])
+    }
+    
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+/// Summarizes a long text chunk by chunk: the first chunk is summarized
+/// with `initial_prompt` (`{text}`), then each following chunk updates the
+/// running summary with `refine_prompt` (`{summary}` and `{text}`).
+///
+/// Chunks are sized to leave room in the budget for a summary as long as
+/// the reply limit (`max_tokens`, or 512 tokens).
+pub struct RefineChain {
+    name: String,
+    description: String,
+    summarizer: Summarizer,
+    initial_prompt: PromptTemplate,
+    refine_prompt: PromptTemplate,
+}
+
+impl RefineChain {
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        llm: Arc<dyn LLMProvider>,
+        initial_prompt: PromptTemplate,
+        refine_prompt: PromptTemplate,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            summarizer: Summarizer::new(llm),
+            initial_prompt,
+            refine_prompt,
+        }
+    }
+    
+    pub fn with_params(mut self, params: GenerationParams) -> Self {
+        self.summarizer.params = params;
+        self
+    }
+    
+    /// Tokens one call may use, prompt and reply together.
+    pub fn with_token_budget(mut self, tokens: usize) -> Self {
+        self.summarizer.token_budget = tokens;
+        self
+    }
+    
+    /// Bytes repeated between neighbouring chunks.
+    pub fn with_chunk_overlap(mut self, overlap: usize) -> Self {
+        self.summarizer.chunk_overlap = overlap;
+        self
+    }
+    
+    // A failed run carries what the finished calls spent
+    async fn run(&self, input: ChainInput, events: &ChainEvents) -> Result<ChainOutput> {
+        let mut spent = ChainSpend::default();
+        self.run_steps(input, events, &mut spent).await.map_err(|e| spent.attach(e))
+    }
+    
+    async fn run_steps(&self, input: ChainInput, events: &ChainEvents, spent: &mut ChainSpend) -> Result<ChainOutput> {
+        let start = std::time::Instant::now();
+        let text = text_input(&input)?;
+        let mut steps = Vec::new();
+        
+        // The refine prompt also holds the summary, up to one reply long
+        let summary_tokens = self.summarizer.params.max_tokens.unwrap_or(DEFAULT_REPLY_TOKENS);
+        let budget = self.summarizer
+            .text_budget(&self.initial_prompt, &[])?
+            .min(self.summarizer.text_budget(&self.refine_prompt, &[("summary", "")])?.saturating_sub(summary_tokens));
+        
+        if budget == 0 {
+            anyhow::bail!("Token budget of {} leaves no room for text next to the summary", self.summarizer.token_budget);
+        }
+        
+        let chunks = self.summarizer.split(&text, budget)?;
+        let mut summary = String::new();
+        
+        for (index, chunk) in chunks.iter().enumerate() {
+            let name = if index == 0 { "initial".to_string() } else { format!("refine_{}", index) };
+            events.send(ChainEvent::StepStart { name: name.clone() });
+            let step_start = std::time::Instant::now();
+            
+            let (updated, tokens, cost) = if index == 0 {
+                self.summarizer.generate(&self.initial_prompt, &[("text", chunk.as_str())]).await?
+            } else {
+                self.summarizer
+                    .generate(&self.refine_prompt, &[("summary", summary.as_str()), ("text", chunk.as_str())])
+                    .await?
+            };
+            
+            spent.add(tokens, cost);
+            steps.push(finish_step(events, &name, format!("chunk {} of {}", index + 1, chunks.len()), updated.clone(), step_start));
+            summary = updated;
+        }
+        
+        Ok(ChainOutput {
+            result: serde_json::json!({
+                "output": summary,
+                "chunks": chunks.len(),
+            }),
+            metadata: ChainMetadata {
+                chain_name: self.name.clone(),
+                execution_time_ms: start.elapsed().as_millis() as u64,
+                steps,
+                total_tokens: spent.tokens,
+                total_cost: spent.cost,
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl Chain for RefineChain {
+    async fn execute(&self, input: ChainInput) -> Result<ChainOutput> {
+        self.run(input, &ChainEvents::disabled()).await
+    }
+    
+    async fn execute_streaming(&self, input: ChainInput, events: ChainEvents) -> Result<ChainOutput> {
+        self.run(input, &events).await
+    }
+    
+    fn input_keys(&self) -> Option<Vec<String>> {
+        Some(vec!["text".to_string()])
+    }
+    
+    fn structure(&self) -> ChainStructure {
+        ChainStructure::new(&self.name, "refine")
+            .with_node("initial", ChainStructure::new("initial", "summarize"))
+            .with_node("refine", ChainStructure::new("refine", "refine"))
+            .with_edge("initial", "refine", ["summary"])
+            .with_edge("refine", "refine", ["summary"])
+    }
+    
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 pub mod settings;
@@ -16,6 +17,8 @@ pub struct AppConfig {
     pub monitoring: MonitoringConfig,
     pub database: DatabaseConfig,
     pub plugins: PluginsConfig,
+    #[serde(default)]
+    pub budgets: BudgetConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,6 +32,12 @@ pub struct ServerConfig {
 pub struct LlmConfig {
     pub default_provider: String,
     pub providers: LlmProviders,
+    #[serde(default)]
+    pub routers: HashMap<String, RouterConfig>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub semantic_cache: SemanticCacheConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +45,14 @@ pub struct LlmProviders {
     pub openai: OpenAIConfig,
     pub ollama: OllamaConfig,
     pub huggingface: HuggingFaceConfig,
+    #[serde(default)]
+    pub anthropic: Option<AnthropicConfig>,
+    /// Scripted offline provider; also registered when `default_provider` is "mock"
+    #[serde(default)]
+    pub mock: Option<MockConfig>,
+    /// Servers speaking the OpenAI chat API, registered under their map key
+    #[serde(default)]
+    pub openai_compatible: HashMap<String, OpenAICompatibleConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,18 +62,255 @@ pub struct OpenAIConfig {
     pub temperature: f32,
     pub max_tokens: usize,
     pub top_p: f32,
+    #[serde(default)]
+    pub http: HttpConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OllamaConfig {
     pub base_url: String,
     pub default_model: String,
+    #[serde(default)]
+    pub tokenizer_path: Option<PathBuf>,
+    #[serde(default)]
+    pub http: HttpConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HuggingFaceConfig {
     pub api_key_env: String,
     pub default_model: String,
+    #[serde(default)]
+    pub tokenizer_path: Option<PathBuf>,
+    #[serde(default)]
+    pub http: HttpConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnthropicConfig {
+    pub api_key_env: String,
+    pub default_model: String,
+    /// Required by the Messages API on every request
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: usize,
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub tokenizer_path: Option<PathBuf>,
+    #[serde(default)]
+    pub http: HttpConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockConfig {
+    pub latency_ms: u64,
+    pub default_response: String,
+    /// Replies keyed by exact prompt
+    pub responses: HashMap<String, String>,
+    /// Regex rules, tried in order after the exact matches
+    pub patterns: Vec<MockPatternConfig>,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            latency_ms: 0,
+            default_response: "This is a mock response.".to_string(),
+            responses: HashMap::new(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockPatternConfig {
+    pub pattern: String,
+    pub response: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAICompatibleConfig {
+    pub base_url: String,
+    /// Unset for local servers that do not check keys
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    pub default_model: String,
+    #[serde(default = "default_temperature")]
+    pub temperature: f32,
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: usize,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub tokenizer_path: Option<PathBuf>,
+    #[serde(default)]
+    pub http: HttpConfig,
+}
+
+fn default_temperature() -> f32 {
+    0.7
+}
+
+fn default_max_tokens() -> usize {
+    2048
+}
+
+/// Timeout and retry policy for a provider's HTTP calls.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub timeout_seconds: u64,
+    pub connect_timeout_seconds: u64,
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub cassette: Option<CassetteConfig>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: 60,
+            connect_timeout_seconds: 10,
+            max_retries: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            cassette: None,
+        }
+    }
+}
+
+/// Records a provider's HTTP traffic to a file, or replays it from one.
+/// Providers pointing at the same `path` share the cassette.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMode {
+    /// Call the real endpoint and write every exchange to the cassette
+    Record,
+    /// Answer from the cassette only; unmatched requests fail
+    Replay,
+}
+
+/// A composite provider that spreads requests over several providers/models.
+/// Registered under its map key, so it can be used as `default_provider`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouterConfig {
+    #[serde(default)]
+    pub strategy: RoutingStrategy,
+    pub targets: Vec<RouteTargetConfig>,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingStrategy {
+    /// Always try targets in the listed order
+    #[default]
+    Fallback,
+    /// Rotate the first target on every request
+    RoundRobin,
+    /// Pick the first target at random, proportionally to `weight`
+    Weighted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteTargetConfig {
+    pub provider: String,
+    /// Model to request from this provider; its default model when unset
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default = "default_route_weight")]
+    pub weight: u32,
+}
+
+fn default_route_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures before a target is ejected
+    pub failure_threshold: u32,
+    /// How long an ejected target is skipped before it is tried again
+    pub cooldown_seconds: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown_seconds: 30,
+        }
+    }
+}
+
+/// Response cache wrapped around every provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub backend: CacheBackendKind,
+    /// Maximum entries kept by the in-memory backend
+    pub capacity: usize,
+    pub ttl_seconds: u64,
+    /// Skip the cache for sampled requests (temperature unset or above zero)
+    pub skip_when_sampling: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: CacheBackendKind::Memory,
+            capacity: 1000,
+            ttl_seconds: 3600,
+            skip_when_sampling: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheBackendKind {
+    /// LRU map, lost on restart
+    #[default]
+    Memory,
+    /// Table in the `database.url` SQLite database
+    Sqlite,
+}
+
+/// Cache that answers paraphrased prompts, stored in its own Qdrant collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SemanticCacheConfig {
+    pub enabled: bool,
+    pub collection_name: String,
+    /// Minimum cosine similarity for a stored prompt to count as a hit
+    pub similarity_threshold: f32,
+    pub ttl_seconds: u64,
+    /// Ids of the chains whose LLM calls go through the cache
+    pub chains: Vec<String>,
+}
+
+impl Default for SemanticCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            collection_name: "chainforge_semantic_cache".to_string(),
+            similarity_threshold: 0.95,
+            ttl_seconds: 86400,
+            chains: Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,6 +354,17 @@ pub struct ChainsConfig {
     pub max_iterations: usize,
     pub timeout_seconds: u64,
     pub enable_graph_view: bool,
+    /// Directory of YAML chain definitions registered at startup
+    #[serde(default)]
+    pub definitions_dir: Option<PathBuf>,
+    /// Tokens one call of the built-in summarize chains may use, prompt and
+    /// reply together; at most the default model's context window
+    #[serde(default = "default_summarize_token_budget")]
+    pub summarize_token_budget: usize,
+}
+
+fn default_summarize_token_budget() -> usize {
+    crate::chains::summarize::DEFAULT_TOKEN_BUDGET
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -117,6 +382,66 @@ pub struct MonitoringConfig {
     pub log_format: String,
     pub enable_token_tracking: bool,
     pub enable_cost_tracking: bool,
+    /// YAML or JSON price table; the built-in `pricing.yaml` when unset
+    #[serde(default)]
+    pub pricing_file: Option<PathBuf>,
+}
+
+/// Token and cost limits per tenant (identified by API key) and per chain.
+/// Spend is stored in `database.url`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetConfig {
+    pub enabled: bool,
+    /// Request header carrying the tenant's API key
+    pub api_key_header: String,
+    /// Reject callers without a known API key instead of treating them as anonymous
+    pub require_api_key: bool,
+    /// Limits for anonymous callers and tenants without limits of their own
+    pub default_limits: BudgetLimits,
+    /// Tenants by id
+    pub tenants: HashMap<String, TenantBudgetConfig>,
+    /// Limits shared by every tenant, by chain id
+    pub chains: HashMap<String, BudgetLimits>,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            api_key_header: "x-api-key".to_string(),
+            require_api_key: false,
+            default_limits: BudgetLimits::default(),
+            tenants: HashMap::new(),
+            chains: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantBudgetConfig {
+    /// Environment variable holding the tenant's API key
+    pub api_key_env: String,
+    #[serde(default)]
+    pub limits: Option<BudgetLimits>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetLimits {
+    pub daily: SpendLimit,
+    pub monthly: SpendLimit,
+}
+
+/// Soft limits log a warning; hard limits reject further requests until the
+/// period (UTC day or month) rolls over.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpendLimit {
+    pub soft_cost_usd: Option<f64>,
+    pub hard_cost_usd: Option<f64>,
+    pub soft_tokens: Option<u64>,
+    pub hard_tokens: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -154,4 +479,12 @@ impl AppConfig {
         std::env::var(&self.llm.providers.huggingface.api_key_env)
             .map_err(|_| anyhow::anyhow!("Hugging Face API key not found"))
     }
+    
+    pub fn get_anthropic_api_key(&self) -> Result<String> {
+        let anthropic = self.llm.providers.anthropic.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Anthropic provider is not configured"))?;
+        
+        std::env::var(&anthropic.api_key_env)
+            .map_err(|_| anyhow::anyhow!("Anthropic API key not found"))
+    }
 }
@@ -0,0 +1,373 @@
+use super::{ChatMessage, LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage, ToolCall};
+use super::error::LLMError;
+use super::http::HttpClient;
+use super::pricing::PricingTable;
+use super::streaming::{self, UpstreamEvent};
+use super::structured;
+use super::tokenizer::TokenCounter;
+use async_trait::async_trait;
+use anyhow::Result;
+use futures::StreamExt;
+use reqwest::header::{HeaderMap, HeaderValue};
+use crate::config::HttpConfig;
+use crate::memory::MessageRole;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const API_VERSION: &str = "2023-06-01";
+
+pub struct AnthropicProvider {
+    http: HttpClient,
+    api_key: String,
+    base_url: String,
+    default_model: String,
+    default_max_tokens: usize,
+    tokenizer: TokenCounter,
+    pricing: Arc<PricingTable>,
+}
+
+impl AnthropicProvider {
+    pub fn new(api_key: String, default_model: String, max_tokens: usize) -> Result<Self> {
+        Ok(Self {
+            http: HttpClient::new("anthropic", &HttpConfig::default(), auth_headers(&api_key))?,
+            api_key,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            default_model,
+            default_max_tokens: max_tokens,
+            tokenizer: TokenCounter::Estimate,
+            pricing: Arc::new(PricingTable::builtin()),
+        })
+    }
+    
+    pub fn with_http_config(mut self, config: &HttpConfig) -> Result<Self> {
+        self.http = HttpClient::new("anthropic", config, auth_headers(&self.api_key))?;
+        Ok(self)
+    }
+    
+    /// Prices responses with `pricing` instead of the built-in table.
+    pub fn with_pricing(mut self, pricing: Arc<PricingTable>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+    
+    /// Overrides the API endpoint, e.g. for a proxy or a local mock server.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+    
+    pub fn with_tokenizer(mut self, tokenizer: TokenCounter) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+    
+    fn build_payload(&self, request: &LLMRequest, model: &str, stream: bool) -> serde_json::Value {
+        // No native schema option; describe it in the system prompt
+        let request = &structured::with_text_protocol(request);
+        
+        // The Messages API takes the system prompt as a top-level field
+        let system: Vec<&str> = request
+            .system_message
+            .iter()
+            .map(|s| s.as_str())
+            .chain(
+                request
+                    .messages
+                    .iter()
+                    .filter(|m| m.role == MessageRole::System)
+                    .map(|m| m.content.as_str()),
+            )
+            .collect();
+        
+        let conversation: Vec<ChatMessage> = request
+            .chat_messages()
+            .into_iter()
+            .filter(|m| m.role != MessageRole::System)
+            .collect();
+        
+        let mut payload = serde_json::json!({
+            "model": model,
+            "messages": to_anthropic_messages(&conversation),
+            "max_tokens": request.max_tokens.unwrap_or(self.default_max_tokens),
+            "stream": stream,
+        });
+        
+        if !system.is_empty() {
+            payload["system"] = system.join("\n\n").into();
+        }
+        
+        if let Some(temperature) = request.temperature {
+            payload["temperature"] = temperature.into();
+        }
+        
+        if let Some(top_p) = request.top_p {
+            payload["top_p"] = top_p.into();
+        }
+        
+        if let Some(stop_sequences) = &request.stop_sequences {
+            payload["stop_sequences"] = serde_json::json!(stop_sequences);
+        }
+        
+        if !request.tools.is_empty() {
+            payload["tools"] = request.tools
+                .iter()
+                .map(|t| serde_json::json!({
+                    "name": t.name,
+                    "description": t.description,
+                    "input_schema": t.parameters,
+                }))
+                .collect();
+        }
+        
+        payload
+    }
+}
+
+#[async_trait]
+impl LLMProvider for AnthropicProvider {
+    async fn generate(&self, request: &LLMRequest) -> Result<LLMResponse> {
+        let start = std::time::Instant::now();
+        
+        let model = request.model.as_ref().unwrap_or(&self.default_model);
+        
+        let url = format!("{}/v1/messages", self.base_url);
+        
+        let payload = self.build_payload(request, model, false);
+        
+        let response = self.http.post_json(&url, &payload).await?;
+        
+        let blocks = response["content"].as_array().cloned().unwrap_or_default();
+        
+        let text: String = blocks
+            .iter()
+            .filter(|b| b["type"] == "text")
+            .filter_map(|b| b["text"].as_str())
+            .collect();
+        
+        let tool_calls: Vec<ToolCall> = blocks
+            .iter()
+            .filter(|b| b["type"] == "tool_use")
+            .map(|b| ToolCall {
+                id: b["id"].as_str().unwrap_or_default().to_string(),
+                name: b["name"].as_str().unwrap_or_default().to_string(),
+                arguments: b["input"].clone(),
+            })
+            .collect();
+        
+        let usage = &response["usage"];
+        
+        // `input_tokens` excludes prompt cache reads and writes
+        let tokens_used = match (usage["input_tokens"].as_u64(), usage["output_tokens"].as_u64()) {
+            (Some(input), Some(output)) => {
+                let cache_read = usage["cache_read_input_tokens"].as_u64().unwrap_or_default();
+                let cache_write = usage["cache_creation_input_tokens"].as_u64().unwrap_or_default();
+                
+                TokenUsage::new((input + cache_read + cache_write) as usize, output as usize)
+                    .with_cached_tokens(cache_read as usize)
+            }
+            _ => TokenUsage::new(self.count_prompt_tokens(request)?, self.count_tokens(&text)?),
+        };
+        
+        let model = response["model"].as_str().unwrap_or(model).to_string();
+        let cost = self.pricing.cost("anthropic", &model, &tokens_used);
+        
+        Ok(LLMResponse {
+            text,
+            model,
+            tokens_used,
+            finish_reason: finish_reason(response["stop_reason"].as_str()),
+            latency_ms: start.elapsed().as_millis() as u64,
+            tool_calls,
+            cost,
+            cached: false,
+        })
+    }
+    
+    async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream> {
+        let model = request.model.as_ref().unwrap_or(&self.default_model).clone();
+        
+        let url = format!("{}/v1/messages", self.base_url);
+        
+        let payload = self.build_payload(request, &model, true);
+        
+        let response = self.http.post_stream(&url, &payload).await?;
+        
+        let upstream = streaming::sse_data(response).scan(StreamState::default(), |state, data| {
+            futures::future::ready(Some(parse_stream_event(data, state)))
+        });
+        
+        let prompt_tokens = self.count_prompt_tokens(request)?;
+        
+        let tokenizer = self.tokenizer.clone();
+        Ok(streaming::finalize(
+            upstream,
+            "anthropic".to_string(),
+            model,
+            prompt_tokens,
+            move |text| tokenizer.count(text),
+            self.pricing.clone(),
+        ))
+    }
+    
+    fn count_tokens(&self, text: &str) -> Result<usize> {
+        Ok(self.tokenizer.count(text))
+    }
+    
+    fn estimate_cost(&self, request: &LLMRequest, usage: &TokenUsage) -> f64 {
+        self.pricing.cost("anthropic", request.model.as_ref().unwrap_or(&self.default_model), usage)
+    }
+}
+
+// The Messages API needs strictly alternating user/assistant turns, so
+// consecutive turns with the same role (e.g. several tool results) are merged
+fn to_anthropic_messages(messages: &[ChatMessage]) -> Vec<serde_json::Value> {
+    let mut turns: Vec<(&str, Vec<serde_json::Value>)> = Vec::new();
+    
+    for message in messages {
+        let (role, blocks) = match message.role {
+            MessageRole::Assistant => {
+                let mut blocks = Vec::new();
+                if !message.content.is_empty() {
+                    blocks.push(serde_json::json!({ "type": "text", "text": message.content }));
+                }
+                blocks.extend(message.tool_calls.iter().map(|c| serde_json::json!({
+                    "type": "tool_use",
+                    "id": c.id,
+                    "name": c.name,
+                    "input": c.arguments,
+                })));
+                ("assistant", blocks)
+            }
+            MessageRole::Tool => ("user", vec![serde_json::json!({
+                "type": "tool_result",
+                "tool_use_id": message.tool_call_id.clone().unwrap_or_default(),
+                "content": message.content,
+            })]),
+            MessageRole::User | MessageRole::System => {
+                ("user", vec![serde_json::json!({ "type": "text", "text": message.content })])
+            }
+        };
+        
+        match turns.last_mut() {
+            Some((last_role, last_blocks)) if *last_role == role => last_blocks.extend(blocks),
+            _ => turns.push((role, blocks)),
+        }
+    }
+    
+    turns
+        .into_iter()
+        .map(|(role, content)| serde_json::json!({ "role": role, "content": content }))
+        .collect()
+}
+
+// Carried between stream events: input tokens arrive in `message_start`,
+// output tokens in `message_delta`, and a tool call's input in pieces
+// between its block's start and stop
+#[derive(Default)]
+struct StreamState {
+    input_tokens: usize,
+    cache_read_tokens: usize,
+    // (id, name, input JSON so far) by content block index
+    tool_blocks: HashMap<u64, (String, String, String)>,
+}
+
+fn parse_stream_event(data: Result<String>, state: &mut StreamState) -> Result<UpstreamEvent> {
+    let event: serde_json::Value = serde_json::from_str(&data?)?;
+    let index = event["index"].as_u64().unwrap_or_default();
+    
+    match event["type"].as_str().unwrap_or_default() {
+        "message_start" => {
+            // As in `generate`, `input_tokens` excludes prompt cache reads and writes
+            let usage = &event["message"]["usage"];
+            let count = |key: &str| usage[key].as_u64().unwrap_or_default() as usize;
+            
+            state.cache_read_tokens = count("cache_read_input_tokens");
+            state.input_tokens = count("input_tokens") + state.cache_read_tokens + count("cache_creation_input_tokens");
+            Ok(UpstreamEvent::Skip)
+        }
+        "content_block_start" if event["content_block"]["type"] == "tool_use" => {
+            let block = &event["content_block"];
+            state.tool_blocks.insert(index, (
+                block["id"].as_str().unwrap_or_default().to_string(),
+                block["name"].as_str().unwrap_or_default().to_string(),
+                String::new(),
+            ));
+            Ok(UpstreamEvent::Skip)
+        }
+        "content_block_delta" if event["delta"]["type"] == "text_delta" => Ok(UpstreamEvent::Delta(
+            event["delta"]["text"].as_str().unwrap_or_default().to_string(),
+        )),
+        "content_block_delta" if event["delta"]["type"] == "input_json_delta" => {
+            if let Some((_, _, input)) = state.tool_blocks.get_mut(&index) {
+                input.push_str(event["delta"]["partial_json"].as_str().unwrap_or_default());
+            }
+            Ok(UpstreamEvent::Skip)
+        }
+        "content_block_stop" => match state.tool_blocks.remove(&index) {
+            Some((id, name, input)) => {
+                // A call without arguments may send no input; input that
+                // isn't valid JSON is kept as raw text
+                let arguments = if input.trim().is_empty() {
+                    serde_json::json!({})
+                } else {
+                    serde_json::from_str(&input).unwrap_or(serde_json::Value::String(input))
+                };
+                Ok(UpstreamEvent::ToolCall(ToolCall { id, name, arguments }))
+            }
+            None => Ok(UpstreamEvent::Skip),
+        },
+        "message_delta" => Ok(UpstreamEvent::Finish {
+            reason: Some(finish_reason(event["delta"]["stop_reason"].as_str())),
+            usage: event["usage"]["output_tokens"].as_u64().map(|output| {
+                TokenUsage::new(state.input_tokens, output as usize).with_cached_tokens(state.cache_read_tokens)
+            }),
+        }),
+        "error" => Err(stream_error(&event["error"]).into()),
+        // ping, text block start/stop and message_stop
+        _ => Ok(UpstreamEvent::Skip),
+    }
+}
+
+// Failures after the stream started arrive as an event; each error type is
+// classified like the HTTP status the API sends it with
+fn stream_error(error: &serde_json::Value) -> LLMError {
+    let status = match error["type"].as_str().unwrap_or_default() {
+        "invalid_request_error" => 400,
+        "authentication_error" => 401,
+        "permission_error" => 403,
+        "not_found_error" => 404,
+        "request_too_large" => 413,
+        "rate_limit_error" => 429,
+        "overloaded_error" => 529,
+        _ => 500,
+    };
+    
+    LLMError::from_status("anthropic", status, error["message"].as_str().unwrap_or("unknown error"), None)
+}
+
+// Map stop reasons onto the names the other providers use
+fn finish_reason(stop_reason: Option<&str>) -> String {
+    match stop_reason {
+        Some("end_turn") | Some("stop_sequence") | None => "stop",
+        Some("max_tokens") => "length",
+        Some("tool_use") => "tool_calls",
+        Some(other) => other,
+    }
+    .to_string()
+}
+
+fn auth_headers(api_key: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    
+    match HeaderValue::from_str(api_key) {
+        Ok(mut value) => {
+            value.set_sensitive(true);
+            headers.insert("x-api-key", value);
+        }
+        Err(_) => tracing::warn!("Anthropic API key is not a valid header value"),
+    }
+    
+    headers.insert("anthropic-version", HeaderValue::from_static(API_VERSION));
+    headers
+}
@@ -0,0 +1,269 @@
+use super::{streaming, LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage};
+use async_trait::async_trait;
+use anyhow::{Context, Result};
+use lru::LruCache;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::config::CacheConfig;
+use crate::monitoring::MetricsCollector;
+
+/// Storage for cached generations, keyed by `cache_key`.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<LLMResponse>>;
+    async fn put(&self, key: &str, response: &LLMResponse, ttl: Duration) -> Result<()>;
+}
+
+/// Bounded in-process cache; least recently used entries are evicted first.
+pub struct InMemoryCache {
+    entries: Mutex<LruCache<String, (LLMResponse, Instant)>>,
+}
+
+impl InMemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheBackend for InMemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<LLMResponse>> {
+        let mut entries = self.entries.lock();
+        
+        match entries.get(key) {
+            Some((response, expires_at)) if Instant::now() < *expires_at => Ok(Some(response.clone())),
+            Some(_) => {
+                entries.pop(key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+    
+    async fn put(&self, key: &str, response: &LLMResponse, ttl: Duration) -> Result<()> {
+        self.entries
+            .lock()
+            .put(key.to_string(), (response.clone(), Instant::now() + ttl));
+        Ok(())
+    }
+}
+
+/// Cache table in the application's SQLite database, shared across restarts.
+pub struct SqliteCache {
+    pool: SqlitePool,
+}
+
+impl SqliteCache {
+    pub async fn connect(url: &str, max_connections: u32) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)
+            .with_context(|| format!("Invalid SQLite URL: {}", url))?
+            .create_if_missing(true);
+        
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await
+            .context("Failed to open cache database")?;
+        
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS llm_cache (
+                key TEXT PRIMARY KEY,
+                response TEXT NOT NULL,
+                expires_at INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        
+        sqlx::query("DELETE FROM llm_cache WHERE expires_at <= ?")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&pool)
+            .await?;
+        
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl CacheBackend for SqliteCache {
+    async fn get(&self, key: &str) -> Result<Option<LLMResponse>> {
+        let row: Option<(String,)> = sqlx::query_as(
+            "SELECT response FROM llm_cache WHERE key = ? AND expires_at > ?",
+        )
+        .bind(key)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        match row {
+            Some((response,)) => Ok(Some(serde_json::from_str(&response)?)),
+            None => Ok(None),
+        }
+    }
+    
+    async fn put(&self, key: &str, response: &LLMResponse, ttl: Duration) -> Result<()> {
+        let expires_at = chrono::Utc::now().timestamp() + ttl.as_secs() as i64;
+        
+        sqlx::query("INSERT OR REPLACE INTO llm_cache (key, response, expires_at) VALUES (?, ?, ?)")
+            .bind(key)
+            .bind(serde_json::to_string(response)?)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+}
+
+/// Hex SHA-256 of everything in a request that affects the generation.
+///
+/// `serde_json` orders object keys, so the hash does not depend on field order.
+pub fn cache_key(provider: &str, request: &LLMRequest) -> String {
+    let canonical = serde_json::json!({
+        "provider": provider,
+        "model": request.model,
+        "messages": request.chat_messages(),
+        "temperature": request.temperature,
+        "max_tokens": request.max_tokens,
+        "top_p": request.top_p,
+        "stop_sequences": request.stop_sequences,
+        "tools": request.tools,
+        "response_format": request.response_format,
+    });
+    
+    format!("{:x}", Sha256::digest(canonical.to_string().as_bytes()))
+}
+
+/// Decorator that answers repeated requests from a `CacheBackend`.
+///
+/// Cache failures are logged and treated as misses so they never fail a request.
+pub struct CachedProvider {
+    name: String,
+    inner: Arc<dyn LLMProvider>,
+    backend: Arc<dyn CacheBackend>,
+    ttl: Duration,
+    skip_when_sampling: bool,
+    metrics: Option<Arc<MetricsCollector>>,
+}
+
+impl CachedProvider {
+    pub fn new(name: impl Into<String>, inner: Arc<dyn LLMProvider>, backend: Arc<dyn CacheBackend>) -> Self {
+        Self {
+            name: name.into(),
+            inner,
+            backend,
+            ttl: Duration::from_secs(CacheConfig::default().ttl_seconds),
+            skip_when_sampling: CacheConfig::default().skip_when_sampling,
+            metrics: None,
+        }
+    }
+    
+    pub fn with_config(mut self, config: &CacheConfig) -> Self {
+        self.ttl = Duration::from_secs(config.ttl_seconds);
+        self.skip_when_sampling = config.skip_when_sampling;
+        self
+    }
+    
+    pub fn with_metrics(mut self, metrics: Arc<MetricsCollector>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+    
+    // Providers default to a non-zero temperature, so an unset one counts as sampling
+    fn is_cacheable(&self, request: &LLMRequest) -> bool {
+        !self.skip_when_sampling || request.temperature.is_some_and(|t| t <= 0.0)
+    }
+    
+    async fn lookup(&self, key: &str) -> Option<LLMResponse> {
+        let cached = self.backend.get(key).await.unwrap_or_else(|e| {
+            tracing::warn!("LLM cache lookup failed for {}: {}", self.name, e);
+            None
+        });
+        
+        if let Some(metrics) = &self.metrics {
+            match cached {
+                Some(_) => metrics.record_cache_hit(),
+                None => metrics.record_cache_miss(),
+            }
+        }
+        
+        // A hit costs nothing, so budgets and totals must not charge it again
+        cached.map(|mut response| {
+            response.cost = 0.0;
+            response.cached = true;
+            response
+        })
+    }
+}
+
+#[async_trait]
+impl LLMProvider for CachedProvider {
+    async fn generate(&self, request: &LLMRequest) -> Result<LLMResponse> {
+        if !self.is_cacheable(request) {
+            return self.inner.generate(request).await;
+        }
+        
+        let start = Instant::now();
+        let key = cache_key(&self.name, request);
+        
+        if let Some(mut response) = self.lookup(&key).await {
+            response.latency_ms = start.elapsed().as_millis() as u64;
+            return Ok(response);
+        }
+        
+        let response = self.inner.generate(request).await?;
+        
+        if let Err(e) = self.backend.put(&key, &response, self.ttl).await {
+            tracing::warn!("LLM cache store failed for {}: {}", self.name, e);
+        }
+        
+        Ok(response)
+    }
+    
+    async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream> {
+        if !self.is_cacheable(request) {
+            return self.inner.stream_generate(request).await;
+        }
+        
+        let key = cache_key(&self.name, request);
+        
+        if let Some(response) = self.lookup(&key).await {
+            return Ok(streaming::replay(response));
+        }
+        
+        let start = Instant::now();
+        let backend = self.backend.clone();
+        let ttl = self.ttl;
+        
+        // Only streams that run to completion are stored
+        let stream = self.inner.stream_generate(request).await?;
+        Ok(streaming::on_complete(stream, start, move |response| {
+            tokio::spawn(async move {
+                if let Err(e) = backend.put(&key, &response, ttl).await {
+                    tracing::warn!("LLM cache store failed: {}", e);
+                }
+            });
+        }))
+    }
+    
+    fn count_tokens(&self, text: &str) -> Result<usize> {
+        self.inner.count_tokens(text)
+    }
+    
+    fn count_prompt_tokens(&self, request: &LLMRequest) -> Result<usize> {
+        self.inner.count_prompt_tokens(request)
+    }
+    
+    fn estimate_cost(&self, request: &LLMRequest, usage: &TokenUsage) -> f64 {
+        self.inner.estimate_cost(request, usage)
+    }
+}
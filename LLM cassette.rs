@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::config::{CassetteConfig, CassetteMode};
+
+lazy_static! {
+    // Every provider configured with the same path must share one cassette,
+    // otherwise recorders would overwrite each other's files
+    static ref CASSETTES: DashMap<PathBuf, Arc<Cassette>> = DashMap::new();
+}
+
+/// One recorded request/response exchange.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    pub request_body: serde_json::Value,
+    pub status: u16,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    pub response_body: String,
+}
+
+/// Request/response pairs captured from a real endpoint so providers can be
+/// exercised without network access.
+///
+/// Replayed requests are matched on method, URL and JSON body (key order and
+/// formatting don't matter). Each interaction answers at most once, in the
+/// order recorded, and a request with no match fails instead of going out.
+/// Headers are never recorded, so API keys stay out of the file.
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Cassette {
+    /// Returns the cassette for `config.path`, loading it on first use.
+    ///
+    /// Recording always starts from an empty cassette. A replay cassette that
+    /// can't be read is logged and left empty, so every request fails loudly.
+    pub fn shared(config: &CassetteConfig) -> Arc<Cassette> {
+        CASSETTES
+            .entry(config.path.clone())
+            .or_insert_with(|| {
+                let cassette = match config.mode {
+                    CassetteMode::Record => Self::new(config.path.clone(), CassetteMode::Record, Vec::new()),
+                    CassetteMode::Replay => Self::load(&config.path).unwrap_or_else(|e| {
+                        tracing::error!("Failed to load cassette {}: {:#}", config.path.display(), e);
+                        Self::new(config.path.clone(), CassetteMode::Replay, Vec::new())
+                    }),
+                };
+                
+                Arc::new(cassette)
+            })
+            .clone()
+    }
+    
+    pub fn new(path: impl Into<PathBuf>, mode: CassetteMode, interactions: Vec<Interaction>) -> Self {
+        Self {
+            path: path.into(),
+            mode,
+            interactions: Mutex::new(interactions),
+        }
+    }
+    
+    /// Opens a recorded cassette for replay.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+        let interactions: Vec<Interaction> = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid cassette {}", path.display()))?;
+        
+        Ok(Self::new(path, CassetteMode::Replay, interactions))
+    }
+    
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+    
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    
+    /// Recorded interactions, or the ones not yet replayed.
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.interactions.lock().clone()
+    }
+    
+    /// Answers a request from the cassette, consuming the matching interaction.
+    ///
+    /// The error on a miss is deliberately not an `LLMError`, so `HttpClient`
+    /// won't retry it and no fallback provider hides it.
+    pub fn replay(&self, method: &str, url: &str, body: &serde_json::Value) -> Result<reqwest::Response> {
+        let mut interactions = self.interactions.lock();
+        
+        let position = interactions
+            .iter()
+            .position(|i| i.method == method && i.url == url && i.request_body == *body);
+        
+        let Some(position) = position else {
+            anyhow::bail!(
+                "No interaction in cassette {} matches {} {} ({} left unplayed) with body: {}",
+                self.path.display(),
+                method,
+                url,
+                interactions.len(),
+                body
+            );
+        };
+        
+        to_response(interactions.remove(position))
+    }
+    
+    /// Saves the exchange and hands back an equivalent, fully buffered response.
+    ///
+    /// Streaming responses are buffered too, so while recording they arrive
+    /// in one piece.
+    pub async fn record(
+        &self,
+        method: &str,
+        url: &str,
+        body: &serde_json::Value,
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response> {
+        let status = response.status().as_u16();
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let response_body = response.text().await?;
+        
+        let interaction = Interaction {
+            method: method.to_string(),
+            url: url.to_string(),
+            request_body: body.clone(),
+            status,
+            content_type,
+            response_body,
+        };
+        
+        // Written after every exchange so an aborted run still leaves a usable file
+        let snapshot = {
+            let mut interactions = self.interactions.lock();
+            interactions.push(interaction.clone());
+            serde_json::to_string_pretty(&*interactions)?
+        };
+        
+        tokio::fs::write(&self.path, snapshot)
+            .await
+            .with_context(|| format!("Failed to write cassette {}", self.path.display()))?;
+        
+        to_response(interaction)
+    }
+}
+
+fn to_response(interaction: Interaction) -> Result<reqwest::Response> {
+    let mut builder = http::Response::builder().status(interaction.status);
+    
+    if let Some(content_type) = &interaction.content_type {
+        builder = builder.header(http::header::CONTENT_TYPE, content_type.as_str());
+    }
+    
+    Ok(builder.body(interaction.response_body)?.into())
+}
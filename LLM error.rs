@@ -0,0 +1,118 @@
+use std::time::Duration;
+use thiserror::Error;
+
+/// Failures from an LLM backend, classified so callers can decide whether
+/// to retry, fall back or report the problem to the client.
+///
+/// Providers return these inside `anyhow::Error`; use `LLMError::find` to
+/// recover them.
+#[derive(Debug, Clone, Error)]
+pub enum LLMError {
+    #[error("{provider} rate limit exceeded: {message}")]
+    RateLimited {
+        provider: String,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    
+    #[error("{provider} authentication failed: {message}")]
+    Auth { provider: String, message: String },
+    
+    #[error("{provider} context length exceeded: {message}")]
+    ContextLength { provider: String, message: String },
+    
+    #[error("{provider} request timed out after {timeout:?}")]
+    Timeout { provider: String, timeout: Duration },
+    
+    #[error("{provider} transient failure: {message}")]
+    Transient {
+        provider: String,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    
+    #[error("{provider} rejected the request ({status}): {message}")]
+    InvalidRequest {
+        provider: String,
+        status: u16,
+        message: String,
+    },
+    
+    /// The backend answered but its response couldn't be read. Never
+    /// retried: the generation already ran and would be paid for twice.
+    #[error("{provider} sent an unreadable response: {message}")]
+    InvalidResponse { provider: String, message: String },
+}
+
+impl LLMError {
+    /// Finds an `LLMError` anywhere in an error's context chain.
+    pub fn find(error: &anyhow::Error) -> Option<&LLMError> {
+        error.chain().find_map(|e| e.downcast_ref::<LLMError>())
+    }
+    
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            LLMError::RateLimited { .. } | LLMError::Timeout { .. } | LLMError::Transient { .. }
+        )
+    }
+    
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            LLMError::RateLimited { retry_after, .. } | LLMError::Transient { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+    
+    /// Classifies a non-success HTTP response from a provider.
+    pub fn from_status(provider: &str, status: u16, body: &str, retry_after: Option<Duration>) -> Self {
+        let provider = provider.to_string();
+        let message = body.to_string();
+        
+        match status {
+            401 | 403 => LLMError::Auth { provider, message },
+            429 => LLMError::RateLimited { provider, message, retry_after },
+            400 | 413 | 422 if is_context_length_message(body) => {
+                LLMError::ContextLength { provider, message }
+            }
+            408 | 500 | 502 | 503 | 504 | 529 => LLMError::Transient { provider, message, retry_after },
+            _ => LLMError::InvalidRequest { provider, status, message },
+        }
+    }
+    
+    /// Classifies a transport-level failure (connect, reset, timeout) or a
+    /// response body that couldn't be read.
+    pub fn from_reqwest(provider: &str, error: &reqwest::Error, timeout: Duration) -> Self {
+        if error.is_timeout() {
+            return LLMError::Timeout {
+                provider: provider.to_string(),
+                timeout,
+            };
+        }
+        
+        if let Some(status) = error.status() {
+            return Self::from_status(provider, status.as_u16(), &error.to_string(), None);
+        }
+        
+        if error.is_decode() || error.is_body() {
+            return LLMError::InvalidResponse {
+                provider: provider.to_string(),
+                message: error.to_string(),
+            };
+        }
+        
+        LLMError::Transient {
+            provider: provider.to_string(),
+            message: error.to_string(),
+            retry_after: None,
+        }
+    }
+}
+
+pub fn is_context_length_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    
+    ["context length", "context_length", "maximum context", "too many tokens", "prompt is too long"]
+        .iter()
+        .any(|needle| message.contains(needle))
+}
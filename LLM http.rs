@@ -0,0 +1,213 @@
+use super::cassette::Cassette;
+use super::error::LLMError;
+use crate::config::{CassetteMode, HttpConfig};
+use anyhow::{Context, Result};
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Turns a provider's non-success response (status, body, `Retry-After`)
+/// into an `LLMError`.
+pub type ErrorClassifier = fn(&str, u16, &str, Option<Duration>) -> LLMError;
+
+/// Pooled HTTP client shared by every call a provider makes, with the
+/// provider's timeout and retry policy applied.
+///
+/// With a cassette configured, requests are recorded to it or answered from it.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    provider: String,
+    config: HttpConfig,
+    cassette: Option<Arc<Cassette>>,
+    classify: ErrorClassifier,
+}
+
+impl HttpClient {
+    pub fn new(provider: impl Into<String>, config: &HttpConfig, headers: HeaderMap) -> Result<Self> {
+        let provider = provider.into();
+        
+        // No overall timeout here: it would cut off long streaming responses.
+        // Non-streaming calls get `config.timeout_seconds` per attempt instead.
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+            .pool_idle_timeout(Duration::from_secs(90))
+            .default_headers(headers)
+            .build()
+            .with_context(|| format!("Failed to build HTTP client for {}", provider))?;
+        
+        Ok(Self {
+            client,
+            provider,
+            config: config.clone(),
+            cassette: config.cassette.as_ref().map(Cassette::shared),
+            classify: LLMError::from_status,
+        })
+    }
+    
+    /// Classifies error responses with `classify` instead of by status alone,
+    /// for providers whose error bodies say more than the status does.
+    pub fn with_error_classifier(mut self, classify: ErrorClassifier) -> Self {
+        self.classify = classify;
+        self
+    }
+    
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+    
+    pub fn config(&self) -> &HttpConfig {
+        &self.config
+    }
+    
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.timeout_seconds)
+    }
+    
+    /// POSTs `payload` and decodes the JSON response, retrying transient failures.
+    pub async fn post_json(&self, url: &str, payload: &serde_json::Value) -> Result<serde_json::Value> {
+        self.retry(|| async move {
+            let response = self.send(url, payload, Some(self.timeout())).await?;
+            
+            let response = self.check_status(response).await?;
+            
+            // The status was fine, so a body that fails to decode is never retried
+            response.json::<serde_json::Value>().await.map_err(|e| {
+                LLMError::InvalidResponse {
+                    provider: self.provider.clone(),
+                    message: e.to_string(),
+                }
+                .into()
+            })
+        })
+        .await
+    }
+    
+    /// POSTs `payload` and returns the response once its status is known to be
+    /// successful, leaving the body to be streamed by the caller.
+    pub async fn post_stream(&self, url: &str, payload: &serde_json::Value) -> Result<reqwest::Response> {
+        self.retry(|| async move {
+            let response = self.send(url, payload, None).await?;
+            
+            self.check_status(response).await
+        })
+        .await
+    }
+    
+    /// Runs `operation` until it succeeds, fails with a non-retryable error or
+    /// runs out of attempts, sleeping with exponential backoff in between.
+    pub async fn retry<T, F, Fut>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        
+        loop {
+            let error = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            
+            let retry_after = match LLMError::find(&error) {
+                Some(llm_error) if llm_error.is_retryable() && attempt < self.config.max_retries => {
+                    llm_error.retry_after()
+                }
+                _ => return Err(error),
+            };
+            
+            let delay = self.backoff(attempt, retry_after);
+            tracing::warn!(
+                "{} request failed (attempt {}/{}), retrying in {:?}: {}",
+                self.provider,
+                attempt + 1,
+                self.config.max_retries + 1,
+                delay,
+                error
+            );
+            
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+    
+    /// Applies the request timeout to a call made outside `post_json`.
+    pub async fn with_timeout<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        match tokio::time::timeout(self.timeout(), future).await {
+            Ok(result) => result,
+            Err(_) => Err(LLMError::Timeout {
+                provider: self.provider.clone(),
+                timeout: self.timeout(),
+            }
+            .into()),
+        }
+    }
+    
+    // Error responses go through the cassette too, so replays fail the same way
+    async fn send(&self, url: &str, payload: &serde_json::Value, timeout: Option<Duration>) -> Result<reqwest::Response> {
+        if let Some(cassette) = self.cassette.as_ref().filter(|c| c.mode() == CassetteMode::Replay) {
+            return cassette.replay("POST", url, payload);
+        }
+        
+        let mut request = self.client.post(url).json(payload);
+        
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        
+        let response = request
+            .send()
+            .await
+            .map_err(|e| LLMError::from_reqwest(&self.provider, &e, self.timeout()))?;
+        
+        match &self.cassette {
+            Some(cassette) => cassette.record("POST", url, payload, response).await,
+            None => Ok(response),
+        }
+    }
+    
+    async fn check_status(&self, response: reqwest::Response) -> Result<reqwest::Response> {
+        let status = response.status();
+        
+        if status.is_success() {
+            return Ok(response);
+        }
+        
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        
+        let body = response.text().await.unwrap_or_default();
+        
+        Err((self.classify)(&self.provider, status.as_u16(), &body, retry_after).into())
+    }
+    
+    // Full exponential backoff with jitter; a server-provided Retry-After wins
+    fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let max = Duration::from_millis(self.config.max_backoff_ms);
+        
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(max);
+        }
+        
+        let base = self.config.initial_backoff_ms.saturating_mul(1u64 << attempt.min(16));
+        let capped = base.min(self.config.max_backoff_ms);
+        let jittered = rand::thread_rng().gen_range(capped / 2..=capped);
+        
+        Duration::from_millis(jittered)
+    }
+}
+
+/// Parses a `Retry-After` header given either in seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
+}
@@ -1,17 +1,60 @@
-use super::{LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage};
+use super::{ChatMessage, LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage, ToolDefinition};
+use super::http::HttpClient;
+use super::pricing::PricingTable;
+use super::streaming::{self, UpstreamEvent};
+use super::structured;
+use super::tokenizer::TokenCounter;
+use super::tools;
 use async_trait::async_trait;
 use anyhow::Result;
+use futures::StreamExt;
+use crate::config::HttpConfig;
+use crate::memory::MessageRole;
+use std::sync::Arc;
 
 pub struct HuggingFaceProvider {
+    http: HttpClient,
     api_key: String,
     default_model: String,
+    tokenizer: TokenCounter,
+    pricing: Arc<PricingTable>,
 }
 
 impl HuggingFaceProvider {
-    pub fn new(api_key: String, default_model: String) -> Self {
-        Self {
+    pub fn new(api_key: String, default_model: String) -> Result<Self> {
+        Ok(Self {
+            http: HttpClient::new("huggingface", &HttpConfig::default(), auth_headers(&api_key))?,
             api_key,
             default_model,
+            tokenizer: TokenCounter::Estimate,
+            pricing: Arc::new(PricingTable::builtin()),
+        })
+    }
+    
+    pub fn with_http_config(mut self, config: &HttpConfig) -> Result<Self> {
+        self.http = HttpClient::new("huggingface", config, auth_headers(&self.api_key))?;
+        Ok(self)
+    }
+    
+    /// Prices responses with `pricing` instead of the built-in table.
+    pub fn with_pricing(mut self, pricing: Arc<PricingTable>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+    
+    /// Counts tokens with a local `tokenizer.json` for the served model.
+    pub fn with_tokenizer(mut self, tokenizer: TokenCounter) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+    
+    /// The Inference API only takes raw text, so conversations are rendered
+    /// through a chat template; plain prompts are sent unchanged.
+    fn build_inputs(&self, request: &LLMRequest, model: &str) -> String {
+        if request.is_chat() {
+            render_chat_template(model, &request.chat_messages())
+        } else {
+            request.prompt.clone()
         }
     }
 }
@@ -23,26 +66,23 @@ impl LLMProvider for HuggingFaceProvider {
         
         let model = request.model.as_ref().unwrap_or(&self.default_model);
         
-        let client = reqwest::Client::new();
         let url = format!("https://api-inference.huggingface.co/models/{}", model);
         
+        // No native tools or schema API; describe both in the prompt and parse the reply
+        let text_request = structured::with_text_protocol(&tools::with_text_protocol(request));
+        let inputs = self.build_inputs(&text_request, model);
+        
         let payload = serde_json::json!({
-            "inputs": request.prompt,
+            "inputs": inputs,
             "parameters": {
                 "temperature": request.temperature.unwrap_or(0.7),
                 "max_new_tokens": request.max_tokens.unwrap_or(2048),
                 "top_p": request.top_p.unwrap_or(1.0),
+                "return_full_text": !text_request.is_chat(),
             }
         });
         
-        let response = client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&payload)
-            .send()
-            .await?
-            .json::<serde_json::Value>()
-            .await?;
+        let response = self.http.post_json(&url, &payload).await?;
         
         let text = if let Some(arr) = response.as_array() {
             arr.first()
@@ -56,24 +96,175 @@ impl LLMProvider for HuggingFaceProvider {
                 .to_string()
         };
         
+        let tool_calls = tools::parse_tool_calls(&text, &request.tools);
+        
+        // The Inference API does not report usage; plain prompts are echoed back
+        let completion = text.strip_prefix(inputs.as_str()).unwrap_or(&text);
+        let tokens_used = TokenUsage::new(self.count_tokens(&inputs)?, self.count_tokens(completion)?);
+        
         Ok(LLMResponse {
             text,
+            cost: self.pricing.cost("huggingface", &model, &tokens_used),
             model: model.clone(),
-            tokens_used: TokenUsage {
-                prompt_tokens: 0,
-                completion_tokens: 0,
-                total_tokens: 0,
-            },
-            finish_reason: "stop".to_string(),
+            tokens_used,
+            finish_reason: if tool_calls.is_empty() { "stop" } else { "tool_calls" }.to_string(),
             latency_ms: start.elapsed().as_millis() as u64,
+            tool_calls,
+            cached: false,
         })
     }
     
-    async fn stream_generate(&self, _request: &LLMRequest) -> Result<LLMStream> {
-        unimplemented!("Streaming not yet implemented for Hugging Face")
+    async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream> {
+        let model = request.model.as_ref().unwrap_or(&self.default_model).clone();
+        
+        let url = format!("https://api-inference.huggingface.co/models/{}", model);
+        
+        // Same text protocol as `generate`; tool calls are parsed once the text is complete
+        let text_request = structured::with_text_protocol(&tools::with_text_protocol(request));
+        let inputs = self.build_inputs(&text_request, &model);
+        
+        let payload = serde_json::json!({
+            "inputs": inputs,
+            "stream": true,
+            "parameters": {
+                "temperature": request.temperature.unwrap_or(0.7),
+                "max_new_tokens": request.max_tokens.unwrap_or(2048),
+                "top_p": request.top_p.unwrap_or(1.0),
+                "return_full_text": !text_request.is_chat(),
+            }
+        });
+        
+        let response = self.http.post_stream(&url, &payload).await?;
+        
+        let tools = request.tools.clone();
+        let upstream = streaming::sse_data(response)
+            .scan(String::new(), move |text, data| {
+                futures::future::ready(Some(parse_stream_frame(data, text, &tools)))
+            })
+            .flat_map(futures::stream::iter);
+        
+        let prompt_tokens = self.count_tokens(&inputs)?;
+        
+        let tokenizer = self.tokenizer.clone();
+        Ok(streaming::finalize(
+            upstream,
+            "huggingface".to_string(),
+            model,
+            prompt_tokens,
+            move |text| tokenizer.count(text),
+            self.pricing.clone(),
+        ))
     }
     
     fn count_tokens(&self, text: &str) -> Result<usize> {
-        Ok(text.split_whitespace().count())
+        Ok(self.tokenizer.count(text))
+    }
+    
+    fn estimate_cost(&self, request: &LLMRequest, usage: &TokenUsage) -> f64 {
+        self.pricing.cost("huggingface", request.model.as_ref().unwrap_or(&self.default_model), usage)
+    }
+}
+
+// Text generation streams SSE frames with one token each; the last frame
+// also carries `details` with the finish reason. `text` collects the reply
+// so the tool calls in it can be parsed at the end.
+fn parse_stream_frame(data: Result<String>, text: &mut String, tools: &[ToolDefinition]) -> Vec<Result<UpstreamEvent>> {
+    let frame: serde_json::Value = match data.and_then(|d| Ok(serde_json::from_str(&d)?)) {
+        Ok(frame) => frame,
+        Err(e) => return vec![Err(e)],
+    };
+    
+    if let Some(error) = frame["error"].as_str() {
+        return vec![Err(anyhow::anyhow!("Hugging Face stream error: {}", error))];
     }
+    
+    let mut events = Vec::new();
+    
+    if !frame["token"]["special"].as_bool().unwrap_or(false) {
+        let delta = frame["token"]["text"].as_str().unwrap_or_default().to_string();
+        text.push_str(&delta);
+        events.push(Ok(UpstreamEvent::Delta(delta)));
+    }
+    
+    if frame["details"].is_object() {
+        let tool_calls = tools::parse_tool_calls(text, tools);
+        
+        let reason = if tool_calls.is_empty() {
+            frame["details"]["finish_reason"].as_str().map(|r| r.to_string())
+        } else {
+            Some("tool_calls".to_string())
+        };
+        
+        events.extend(tool_calls.into_iter().map(|call| Ok(UpstreamEvent::ToolCall(call))));
+        events.push(Ok(UpstreamEvent::Finish { reason, usage: None }));
+    }
+    
+    events
+}
+
+fn auth_headers(api_key: &str) -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    
+    match reqwest::header::HeaderValue::from_str(&format!("Bearer {}", api_key)) {
+        Ok(mut value) => {
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+        Err(_) => tracing::warn!("Hugging Face API key is not a valid header value"),
+    }
+    
+    headers
+}
+
+fn render_chat_template(model: &str, messages: &[ChatMessage]) -> String {
+    let model = model.to_lowercase();
+    
+    if model.contains("llama-2") || model.contains("mistral") || model.contains("mixtral") {
+        render_inst_template(messages)
+    } else {
+        render_chatml_template(messages)
+    }
+}
+
+// Llama 2 / Mistral `[INST]` format; the system prompt is folded into the first user turn
+fn render_inst_template(messages: &[ChatMessage]) -> String {
+    let mut prompt = String::new();
+    let mut system = None;
+    
+    for message in messages {
+        match message.role {
+            MessageRole::System => system = Some(message.content.as_str()),
+            MessageRole::User | MessageRole::Tool => {
+                prompt.push_str("<s>[INST] ");
+                if let Some(system) = system.take() {
+                    prompt.push_str(&format!("<<SYS>>\n{}\n<</SYS>>\n\n", system));
+                }
+                if message.role == MessageRole::Tool {
+                    prompt.push_str("Tool result: ");
+                }
+                prompt.push_str(&message.content);
+                prompt.push_str(" [/INST]");
+            }
+            MessageRole::Assistant => {
+                prompt.push_str(&format!(" {} </s>", message.content));
+            }
+        }
+    }
+    
+    prompt
+}
+
+fn render_chatml_template(messages: &[ChatMessage]) -> String {
+    let mut prompt = String::new();
+    
+    for message in messages {
+        prompt.push_str(&format!(
+            "<|im_start|>{}\n{}<|im_end|>\n",
+            message.role.as_str(),
+            message.content
+        ));
+    }
+    
+    prompt.push_str("<|im_start|>assistant\n");
+    prompt
 }
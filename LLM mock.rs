@@ -0,0 +1,241 @@
+use super::{LLMProvider, LLMRequest, LLMResponse, LLMStream, StreamChunk, TokenUsage, ToolCall};
+use super::error::LLMError;
+use super::pricing::PricingTable;
+use super::tokenizer::TokenCounter;
+use async_trait::async_trait;
+use anyhow::Result;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::config::MockConfig;
+
+/// What the mock answers with.
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    Text(String),
+    ToolCalls(Vec<ToolCall>),
+    Error(LLMError),
+}
+
+impl MockReply {
+    pub fn text(text: impl Into<String>) -> Self {
+        MockReply::Text(text.into())
+    }
+}
+
+/// Deterministic provider for tests and offline development.
+///
+/// Replies are picked in this order: the next queued reply, a rule matching
+/// the prompt exactly, the first matching regex rule, then the default reply.
+/// The prompt is `LLMRequest.prompt`, or the last message for chat requests.
+/// Every request is recorded so tests can assert on what was sent.
+pub struct MockProvider {
+    model: String,
+    latency: Duration,
+    default_reply: MockReply,
+    exact: HashMap<String, MockReply>,
+    patterns: Vec<(Regex, MockReply)>,
+    queue: Mutex<VecDeque<MockReply>>,
+    requests: Mutex<Vec<LLMRequest>>,
+    pricing: Arc<PricingTable>,
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self {
+            model: "mock".to_string(),
+            latency: Duration::ZERO,
+            default_reply: MockReply::text("This is a mock response."),
+            exact: HashMap::new(),
+            patterns: Vec::new(),
+            queue: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(Vec::new()),
+            pricing: Arc::new(PricingTable::builtin()),
+        }
+    }
+    
+    /// Builds a mock from the `llm.providers.mock` section of the config.
+    pub fn from_config(config: &MockConfig) -> Result<Self> {
+        let mut mock = Self::new()
+            .with_latency(Duration::from_millis(config.latency_ms))
+            .with_default_reply(MockReply::text(config.default_response.clone()));
+        
+        for (prompt, response) in &config.responses {
+            mock = mock.on_prompt(prompt.clone(), MockReply::text(response.clone()));
+        }
+        
+        for rule in &config.patterns {
+            mock = mock.on_regex(Regex::new(&rule.pattern)?, MockReply::text(rule.response.clone()));
+        }
+        
+        Ok(mock)
+    }
+    
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+    
+    /// Delay before every reply (and before the first streamed chunk).
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+    
+    pub fn with_default_reply(mut self, reply: MockReply) -> Self {
+        self.default_reply = reply;
+        self
+    }
+    
+    /// Prices responses with `pricing` instead of the built-in table.
+    pub fn with_pricing(mut self, pricing: Arc<PricingTable>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+    
+    pub fn on_prompt(mut self, prompt: impl Into<String>, reply: MockReply) -> Self {
+        self.exact.insert(prompt.into(), reply);
+        self
+    }
+    
+    pub fn on_regex(mut self, pattern: Regex, reply: MockReply) -> Self {
+        self.patterns.push((pattern, reply));
+        self
+    }
+    
+    /// Queues replies returned, in order, before any rule is consulted.
+    pub fn with_sequence(self, replies: impl IntoIterator<Item = MockReply>) -> Self {
+        self.queue.lock().extend(replies);
+        self
+    }
+    
+    pub fn push_reply(&self, reply: MockReply) {
+        self.queue.lock().push_back(reply);
+    }
+    
+    /// Every request received so far, oldest first.
+    pub fn requests(&self) -> Vec<LLMRequest> {
+        self.requests.lock().clone()
+    }
+    
+    pub fn last_request(&self) -> Option<LLMRequest> {
+        self.requests.lock().last().cloned()
+    }
+    
+    pub fn clear_requests(&self) {
+        self.requests.lock().clear();
+    }
+    
+    async fn reply_for(&self, request: &LLMRequest) -> MockReply {
+        self.requests.lock().push(request.clone());
+        
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+        
+        if let Some(reply) = self.queue.lock().pop_front() {
+            return reply;
+        }
+        
+        let prompt = prompt_of(request);
+        
+        if let Some(reply) = self.exact.get(&prompt) {
+            return reply.clone();
+        }
+        
+        self.patterns
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(&prompt))
+            .map(|(_, reply)| reply.clone())
+            .unwrap_or_else(|| self.default_reply.clone())
+    }
+    
+    fn model_for(&self, request: &LLMRequest) -> String {
+        request.model.clone().unwrap_or_else(|| self.model.clone())
+    }
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl LLMProvider for MockProvider {
+    async fn generate(&self, request: &LLMRequest) -> Result<LLMResponse> {
+        let start = std::time::Instant::now();
+        
+        let (text, tool_calls) = match self.reply_for(request).await {
+            MockReply::Text(text) => (text, Vec::new()),
+            MockReply::ToolCalls(calls) => (String::new(), calls),
+            MockReply::Error(e) => return Err(e.into()),
+        };
+        
+        let model = self.model_for(request);
+        let tokens_used = TokenUsage::new(self.count_prompt_tokens(request)?, self.count_tokens(&text)?);
+        
+        Ok(LLMResponse {
+            cost: self.pricing.cost("mock", &model, &tokens_used),
+            tokens_used,
+            text,
+            model,
+            finish_reason: if tool_calls.is_empty() { "stop" } else { "tool_calls" }.to_string(),
+            latency_ms: start.elapsed().as_millis() as u64,
+            tool_calls,
+            cached: false,
+        })
+    }
+    
+    // Streams the reply word by word; tool calls arrive with the final chunk
+    async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream> {
+        let (text, tool_calls) = match self.reply_for(request).await {
+            MockReply::Text(text) => (text, Vec::new()),
+            MockReply::ToolCalls(calls) => (String::new(), calls),
+            MockReply::Error(e) => return Err(e.into()),
+        };
+        
+        let model = self.model_for(request);
+        let tokens_used = TokenUsage::new(self.count_prompt_tokens(request)?, self.count_tokens(&text)?);
+        
+        let done = StreamChunk::Done {
+            cost: self.pricing.cost("mock", &model, &tokens_used),
+            model,
+            tokens_used,
+            finish_reason: if tool_calls.is_empty() { "stop" } else { "tool_calls" }.to_string(),
+            tool_calls,
+            cached: false,
+        };
+        
+        let chunks: Vec<Result<StreamChunk>> = text
+            .split_inclusive(' ')
+            .map(|word| Ok(StreamChunk::Delta(word.to_string())))
+            .chain(std::iter::once(Ok(done)))
+            .collect();
+        
+        Ok(futures::stream::iter(chunks).boxed())
+    }
+    
+    fn count_tokens(&self, text: &str) -> Result<usize> {
+        Ok(TokenCounter::Estimate.count(text))
+    }
+    
+    fn estimate_cost(&self, request: &LLMRequest, usage: &TokenUsage) -> f64 {
+        self.pricing.cost("mock", &self.model_for(request), usage)
+    }
+}
+
+fn prompt_of(request: &LLMRequest) -> String {
+    if !request.prompt.is_empty() {
+        return request.prompt.clone();
+    }
+    
+    request
+        .messages
+        .last()
+        .map(|m| m.content.clone())
+        .unwrap_or_default()
+}
@@ -1,43 +1,95 @@
 use async_trait::async_trait;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use crate::memory::{Message, MessageRole};
+use structured::ResponseFormat;
 
+pub mod anthropic;
+pub mod cache;
+pub mod cassette;
+pub mod error;
+pub mod http;
+pub mod mock;
 pub mod openai;
+pub mod pricing;
 pub mod ollama;
 pub mod huggingface;
 pub mod provider;
+pub mod router;
+pub mod semantic_cache;
+pub mod streaming;
+pub mod structured;
+pub mod tokenizer;
+pub mod tools;
 
 #[async_trait]
 pub trait LLMProvider: Send + Sync {
     async fn generate(&self, request: &LLMRequest) -> Result<LLMResponse>;
     async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream>;
     fn count_tokens(&self, text: &str) -> Result<usize>;
+    
+    /// Tokens in every message of the conversation sent for `request`.
+    fn count_prompt_tokens(&self, request: &LLMRequest) -> Result<usize> {
+        request
+            .chat_messages()
+            .iter()
+            .map(|m| self.count_tokens(&m.content))
+            .sum()
+    }
+    
+    /// Cost in USD of `usage` for `request` at this provider's prices, for
+    /// billing a stream that ended before its final chunk.
+    fn estimate_cost(&self, _request: &LLMRequest, _usage: &TokenUsage) -> f64 {
+        0.0
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMRequest {
     pub prompt: String,
+    #[serde(default)]
+    pub messages: Vec<ChatMessage>,
     pub model: Option<String>,
     pub temperature: Option<f32>,
     pub max_tokens: Option<usize>,
     pub top_p: Option<f32>,
     pub stop_sequences: Option<Vec<String>>,
     pub system_message: Option<String>,
+    #[serde(default)]
+    pub tools: Vec<ToolDefinition>,
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
 }
 
 impl LLMRequest {
     pub fn new(prompt: impl Into<String>) -> Self {
         Self {
             prompt: prompt.into(),
+            messages: Vec::new(),
             model: None,
             temperature: None,
             max_tokens: None,
             top_p: None,
             stop_sequences: None,
             system_message: None,
+            tools: Vec::new(),
+            response_format: None,
+        }
+    }
+    
+    /// Creates a request from a conversation, with no trailing prompt.
+    pub fn from_messages(messages: Vec<ChatMessage>) -> Self {
+        Self {
+            messages,
+            ..Self::new(String::new())
         }
     }
     
+    pub fn with_messages(mut self, messages: Vec<ChatMessage>) -> Self {
+        self.messages = messages;
+        self
+    }
+    
     pub fn with_model(mut self, model: impl Into<String>) -> Self {
         self.model = Some(model.into());
         self
@@ -57,6 +109,136 @@ impl LLMRequest {
         self.system_message = Some(message.into());
         self
     }
+    
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools = tools;
+        self
+    }
+    
+    /// Asks for JSON matching a schema; see `structured::generate`.
+    pub fn with_response_format(mut self, format: ResponseFormat) -> Self {
+        self.response_format = Some(format);
+        self
+    }
+    
+    /// Full conversation sent to the model: the system message, the history
+    /// in `messages`, then `prompt` as the final user turn.
+    pub fn chat_messages(&self) -> Vec<ChatMessage> {
+        let mut messages = Vec::with_capacity(self.messages.len() + 2);
+        
+        if let Some(system_msg) = &self.system_message {
+            messages.push(ChatMessage::system(system_msg.clone()));
+        }
+        
+        messages.extend(self.messages.iter().cloned());
+        
+        if !self.prompt.is_empty() {
+            messages.push(ChatMessage::user(self.prompt.clone()));
+        }
+        
+        messages
+    }
+    
+    /// Whether the request is more than a single plain prompt.
+    pub fn is_chat(&self) -> bool {
+        self.system_message.is_some() || !self.messages.is_empty()
+    }
+}
+
+/// Generation settings a chain applies to every request it sends; unset
+/// fields keep the provider's defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationParams {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+}
+
+impl GenerationParams {
+    pub fn apply(&self, mut request: LLMRequest) -> LLMRequest {
+        request.model = self.model.clone().or(request.model);
+        request.temperature = self.temperature.or(request.temperature);
+        request.max_tokens = self.max_tokens.or(request.max_tokens);
+        request.top_p = self.top_p.or(request.top_p);
+        request.stop_sequences = self.stop_sequences.clone().or(request.stop_sequences);
+        request
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: MessageRole,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+impl ChatMessage {
+    pub fn new(role: MessageRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            tool_call_id: None,
+            tool_calls: Vec::new(),
+        }
+    }
+    
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(MessageRole::System, content)
+    }
+    
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(MessageRole::User, content)
+    }
+    
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(MessageRole::Assistant, content)
+    }
+    
+    /// Assistant turn that requested tool calls, to be followed by `tool` results.
+    pub fn assistant_tool_calls(content: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::new(MessageRole::Assistant, content)
+        }
+    }
+    
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new(MessageRole::Tool, content)
+        }
+    }
+}
+
+/// A function the model may call, described by a JSON Schema for its arguments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+impl From<&Message> for ChatMessage {
+    fn from(message: &Message) -> Self {
+        Self::new(message.role, message.content.clone())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,6 +248,21 @@ pub struct LLMResponse {
     pub tokens_used: TokenUsage,
     pub finish_reason: String,
     pub latency_ms: u64,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+    /// USD, at the prices of the provider's pricing table
+    #[serde(default)]
+    pub cost: f64,
+    /// Served from a response cache: nothing was generated, so `cost` is 0
+    #[serde(default)]
+    pub cached: bool,
+}
+
+impl LLMResponse {
+    /// Tokens to charge to budgets and metrics; none for a cached response.
+    pub fn billed_tokens(&self) -> usize {
+        if self.cached { 0 } else { self.tokens_used.total_tokens }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,21 +270,49 @@ pub struct TokenUsage {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
     pub total_tokens: usize,
+    /// Part of `prompt_tokens` read from the provider's prompt cache
+    #[serde(default)]
+    pub cached_tokens: usize,
 }
 
 impl TokenUsage {
-    pub fn estimate_cost(&self, model: &str) -> f64 {
-        // Simplified cost estimation (USD)
-        match model {
-            m if m.contains("gpt-4") => {
-                (self.prompt_tokens as f64 * 0.00003) + (self.completion_tokens as f64 * 0.00006)
-            }
-            m if m.contains("gpt-3.5") => {
-                (self.prompt_tokens as f64 * 0.0000015) + (self.completion_tokens as f64 * 0.000002)
-            }
-            _ => 0.0,
+    pub fn new(prompt_tokens: usize, completion_tokens: usize) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            cached_tokens: 0,
         }
     }
+    
+    /// Prompt tokens the provider served from its prompt cache.
+    pub fn with_cached_tokens(mut self, cached_tokens: usize) -> Self {
+        self.cached_tokens = cached_tokens;
+        self
+    }
+}
+
+impl std::ops::AddAssign<&TokenUsage> for TokenUsage {
+    fn add_assign(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cached_tokens += other.cached_tokens;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StreamChunk {
+    Delta(String),
+    Done {
+        model: String,
+        tokens_used: TokenUsage,
+        finish_reason: String,
+        /// Tool calls requested by the model, complete with their arguments
+        tool_calls: Vec<ToolCall>,
+        cost: f64,
+        cached: bool,
+    },
 }
 
-pub type LLMStream = Box<dyn futures::Stream<Item = Result<String>> + Send + Unpin>;
+pub type LLMStream = futures::stream::BoxStream<'static, Result<StreamChunk>>;
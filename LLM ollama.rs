@@ -1,18 +1,102 @@
-use super::{LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage};
+use super::{LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage, ToolCall};
+use super::http::HttpClient;
+use super::pricing::PricingTable;
+use super::streaming::{self, UpstreamEvent};
+use super::tokenizer::TokenCounter;
 use async_trait::async_trait;
 use anyhow::Result;
+use futures::StreamExt;
+use crate::config::HttpConfig;
+use std::sync::Arc;
 
 pub struct OllamaProvider {
+    http: HttpClient,
     base_url: String,
     default_model: String,
+    tokenizer: TokenCounter,
+    pricing: Arc<PricingTable>,
 }
 
 impl OllamaProvider {
-    pub fn new(base_url: String, default_model: String) -> Self {
-        Self {
+    pub fn new(base_url: String, default_model: String) -> Result<Self> {
+        Ok(Self {
+            http: HttpClient::new("ollama", &HttpConfig::default(), Default::default())?,
             base_url,
             default_model,
+            tokenizer: TokenCounter::Estimate,
+            pricing: Arc::new(PricingTable::builtin()),
+        })
+    }
+    
+    pub fn with_http_config(mut self, config: &HttpConfig) -> Result<Self> {
+        self.http = HttpClient::new("ollama", config, Default::default())?;
+        Ok(self)
+    }
+    
+    /// Prices responses with `pricing` instead of the built-in table.
+    pub fn with_pricing(mut self, pricing: Arc<PricingTable>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+    
+    /// Counts tokens with a local `tokenizer.json` for the served model.
+    pub fn with_tokenizer(mut self, tokenizer: TokenCounter) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+    
+    fn build_payload(&self, request: &LLMRequest, model: &str, stream: bool) -> serde_json::Value {
+        let messages: Vec<serde_json::Value> = request
+            .chat_messages()
+            .iter()
+            .map(|m| {
+                let mut message = serde_json::json!({
+                    "role": m.role.as_str(),
+                    "content": m.content,
+                });
+                
+                if !m.tool_calls.is_empty() {
+                    message["tool_calls"] = m.tool_calls
+                        .iter()
+                        .map(|c| serde_json::json!({
+                            "function": { "name": c.name, "arguments": c.arguments }
+                        }))
+                        .collect();
+                }
+                
+                message
+            })
+            .collect();
+        
+        let mut payload = serde_json::json!({
+            "model": model,
+            "messages": messages,
+            "stream": stream,
+            "options": {
+                "temperature": request.temperature.unwrap_or(0.7),
+                "num_predict": request.max_tokens.unwrap_or(2048),
+            }
+        });
+        
+        if !request.tools.is_empty() {
+            payload["tools"] = request.tools
+                .iter()
+                .map(|t| serde_json::json!({
+                    "type": "function",
+                    "function": {
+                        "name": t.name,
+                        "description": t.description,
+                        "parameters": t.parameters,
+                    }
+                }))
+                .collect();
         }
+        
+        if let Some(format) = &request.response_format {
+            payload["format"] = format.schema.clone();
+        }
+        
+        payload
     }
 }
 
@@ -23,50 +107,126 @@ impl LLMProvider for OllamaProvider {
         
         let model = request.model.as_ref().unwrap_or(&self.default_model);
         
-        let client = reqwest::Client::new();
-        let url = format!("{}/api/generate", self.base_url);
+        let url = format!("{}/api/chat", self.base_url);
         
-        let payload = serde_json::json!({
-            "model": model,
-            "prompt": request.prompt,
-            "stream": false,
-            "options": {
-                "temperature": request.temperature.unwrap_or(0.7),
-                "num_predict": request.max_tokens.unwrap_or(2048),
-            }
-        });
+        let payload = self.build_payload(request, model, false);
         
-        let response = client
-            .post(&url)
-            .json(&payload)
-            .send()
-            .await?
-            .json::<serde_json::Value>()
-            .await?;
+        let response = self.http.post_json(&url, &payload).await?;
         
-        let text = response["response"]
+        let text = response["message"]["content"]
             .as_str()
             .unwrap_or_default()
             .to_string();
         
+        // Older Ollama versions omit the eval counts
+        let tokens_used = match (response["prompt_eval_count"].as_u64(), response["eval_count"].as_u64()) {
+            (Some(prompt), Some(completion)) => TokenUsage::new(prompt as usize, completion as usize),
+            _ => TokenUsage::new(self.count_prompt_tokens(request)?, self.count_tokens(&text)?),
+        };
+        
+        let tool_calls: Vec<ToolCall> = response["message"]["tool_calls"]
+            .as_array()
+            .map(|calls| calls.iter().enumerate().map(|(i, call)| to_tool_call(i, call)).collect())
+            .unwrap_or_default();
+        
         Ok(LLMResponse {
             text,
+            cost: self.pricing.cost("ollama", &model, &tokens_used),
             model: model.clone(),
-            tokens_used: TokenUsage {
-                prompt_tokens: 0, // Ollama doesn't provide token counts
-                completion_tokens: 0,
-                total_tokens: 0,
-            },
-            finish_reason: "stop".to_string(),
+            tokens_used,
+            finish_reason: if tool_calls.is_empty() { "stop" } else { "tool_calls" }.to_string(),
             latency_ms: start.elapsed().as_millis() as u64,
+            tool_calls,
+            cached: false,
         })
     }
     
-    async fn stream_generate(&self, _request: &LLMRequest) -> Result<LLMStream> {
-        unimplemented!("Streaming not yet implemented for Ollama")
+    async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream> {
+        let model = request.model.as_ref().unwrap_or(&self.default_model).clone();
+        
+        let url = format!("{}/api/chat", self.base_url);
+        
+        let payload = self.build_payload(request, &model, true);
+        
+        let response = self.http.post_stream(&url, &payload).await?;
+        
+        let upstream = streaming::lines(response)
+            .scan(0, |tool_calls, line| futures::future::ready(Some(parse_stream_line(line, tool_calls))))
+            .flat_map(futures::stream::iter);
+        
+        let prompt_tokens = self.count_prompt_tokens(request)?;
+        
+        let tokenizer = self.tokenizer.clone();
+        Ok(streaming::finalize(
+            upstream,
+            "ollama".to_string(),
+            model,
+            prompt_tokens,
+            move |text| tokenizer.count(text),
+            self.pricing.clone(),
+        ))
     }
     
     fn count_tokens(&self, text: &str) -> Result<usize> {
-        Ok(text.split_whitespace().count())
+        Ok(self.tokenizer.count(text))
+    }
+    
+    fn estimate_cost(&self, request: &LLMRequest, usage: &TokenUsage) -> f64 {
+        self.pricing.cost("ollama", request.model.as_ref().unwrap_or(&self.default_model), usage)
+    }
+}
+
+// Ollama does not assign ids to tool calls
+fn to_tool_call(index: usize, call: &serde_json::Value) -> ToolCall {
+    ToolCall {
+        id: format!("call_{}", index),
+        name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+        arguments: call["function"]["arguments"].clone(),
     }
 }
+
+// Ollama streams one JSON object per line; the last one has `done: true`.
+// Tool calls arrive whole, usually in a frame before the last; `tool_calls`
+// counts the ones seen so far.
+fn parse_stream_line(line: Result<String>, tool_calls: &mut usize) -> Vec<Result<UpstreamEvent>> {
+    let line = match line {
+        Ok(line) if line.trim().is_empty() => return Vec::new(),
+        Ok(line) => line,
+        Err(e) => return vec![Err(e)],
+    };
+    
+    let frame: serde_json::Value = match serde_json::from_str(&line) {
+        Ok(frame) => frame,
+        Err(e) => return vec![Err(e.into())],
+    };
+    
+    if let Some(error) = frame["error"].as_str() {
+        return vec![Err(anyhow::anyhow!("Ollama stream error: {}", error))];
+    }
+    
+    let mut events = vec![Ok(UpstreamEvent::Delta(
+        frame["message"]["content"].as_str().unwrap_or_default().to_string(),
+    ))];
+    
+    for call in frame["message"]["tool_calls"].as_array().into_iter().flatten() {
+        events.push(Ok(UpstreamEvent::ToolCall(to_tool_call(*tool_calls, call))));
+        *tool_calls += 1;
+    }
+    
+    if frame["done"].as_bool().unwrap_or(false) {
+        let usage = match (frame["prompt_eval_count"].as_u64(), frame["eval_count"].as_u64()) {
+            (Some(prompt), Some(completion)) => Some(TokenUsage::new(prompt as usize, completion as usize)),
+            _ => None,
+        };
+        
+        let reason = if *tool_calls > 0 {
+            Some("tool_calls".to_string())
+        } else {
+            frame["done_reason"].as_str().map(|r| r.to_string())
+        };
+        
+        events.push(Ok(UpstreamEvent::Finish { reason, usage }));
+    }
+    
+    events
+}
@@ -1,26 +1,150 @@
-use super::{LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage};
+use super::{ChatMessage, LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage, ToolCall, ToolDefinition};
+use super::error::{self, LLMError};
+use super::http::HttpClient;
+use super::pricing::PricingTable;
+use super::streaming::{self, UpstreamEvent};
+use super::tokenizer::TokenCounter;
 use async_trait::async_trait;
 use anyhow::{Result, Context};
-use async_openai::{Client, types::{CreateChatCompletionRequestArgs, ChatCompletionRequestMessage, Role}};
+use dashmap::DashMap;
+use async_openai::types::{
+    ChatCompletionRequestMessage, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
+    CreateChatCompletionResponse, CreateChatCompletionStreamResponse, FinishReason, Role,
+};
+use futures::StreamExt;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use crate::config::HttpConfig;
+use crate::memory::MessageRole;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
 
+const DEFAULT_API_BASE: &str = "https://api.openai.com/v1";
+
+// async-openai is used for its request/response types only; calls go
+// through `HttpClient` so they share its retries, timeouts and cassettes
 pub struct OpenAIProvider {
-    client: Client<async_openai::config::OpenAIConfig>,
+    name: String,
+    http: HttpClient,
+    api_key: String,
+    api_base: String,
+    headers: HeaderMap,
     default_model: String,
     default_temperature: f32,
     default_max_tokens: usize,
+    // Set for servers whose models tiktoken doesn't know
+    tokenizer: Option<TokenCounter>,
+    // Encodings differ between models, so each gets its own counter
+    model_tokenizers: DashMap<String, TokenCounter>,
+    pricing: Arc<PricingTable>,
 }
 
 impl OpenAIProvider {
-    pub fn new(api_key: String, default_model: String, temperature: f32, max_tokens: usize) -> Self {
-        let config = async_openai::config::OpenAIConfig::new().with_api_key(api_key);
-        let client = Client::with_config(config);
-        
-        Self {
-            client,
+    pub fn new(api_key: String, default_model: String, temperature: f32, max_tokens: usize) -> Result<Self> {
+        Ok(Self {
+            name: "openai".to_string(),
+            http: HttpClient::new("openai", &HttpConfig::default(), auth_headers(&api_key, &HeaderMap::new()))?
+                .with_error_classifier(classify_error),
+            api_key,
+            api_base: DEFAULT_API_BASE.to_string(),
+            headers: HeaderMap::new(),
+            tokenizer: None,
+            model_tokenizers: DashMap::new(),
+            pricing: Arc::new(PricingTable::builtin()),
             default_model,
             default_temperature: temperature,
             default_max_tokens: max_tokens,
+        })
+    }
+    
+    pub fn with_http_config(mut self, config: &HttpConfig) -> Result<Self> {
+        self.http = HttpClient::new(self.name.clone(), config, auth_headers(&self.api_key, &self.headers))?
+            .with_error_classifier(classify_error);
+        Ok(self)
+    }
+    
+    /// Prices responses with `pricing` instead of the built-in table.
+    pub fn with_pricing(mut self, pricing: Arc<PricingTable>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+    
+    /// Name used in logs and errors; set for OpenAI-compatible servers.
+    pub fn with_name(mut self, name: impl Into<String>) -> Result<Self> {
+        self.name = name.into();
+        let config = self.http.config().clone();
+        self.with_http_config(&config)
+    }
+    
+    /// Points the provider at an OpenAI-compatible server (vLLM, llama.cpp, LM Studio, ...).
+    pub fn with_api_base(mut self, base_url: impl Into<String>) -> Self {
+        self.api_base = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+    
+    /// Extra headers sent with every request.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Result<Self> {
+        self.headers = headers;
+        let config = self.http.config().clone();
+        self.with_http_config(&config)
+    }
+    
+    /// Counts tokens with `tokenizer` for every model instead of the
+    /// model's tiktoken encoding.
+    pub fn with_tokenizer(mut self, tokenizer: TokenCounter) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+    
+    fn tokenizer(&self, model: &str) -> TokenCounter {
+        if let Some(tokenizer) = &self.tokenizer {
+            return tokenizer.clone();
+        }
+        
+        self.model_tokenizers
+            .entry(model.to_string())
+            .or_insert_with(|| TokenCounter::for_openai_model(model))
+            .clone()
+    }
+    
+    fn build_request(&self, request: &LLMRequest, model: &str, stream: bool) -> Result<serde_json::Value> {
+        let messages: Vec<ChatCompletionRequestMessage> = request
+            .chat_messages()
+            .iter()
+            .map(to_openai_message)
+            .collect();
+        
+        let mut args = CreateChatCompletionRequestArgs::default();
+        args.model(model)
+            .messages(messages)
+            .temperature(request.temperature.unwrap_or(self.default_temperature))
+            .max_tokens(request.max_tokens.unwrap_or(self.default_max_tokens) as u16);
+        
+        // OpenAI rejects an empty `tools` array, so only set it when needed
+        if !request.tools.is_empty() {
+            args.tools(request.tools.iter().map(to_openai_tool).collect::<Vec<_>>());
+        }
+        
+        if stream {
+            args.stream(true);
+        }
+        
+        let chat_request: CreateChatCompletionRequest = args.build().context("Failed to build chat completion request")?;
+        let mut payload = serde_json::to_value(chat_request)?;
+        
+        // async-openai 0.20 predates `json_schema` response formats
+        if let Some(format) = &request.response_format {
+            payload["response_format"] = serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": format.name,
+                    "schema": format.schema,
+                    "strict": format.strict,
+                }
+            });
         }
+        
+        Ok(payload)
     }
 }
 
@@ -31,39 +155,22 @@ impl LLMProvider for OpenAIProvider {
         
         let model = request.model.as_ref().unwrap_or(&self.default_model).clone();
         
-        let mut messages = vec![];
+        let url = format!("{}/chat/completions", self.api_base);
         
-        if let Some(system_msg) = &request.system_message {
-            messages.push(ChatCompletionRequestMessage::System(
-                async_openai::types::ChatCompletionRequestSystemMessage {
-                    content: async_openai::types::ChatCompletionRequestSystemMessageContent::Text(system_msg.clone()),
-                    role: Role::System,
-                    name: None,
-                }
-            ));
-        }
+        let payload = self.build_request(request, &model, false)?;
         
-        messages.push(ChatCompletionRequestMessage::User(
-            async_openai::types::ChatCompletionRequestUserMessage {
-                content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(request.prompt.clone()),
-                role: Role::User,
-                name: None,
-            }
-        ));
+        let body = self.http
+            .post_json(&url, &payload)
+            .await
+            .with_context(|| format!("Failed to call {} API", self.name))?;
         
-        let chat_request = CreateChatCompletionRequestArgs::default()
-            .model(&model)
-            .messages(messages)
-            .temperature(request.temperature.unwrap_or(self.default_temperature))
-            .max_tokens(request.max_tokens.unwrap_or(self.default_max_tokens) as u16)
-            .build()
-            .context("Failed to build chat completion request")?;
+        // async-openai's usage type doesn't carry the prompt cache breakdown
+        let cached_tokens = body["usage"]["prompt_tokens_details"]["cached_tokens"]
+            .as_u64()
+            .unwrap_or_default() as usize;
         
-        let response = self.client
-            .chat()
-            .create(chat_request)
-            .await
-            .context("Failed to call OpenAI API")?;
+        let response: CreateChatCompletionResponse = serde_json::from_value(body)
+            .with_context(|| format!("Unexpected {} response", self.name))?;
         
         let text = response
             .choices
@@ -71,32 +178,297 @@ impl LLMProvider for OpenAIProvider {
             .and_then(|c| c.message.content.clone())
             .unwrap_or_default();
         
-        let usage = response.usage.unwrap_or_default();
+        let tool_calls = response
+            .choices
+            .first()
+            .and_then(|c| c.message.tool_calls.as_ref())
+            .map(|calls| calls.iter().map(from_openai_tool_call).collect())
+            .unwrap_or_default();
+        
+        // OpenAI-compatible servers do not always report usage
+        let tokens_used = match response.usage {
+            Some(usage) => TokenUsage::new(usage.prompt_tokens as usize, usage.completion_tokens as usize)
+                .with_cached_tokens(cached_tokens),
+            None => TokenUsage::new(self.count_prompt_tokens(request)?, self.tokenizer(&model).count(&text)),
+        };
         
         Ok(LLMResponse {
             text,
+            cost: self.pricing.cost(&self.name, &model, &tokens_used),
             model,
-            tokens_used: TokenUsage {
-                prompt_tokens: usage.prompt_tokens as usize,
-                completion_tokens: usage.completion_tokens as usize,
-                total_tokens: usage.total_tokens as usize,
-            },
+            tokens_used,
             finish_reason: response
                 .choices
                 .first()
-                .map(|c| format!("{:?}", c.finish_reason))
+                .and_then(|c| c.finish_reason.as_ref())
+                .map(|reason| finish_reason(reason).to_string())
                 .unwrap_or_default(),
             latency_ms: start.elapsed().as_millis() as u64,
+            tool_calls,
+            cached: false,
         })
     }
     
-    async fn stream_generate(&self, _request: &LLMRequest) -> Result<LLMStream> {
-        // Streaming implementation placeholder
-        unimplemented!("Streaming not yet implemented for OpenAI")
+    async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream> {
+        let model = request.model.as_ref().unwrap_or(&self.default_model).clone();
+        
+        let url = format!("{}/chat/completions", self.api_base);
+        
+        let payload = self.build_request(request, &model, true)?;
+        
+        let prompt_tokens = self.count_prompt_tokens(request)?;
+        
+        let response = self.http
+            .post_stream(&url, &payload)
+            .await
+            .with_context(|| format!("Failed to start {} stream", self.name))?;
+        
+        let name = self.name.clone();
+        let upstream = streaming::sse_data(response)
+            .scan(StreamState::default(), move |state, data| {
+                futures::future::ready(Some(parse_stream_chunk(&name, data, state)))
+            })
+            .flat_map(futures::stream::iter);
+        
+        let tokenizer = self.tokenizer(&model);
+        Ok(streaming::finalize(
+            upstream,
+            self.name.clone(),
+            model,
+            prompt_tokens,
+            move |text| tokenizer.count(text),
+            self.pricing.clone(),
+        ))
     }
     
     fn count_tokens(&self, text: &str) -> Result<usize> {
-        // Simplified token counting (approximate)
-        Ok(text.split_whitespace().count())
+        Ok(self.tokenizer(&self.default_model).count(text))
+    }
+    
+    // Counted with the encoding of the model the request will actually use
+    fn count_prompt_tokens(&self, request: &LLMRequest) -> Result<usize> {
+        let tokenizer = self.tokenizer(request.model.as_ref().unwrap_or(&self.default_model));
+        
+        Ok(request
+            .chat_messages()
+            .iter()
+            .map(|m| tokenizer.count(&m.content))
+            .sum())
+    }
+    
+    fn estimate_cost(&self, request: &LLMRequest, usage: &TokenUsage) -> f64 {
+        self.pricing.cost(&self.name, request.model.as_ref().unwrap_or(&self.default_model), usage)
+    }
+}
+
+fn to_openai_message(message: &ChatMessage) -> ChatCompletionRequestMessage {
+    match message.role {
+        MessageRole::System => ChatCompletionRequestMessage::System(
+            async_openai::types::ChatCompletionRequestSystemMessage {
+                content: async_openai::types::ChatCompletionRequestSystemMessageContent::Text(message.content.clone()),
+                role: Role::System,
+                name: None,
+            }
+        ),
+        MessageRole::User => ChatCompletionRequestMessage::User(
+            async_openai::types::ChatCompletionRequestUserMessage {
+                content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(message.content.clone()),
+                role: Role::User,
+                name: None,
+            }
+        ),
+        MessageRole::Assistant => ChatCompletionRequestMessage::Assistant(
+            async_openai::types::ChatCompletionRequestAssistantMessage {
+                content: Some(message.content.clone()).filter(|c| !c.is_empty()),
+                role: Role::Assistant,
+                tool_calls: Some(message.tool_calls.iter().map(to_openai_tool_call).collect::<Vec<_>>())
+                    .filter(|calls| !calls.is_empty()),
+                ..Default::default()
+            }
+        ),
+        MessageRole::Tool => ChatCompletionRequestMessage::Tool(
+            async_openai::types::ChatCompletionRequestToolMessage {
+                content: message.content.clone(),
+                role: Role::Tool,
+                tool_call_id: message.tool_call_id.clone().unwrap_or_default(),
+            }
+        ),
+    }
+}
+
+fn to_openai_tool(tool: &ToolDefinition) -> async_openai::types::ChatCompletionTool {
+    async_openai::types::ChatCompletionTool {
+        r#type: async_openai::types::ChatCompletionToolType::Function,
+        function: async_openai::types::FunctionObject {
+            name: tool.name.clone(),
+            description: Some(tool.description.clone()),
+            parameters: Some(tool.parameters.clone()),
+        },
+    }
+}
+
+fn to_openai_tool_call(call: &ToolCall) -> async_openai::types::ChatCompletionMessageToolCall {
+    async_openai::types::ChatCompletionMessageToolCall {
+        id: call.id.clone(),
+        r#type: async_openai::types::ChatCompletionToolType::Function,
+        function: async_openai::types::FunctionCall {
+            name: call.name.clone(),
+            arguments: call.arguments.to_string(),
+        },
+    }
+}
+
+fn from_openai_tool_call(call: &async_openai::types::ChatCompletionMessageToolCall) -> ToolCall {
+    ToolCall {
+        id: call.id.clone(),
+        name: call.function.name.clone(),
+        arguments: parse_arguments(&call.function.arguments),
+    }
+}
+
+// Arguments arrive as a JSON string; keep the raw text if the model produced invalid JSON
+fn parse_arguments(arguments: &str) -> serde_json::Value {
+    if arguments.trim().is_empty() {
+        return serde_json::json!({});
+    }
+    
+    serde_json::from_str(arguments).unwrap_or_else(|_| serde_json::Value::String(arguments.to_string()))
+}
+
+// Streamed tool calls arrive in fragments keyed by index: the first names
+// the call, later ones append to its arguments
+#[derive(Default)]
+struct StreamState {
+    tool_calls: BTreeMap<i64, (String, String, String)>,
+}
+
+impl StreamState {
+    fn take_tool_calls(&mut self) -> Vec<Result<UpstreamEvent>> {
+        std::mem::take(&mut self.tool_calls)
+            .into_values()
+            .map(|(id, name, arguments)| {
+                Ok(UpstreamEvent::ToolCall(ToolCall {
+                    id,
+                    name,
+                    arguments: parse_arguments(&arguments),
+                }))
+            })
+            .collect()
+    }
+}
+
+fn parse_stream_chunk(provider: &str, data: Result<String>, state: &mut StreamState) -> Vec<Result<UpstreamEvent>> {
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return vec![Err(e)],
+    };
+    
+    // The stream ends with a literal `[DONE]` sentinel
+    if data == "[DONE]" {
+        return state.take_tool_calls();
     }
+    
+    // Failures after the stream started arrive as an error event
+    if data.contains("\"error\"") {
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(&data) {
+            if value.get("error").is_some() {
+                return vec![Err(classify_error(provider, 500, &data, None).into())];
+            }
+        }
+    }
+    
+    let chunk: CreateChatCompletionStreamResponse = match serde_json::from_str(&data) {
+        Ok(chunk) => chunk,
+        Err(e) => return vec![Err(anyhow::anyhow!("{} stream error: {}: {}", provider, e, data))],
+    };
+    
+    let Some(choice) = chunk.choices.into_iter().next() else {
+        return Vec::new();
+    };
+    
+    for call in choice.delta.tool_calls.unwrap_or_default() {
+        let (id, name, arguments) = state.tool_calls.entry(call.index as i64).or_default();
+        
+        if let Some(call_id) = call.id {
+            *id = call_id;
+        }
+        
+        if let Some(function) = call.function {
+            if let Some(function_name) = function.name {
+                *name = function_name;
+            }
+            arguments.push_str(&function.arguments.unwrap_or_default());
+        }
+    }
+    
+    // The chunk carrying the finish reason may still carry text
+    let mut events = vec![Ok(UpstreamEvent::Delta(choice.delta.content.unwrap_or_default()))];
+    
+    if let Some(reason) = choice.finish_reason {
+        events.extend(state.take_tool_calls());
+        events.push(Ok(UpstreamEvent::Finish {
+            reason: Some(finish_reason(&reason).to_string()),
+            usage: None,
+        }));
+    }
+    
+    events
+}
+
+// The API's own spelling, which `Debug` would turn into "ToolCalls"
+fn finish_reason(reason: &FinishReason) -> &'static str {
+    match reason {
+        FinishReason::Stop => "stop",
+        FinishReason::Length => "length",
+        FinishReason::ToolCalls => "tool_calls",
+        FinishReason::ContentFilter => "content_filter",
+        FinishReason::FunctionCall => "function_call",
+    }
+}
+
+// OpenAI's error codes say more than the status does: a 429 is either a
+// rate limit worth retrying or an exhausted quota that isn't
+fn classify_error(provider: &str, status: u16, body: &str, retry_after: Option<Duration>) -> LLMError {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+        return LLMError::from_status(provider, status, body, retry_after);
+    };
+    
+    let api = &value["error"];
+    let code = api["code"].as_str().unwrap_or_default();
+    let kind = api["type"].as_str().unwrap_or_default();
+    let message = api["message"].as_str().unwrap_or(body).to_string();
+    let provider = provider.to_string();
+    
+    if code.contains("insufficient_quota") || kind.contains("insufficient_quota") {
+        LLMError::InvalidRequest { provider, status, message }
+    } else if code.contains("rate_limit") || kind.contains("rate_limit") {
+        LLMError::RateLimited { provider, message, retry_after }
+    } else if code.contains("invalid_api_key") || kind.contains("authentication") || kind.contains("permission") {
+        LLMError::Auth { provider, message }
+    } else if code.contains("context_length_exceeded") || error::is_context_length_message(&message) {
+        LLMError::ContextLength { provider, message }
+    } else if kind.contains("server_error") {
+        LLMError::Transient { provider, message, retry_after }
+    } else {
+        LLMError::from_status(&provider, status, &message, retry_after)
+    }
+}
+
+// Local OpenAI-compatible servers often run without a key
+fn auth_headers(api_key: &str, extra: &HeaderMap) -> HeaderMap {
+    let mut headers = extra.clone();
+    
+    if api_key.is_empty() {
+        return headers;
+    }
+    
+    match HeaderValue::from_str(&format!("Bearer {}", api_key)) {
+        Ok(mut value) => {
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+        Err(_) => tracing::warn!("OpenAI API key is not a valid header value"),
+    }
+    
+    headers
 }
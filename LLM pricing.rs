@@ -0,0 +1,113 @@
+use super::TokenUsage;
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use crate::config::MonitoringConfig;
+
+const BUILTIN_PRICING: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/pricing.yaml"));
+
+/// Price of one model, in USD per 1M tokens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub provider: String,
+    /// Prefix of the model names this price applies to
+    pub model: String,
+    pub input: f64,
+    pub output: f64,
+    #[serde(default)]
+    pub cached_input: Option<f64>,
+    #[serde(default)]
+    pub effective_from: Option<NaiveDate>,
+}
+
+impl ModelPrice {
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        let cached = usage.cached_tokens.min(usage.prompt_tokens);
+        let uncached = usage.prompt_tokens - cached;
+        
+        (uncached as f64 * self.input
+            + cached as f64 * self.cached_input.unwrap_or(self.input)
+            + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PricingFile {
+    prices: Vec<ModelPrice>,
+}
+
+/// Model prices loaded from a YAML or JSON file (see `pricing.yaml`).
+/// Providers price each response with the table given to `with_pricing`.
+///
+/// Lookups pick the longest model prefix that matches, then the newest price
+/// already in effect. Unknown models cost nothing.
+#[derive(Debug, Clone, Default)]
+pub struct PricingTable {
+    prices: Vec<ModelPrice>,
+}
+
+impl PricingTable {
+    pub fn new(prices: Vec<ModelPrice>) -> Self {
+        Self { prices }
+    }
+    
+    /// The table shipped with ChainForge.
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_PRICING).expect("built-in pricing table is valid")
+    }
+    
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read pricing file {}", path.display()))?;
+        
+        Self::parse(&content).with_context(|| format!("Invalid pricing file {}", path.display()))
+    }
+    
+    /// Builds the table selected by `monitoring`: empty when cost tracking is
+    /// off, otherwise `pricing_file` or the built-in table.
+    pub fn from_config(config: &MonitoringConfig) -> Result<Self> {
+        if !config.enable_cost_tracking {
+            return Ok(Self::default());
+        }
+        
+        match &config.pricing_file {
+            Some(path) => Self::from_file(path),
+            None => Ok(Self::builtin()),
+        }
+    }
+    
+    // YAML is a superset of JSON, so one parser reads both formats
+    fn parse(content: &str) -> Result<Self> {
+        let file: PricingFile = serde_yaml::from_str(content)?;
+        Ok(Self::new(file.prices))
+    }
+    
+    pub fn prices(&self) -> &[ModelPrice] {
+        &self.prices
+    }
+    
+    /// Finds the price in effect on `date`. Without a provider, entries from
+    /// every provider are considered.
+    pub fn lookup(&self, provider: Option<&str>, model: &str, date: NaiveDate) -> Option<&ModelPrice> {
+        self.prices
+            .iter()
+            .filter(|p| provider.is_none_or(|provider| p.provider == provider))
+            .filter(|p| model.starts_with(&p.model))
+            .filter(|p| p.effective_from.is_none_or(|from| from <= date))
+            .max_by_key(|p| (p.model.len(), p.effective_from))
+    }
+    
+    /// Cost in USD of `usage` at today's prices.
+    ///
+    /// Only `provider`'s own entries apply, so a self-hosted model named like a
+    /// hosted one stays free unless its provider is listed.
+    pub fn cost(&self, provider: &str, model: &str, usage: &TokenUsage) -> f64 {
+        let today = chrono::Utc::now().date_naive();
+        
+        self.lookup(Some(provider), model, today)
+            .map(|price| price.cost(usage))
+            .unwrap_or(0.0)
+    }
+}
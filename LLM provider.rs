@@ -1,102 +1,244 @@
-use super::{LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage};
-use async_trait::async_trait;
-use anyhow::{Result, Context};
-use async_openai::{Client, types::{CreateChatCompletionRequestArgs, ChatCompletionRequestMessage, Role}};
+use super::LLMProvider;
+use super::anthropic::AnthropicProvider;
+use super::mock::MockProvider;
+use super::cache::{CacheBackend, CachedProvider, InMemoryCache, SqliteCache};
+use super::huggingface::HuggingFaceProvider;
+use super::ollama::OllamaProvider;
+use super::openai::OpenAIProvider;
+use super::pricing::PricingTable;
+use super::router::{RouteTarget, RouterProvider};
+use super::tokenizer::TokenCounter;
+use crate::config::{AppConfig, CacheBackendKind, RouterConfig};
+use crate::monitoring::MetricsCollector;
+use anyhow::Result;
+use dashmap::DashMap;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+use std::sync::Arc;
 
-pub struct OpenAIProvider {
-    client: Client<async_openai::config::OpenAIConfig>,
-    default_model: String,
-    default_temperature: f32,
-    default_max_tokens: usize,
+pub struct ProviderManager {
+    providers: Arc<DashMap<String, Arc<dyn LLMProvider>>>,
+    default_provider: String,
 }
 
-impl OpenAIProvider {
-    pub fn new(api_key: String, default_model: String, temperature: f32, max_tokens: usize) -> Self {
-        let config = async_openai::config::OpenAIConfig::new().with_api_key(api_key);
-        let client = Client::with_config(config);
-        
-        Self {
-            client,
-            default_model,
-            default_temperature: temperature,
-            default_max_tokens: max_tokens,
+impl ProviderManager {
+    pub async fn new(config: &AppConfig, metrics: Arc<MetricsCollector>) -> Result<Self> {
+        let manager = Self {
+            providers: Arc::new(DashMap::new()),
+            default_provider: config.llm.default_provider.clone(),
+        };
+        
+        let providers = &config.llm.providers;
+        
+        // Loaded once and shared, so every provider prices with the same table
+        let pricing = Arc::new(PricingTable::from_config(&config.monitoring)?);
+        tracing::info!("Pricing table loaded: {} model prices", pricing.prices().len());
+        
+        match config.get_openai_api_key() {
+            Ok(api_key) => {
+                let openai = &providers.openai;
+                manager.register_provider("openai", Arc::new(
+                    OpenAIProvider::new(
+                        api_key,
+                        openai.default_model.clone(),
+                        openai.temperature,
+                        openai.max_tokens,
+                    )?
+                    .with_http_config(&openai.http)?
+                    .with_pricing(pricing.clone()),
+                ));
+            }
+            Err(e) => tracing::warn!("Skipping OpenAI provider: {}", e),
         }
-    }
-}
-
-#[async_trait]
-impl LLMProvider for OpenAIProvider {
-    async fn generate(&self, request: &LLMRequest) -> Result<LLMResponse> {
-        let start = std::time::Instant::now();
         
-        let model = request.model.as_ref().unwrap_or(&self.default_model).clone();
+        let ollama = &providers.ollama;
+        manager.register_provider("ollama", Arc::new(
+            OllamaProvider::new(ollama.base_url.clone(), ollama.default_model.clone())?
+                .with_http_config(&ollama.http)?
+                .with_tokenizer(TokenCounter::from_optional_file(ollama.tokenizer_path.as_deref()))
+                .with_pricing(pricing.clone()),
+        ));
         
-        let mut messages = vec![];
+        match config.get_hf_api_key() {
+            Ok(api_key) => {
+                let huggingface = &providers.huggingface;
+                manager.register_provider("huggingface", Arc::new(
+                    HuggingFaceProvider::new(api_key, huggingface.default_model.clone())?
+                        .with_http_config(&huggingface.http)?
+                        .with_tokenizer(TokenCounter::from_optional_file(huggingface.tokenizer_path.as_deref()))
+                        .with_pricing(pricing.clone()),
+                ));
+            }
+            Err(e) => tracing::warn!("Skipping Hugging Face provider: {}", e),
+        }
         
-        if let Some(system_msg) = &request.system_message {
-            messages.push(ChatCompletionRequestMessage::System(
-                async_openai::types::ChatCompletionRequestSystemMessage {
-                    content: async_openai::types::ChatCompletionRequestSystemMessageContent::Text(system_msg.clone()),
-                    role: Role::System,
-                    name: None,
+        if let Some(anthropic) = &providers.anthropic {
+            match config.get_anthropic_api_key() {
+                Ok(api_key) => {
+                    let mut provider = AnthropicProvider::new(api_key, anthropic.default_model.clone(), anthropic.max_tokens)?
+                        .with_http_config(&anthropic.http)?
+                        .with_tokenizer(TokenCounter::from_optional_file(anthropic.tokenizer_path.as_deref()))
+                        .with_pricing(pricing.clone());
+                    
+                    if let Some(base_url) = &anthropic.base_url {
+                        provider = provider.with_base_url(base_url.clone());
+                    }
+                    
+                    manager.register_provider("anthropic", Arc::new(provider));
                 }
-            ));
+                Err(e) => tracing::warn!("Skipping Anthropic provider: {}", e),
+            }
         }
         
-        messages.push(ChatCompletionRequestMessage::User(
-            async_openai::types::ChatCompletionRequestUserMessage {
-                content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(request.prompt.clone()),
-                role: Role::User,
-                name: None,
+        if providers.mock.is_some() || manager.default_provider == "mock" {
+            let mock = providers.mock.clone().unwrap_or_default();
+            manager.register_provider("mock", Arc::new(MockProvider::from_config(&mock)?.with_pricing(pricing.clone())));
+        }
+        
+        let mut compatible: Vec<_> = providers.openai_compatible.iter().collect();
+        compatible.sort_by_key(|(name, _)| name.as_str());
+        
+        for (name, compatible) in compatible {
+            let api_key = match &compatible.api_key_env {
+                Some(env) => match std::env::var(env) {
+                    Ok(key) => key,
+                    Err(_) => {
+                        tracing::warn!("Skipping provider '{}': {} is not set", name, env);
+                        continue;
+                    }
+                },
+                None => String::new(),
+            };
+            
+            let mut provider = OpenAIProvider::new(
+                api_key,
+                compatible.default_model.clone(),
+                compatible.temperature,
+                compatible.max_tokens,
+            )?
+            .with_name(name.clone())?
+            .with_api_base(compatible.base_url.clone())
+            .with_headers(header_map(name, &compatible.headers))?
+            .with_http_config(&compatible.http)?
+            .with_pricing(pricing.clone());
+            
+            if compatible.tokenizer_path.is_some() {
+                provider = provider.with_tokenizer(TokenCounter::from_optional_file(compatible.tokenizer_path.as_deref()));
             }
-        ));
+            
+            manager.register_provider(name.clone(), Arc::new(provider));
+        }
+        
+        // Wrap the concrete providers before building routers, so routed calls share their cache
+        if config.llm.cache.enabled {
+            let backend = Self::cache_backend(config).await?;
+            
+            for mut entry in manager.providers.iter_mut() {
+                let cached = CachedProvider::new(entry.key().clone(), entry.value().clone(), backend.clone())
+                    .with_config(&config.llm.cache)
+                    .with_metrics(metrics.clone());
+                *entry.value_mut() = Arc::new(cached);
+            }
+            
+            tracing::info!("LLM response cache enabled ({:?} backend)", config.llm.cache.backend);
+        }
+        
+        // Routers are built last since their targets refer to the providers above
+        let mut routers: Vec<_> = config.llm.routers.iter().collect();
+        routers.sort_by_key(|(name, _)| name.as_str());
+        
+        for (name, router) in routers {
+            match manager.build_router(name, router) {
+                Some(router) => manager.register_provider(name.clone(), Arc::new(router)),
+                None => tracing::warn!("Skipping router '{}': none of its providers are available", name),
+            }
+        }
         
-        let chat_request = CreateChatCompletionRequestArgs::default()
-            .model(&model)
-            .messages(messages)
-            .temperature(request.temperature.unwrap_or(self.default_temperature))
-            .max_tokens(request.max_tokens.unwrap_or(self.default_max_tokens) as u16)
-            .build()
-            .context("Failed to build chat completion request")?;
-        
-        let response = self.client
-            .chat()
-            .create(chat_request)
-            .await
-            .context("Failed to call OpenAI API")?;
-        
-        let text = response
-            .choices
-            .first()
-            .and_then(|c| c.message.content.clone())
-            .unwrap_or_default();
-        
-        let usage = response.usage.unwrap_or_default();
-        
-        Ok(LLMResponse {
-            text,
-            model,
-            tokens_used: TokenUsage {
-                prompt_tokens: usage.prompt_tokens as usize,
-                completion_tokens: usage.completion_tokens as usize,
-                total_tokens: usage.total_tokens as usize,
-            },
-            finish_reason: response
-                .choices
-                .first()
-                .map(|c| format!("{:?}", c.finish_reason))
-                .unwrap_or_default(),
-            latency_ms: start.elapsed().as_millis() as u64,
+        if !manager.providers.contains_key(&manager.default_provider) {
+            return Err(anyhow::anyhow!(
+                "Default provider '{}' is not available",
+                manager.default_provider
+            ));
+        }
+        
+        Ok(manager)
+    }
+    
+    async fn cache_backend(config: &AppConfig) -> Result<Arc<dyn CacheBackend>> {
+        Ok(match config.llm.cache.backend {
+            CacheBackendKind::Memory => Arc::new(InMemoryCache::new(config.llm.cache.capacity)),
+            CacheBackendKind::Sqlite => Arc::new(
+                SqliteCache::connect(&config.database.url, config.database.max_connections).await?,
+            ),
         })
     }
     
-    async fn stream_generate(&self, _request: &LLMRequest) -> Result<LLMStream> {
-        // Streaming implementation placeholder
-        unimplemented!("Streaming not yet implemented for OpenAI")
+    fn build_router(&self, name: &str, config: &RouterConfig) -> Option<RouterProvider> {
+        let targets: Vec<RouteTarget> = config
+            .targets
+            .iter()
+            .filter_map(|target| match self.providers.get(&target.provider) {
+                Some(provider) => Some(RouteTarget::new(
+                    target.provider.clone(),
+                    provider.value().clone(),
+                    target.model.clone(),
+                    target.weight,
+                )),
+                None => {
+                    tracing::warn!("Router '{}': provider '{}' is not available", name, target.provider);
+                    None
+                }
+            })
+            .collect();
+        
+        if targets.is_empty() {
+            return None;
+        }
+        
+        Some(
+            RouterProvider::new(name, config.strategy, targets)
+                .with_circuit_breaker(config.circuit_breaker.clone()),
+        )
+    }
+    
+    pub fn register_provider(&self, name: impl Into<String>, provider: Arc<dyn LLMProvider>) {
+        let name = name.into();
+        tracing::info!("Registered LLM provider: {}", name);
+        self.providers.insert(name, provider);
     }
     
-    fn count_tokens(&self, text: &str) -> Result<usize> {
-        // Simplified token counting (approximate)
-        Ok(text.split_whitespace().count())
+    /// Returns the named provider, or the configured default when `name` is `None`.
+    pub fn get_provider(&self, name: Option<&str>) -> Result<Arc<dyn LLMProvider>> {
+        let name = name.unwrap_or(&self.default_provider);
+        
+        self.providers
+            .get(name)
+            .map(|entry| entry.value().clone())
+            .ok_or_else(|| anyhow::anyhow!("Unknown LLM provider: {}", name))
     }
+    
+    pub fn list_providers(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.iter().map(|entry| entry.key().clone()).collect();
+        names.sort();
+        names
+    }
+    
+    pub fn default_provider(&self) -> &str {
+        &self.default_provider
+    }
+}
+
+fn header_map(provider: &str, headers: &HashMap<String, String>) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    
+    for (name, value) in headers {
+        match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                map.insert(name, value);
+            }
+            _ => tracing::warn!("Provider '{}': ignoring invalid header '{}'", provider, name),
+        }
+    }
+    
+    map
 }
@@ -0,0 +1,270 @@
+use super::{LLMProvider, LLMRequest, LLMResponse, LLMStream, StreamChunk, TokenUsage};
+use super::error::LLMError;
+use async_trait::async_trait;
+use anyhow::Result;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use rand::Rng;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::config::{CircuitBreakerConfig, RoutingStrategy};
+
+/// One provider/model pair a router can send requests to.
+pub struct RouteTarget {
+    provider_name: String,
+    provider: Arc<dyn LLMProvider>,
+    model: Option<String>,
+    weight: u32,
+    breaker: CircuitBreaker,
+}
+
+impl RouteTarget {
+    pub fn new(provider_name: impl Into<String>, provider: Arc<dyn LLMProvider>, model: Option<String>, weight: u32) -> Self {
+        Self {
+            provider_name: provider_name.into(),
+            provider,
+            model,
+            weight,
+            breaker: CircuitBreaker::default(),
+        }
+    }
+    
+    // The target's model replaces the caller's, which may belong to another provider
+    fn prepare(&self, request: &LLMRequest) -> LLMRequest {
+        LLMRequest {
+            model: self.model.clone(),
+            ..request.clone()
+        }
+    }
+    
+    // `provider/model`, so callers can see which backend served the request
+    fn label(&self, model: &str) -> String {
+        format!("{}/{}", self.provider_name, model)
+    }
+}
+
+/// Composite provider that tries its targets in an order chosen by the
+/// routing strategy, falling through to the next target on failure.
+///
+/// Targets that fail `failure_threshold` times in a row are skipped for
+/// `cooldown_seconds`, then given another chance.
+pub struct RouterProvider {
+    name: String,
+    strategy: RoutingStrategy,
+    targets: Vec<RouteTarget>,
+    circuit_breaker: CircuitBreakerConfig,
+    next: AtomicUsize,
+}
+
+impl RouterProvider {
+    pub fn new(name: impl Into<String>, strategy: RoutingStrategy, targets: Vec<RouteTarget>) -> Self {
+        Self {
+            name: name.into(),
+            strategy,
+            targets,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            next: AtomicUsize::new(0),
+        }
+    }
+    
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = config;
+        self
+    }
+    
+    /// Target indices in the order they should be tried for the next request.
+    fn route(&self) -> Vec<usize> {
+        let count = self.targets.len();
+        if count == 0 {
+            return Vec::new();
+        }
+        
+        let first = match self.strategy {
+            RoutingStrategy::Fallback => 0,
+            RoutingStrategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % count,
+            RoutingStrategy::Weighted => self.pick_weighted(),
+        };
+        
+        // Whatever the strategy picked first, the rest remain as fallbacks in listed order
+        std::iter::once(first)
+            .chain((0..count).filter(|i| *i != first))
+            .filter(|i| self.targets[*i].breaker.is_available())
+            .collect()
+    }
+    
+    fn pick_weighted(&self) -> usize {
+        let total: u32 = self.targets.iter().map(|t| t.weight).sum();
+        if total == 0 {
+            return 0;
+        }
+        
+        let mut roll = rand::thread_rng().gen_range(0..total);
+        for (i, target) in self.targets.iter().enumerate() {
+            if roll < target.weight {
+                return i;
+            }
+            roll -= target.weight;
+        }
+        
+        0
+    }
+    
+    fn record_failure(&self, target: &RouteTarget, error: &anyhow::Error) {
+        tracing::warn!(
+            "Router '{}': {} failed, trying next target: {}",
+            self.name,
+            target.provider_name,
+            error
+        );
+        
+        // Bad requests say nothing about the backend's health
+        if matches!(
+            LLMError::find(error),
+            Some(LLMError::InvalidRequest { .. } | LLMError::ContextLength { .. })
+        ) {
+            return;
+        }
+        
+        if target.breaker.record_failure(&self.circuit_breaker) {
+            tracing::warn!(
+                "Router '{}': ejecting {} for {}s after {} consecutive failures",
+                self.name,
+                target.provider_name,
+                self.circuit_breaker.cooldown_seconds,
+                self.circuit_breaker.failure_threshold
+            );
+        }
+    }
+    
+    fn exhausted(&self, last_error: Option<anyhow::Error>) -> anyhow::Error {
+        match last_error {
+            Some(e) => e.context(format!("All targets of router '{}' failed", self.name)),
+            None => anyhow::anyhow!("All targets of router '{}' are temporarily ejected", self.name),
+        }
+    }
+}
+
+#[async_trait]
+impl LLMProvider for RouterProvider {
+    async fn generate(&self, request: &LLMRequest) -> Result<LLMResponse> {
+        let mut last_error = None;
+        
+        for i in self.route() {
+            let target = &self.targets[i];
+            
+            match target.provider.generate(&target.prepare(request)).await {
+                Ok(mut response) => {
+                    target.breaker.record_success();
+                    response.model = target.label(&response.model);
+                    return Ok(response);
+                }
+                Err(e) => {
+                    self.record_failure(target, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        
+        Err(self.exhausted(last_error))
+    }
+    
+    // Falls back only while opening the stream; errors after the first chunk
+    // are passed through since the caller has already seen partial output
+    async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream> {
+        let mut last_error = None;
+        
+        for i in self.route() {
+            let target = &self.targets[i];
+            
+            match target.provider.stream_generate(&target.prepare(request)).await {
+                Ok(stream) => {
+                    target.breaker.record_success();
+                    
+                    let provider_name = target.provider_name.clone();
+                    let stream = stream.map(move |chunk| match chunk {
+                        Ok(StreamChunk::Done { model, tokens_used, finish_reason, tool_calls, cost, cached }) => Ok(StreamChunk::Done {
+                            model: format!("{}/{}", provider_name, model),
+                            tokens_used,
+                            finish_reason,
+                            tool_calls,
+                            cost,
+                            cached,
+                        }),
+                        other => other,
+                    });
+                    
+                    return Ok(stream.boxed());
+                }
+                Err(e) => {
+                    self.record_failure(target, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        
+        Err(self.exhausted(last_error))
+    }
+    
+    fn count_tokens(&self, text: &str) -> Result<usize> {
+        match self.targets.first() {
+            Some(target) => target.provider.count_tokens(text),
+            None => Ok(text.chars().count().div_ceil(4)),
+        }
+    }
+    
+    fn count_prompt_tokens(&self, request: &LLMRequest) -> Result<usize> {
+        match self.targets.first() {
+            Some(target) => target.provider.count_prompt_tokens(request),
+            None => request.chat_messages().iter().map(|m| self.count_tokens(&m.content)).sum(),
+        }
+    }
+    
+    fn estimate_cost(&self, request: &LLMRequest, usage: &TokenUsage) -> f64 {
+        match self.targets.first() {
+            Some(target) => target.provider.estimate_cost(&target.prepare(request), usage),
+            None => 0.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    // Once the cooldown has passed the target is tried again (half-open);
+    // a single further failure ejects it for another cooldown
+    fn is_available(&self) -> bool {
+        self.state
+            .lock()
+            .open_until
+            .is_none_or(|until| Instant::now() >= until)
+    }
+    
+    fn record_success(&self) {
+        let mut state = self.state.lock();
+        state.consecutive_failures = 0;
+        state.open_until = None;
+    }
+    
+    /// Returns true when this failure ejected the target.
+    fn record_failure(&self, config: &CircuitBreakerConfig) -> bool {
+        let mut state = self.state.lock();
+        state.consecutive_failures += 1;
+        
+        if state.consecutive_failures >= config.failure_threshold.max(1) {
+            state.open_until = Some(Instant::now() + Duration::from_secs(config.cooldown_seconds));
+            true
+        } else {
+            false
+        }
+    }
+}
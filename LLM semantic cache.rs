@@ -0,0 +1,244 @@
+use super::{streaming, LLMProvider, LLMRequest, LLMResponse, LLMStream, TokenUsage};
+use async_trait::async_trait;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::config::SemanticCacheConfig;
+use crate::embeddings::EmbeddingProvider;
+use crate::memory::VectorMemory;
+
+// Candidates are already limited to the scope, and a few paraphrases per
+// prompt leave room for expired ones
+const SEARCH_CANDIDATES: usize = 5;
+
+/// Result of `SemanticCache::lookup`. A miss carries the prompt's embedding,
+/// so storing the response doesn't embed the prompt again.
+pub enum Lookup {
+    Hit(LLMResponse),
+    Miss(Vec<f32>),
+}
+
+/// Answers prompts that are close in meaning to ones already seen.
+///
+/// Entries are scoped (usually by chain id) so a hit never crosses into a
+/// chain with a different prompt template or purpose.
+pub struct SemanticCache {
+    embeddings: Arc<dyn EmbeddingProvider>,
+    store: Arc<dyn VectorMemory>,
+    similarity_threshold: f32,
+    ttl_seconds: i64,
+}
+
+impl SemanticCache {
+    pub fn new(embeddings: Arc<dyn EmbeddingProvider>, store: Arc<dyn VectorMemory>) -> Self {
+        let defaults = SemanticCacheConfig::default();
+        
+        Self {
+            embeddings,
+            store,
+            similarity_threshold: defaults.similarity_threshold,
+            ttl_seconds: defaults.ttl_seconds as i64,
+        }
+    }
+    
+    pub fn with_config(mut self, config: &SemanticCacheConfig) -> Self {
+        self.similarity_threshold = config.similarity_threshold;
+        self.ttl_seconds = config.ttl_seconds as i64;
+        self
+    }
+    
+    /// Routes `inner` through the cache under `scope`.
+    pub fn wrap(self: &Arc<Self>, scope: impl Into<String>, inner: Arc<dyn LLMProvider>) -> Arc<dyn LLMProvider> {
+        Arc::new(SemanticCachedProvider {
+            cache: self.clone(),
+            scope: scope.into(),
+            inner,
+        })
+    }
+    
+    pub async fn lookup(&self, scope: &str, request: &LLMRequest) -> Result<Lookup> {
+        let embedding = self.embeddings.embed_query(&cache_text(request)).await?;
+        
+        let mut filter = serde_json::Map::new();
+        filter.insert("scope".to_string(), serde_json::json!(scope));
+        filter.insert("model".to_string(), serde_json::json!(request.model));
+        
+        let candidates = self.store
+            .search_filtered(embedding.clone(), SEARCH_CANDIDATES, self.similarity_threshold, &filter)
+            .await?;
+        
+        let now = chrono::Utc::now().timestamp();
+        
+        for candidate in candidates {
+            let metadata = &candidate.metadata;
+            
+            if metadata["created_at"].as_i64().is_some_and(|t| now - t < self.ttl_seconds) {
+                tracing::debug!("Semantic cache hit in '{}' (score {:.3})", scope, candidate.score);
+                let mut response: LLMResponse = serde_json::from_value(metadata["response"].clone())?;
+                response.cost = 0.0;
+                response.cached = true;
+                return Ok(Lookup::Hit(response));
+            }
+        }
+        
+        Ok(Lookup::Miss(embedding))
+    }
+    
+    /// Stores `response` under the prompt `embedding` returned by a missed
+    /// `lookup`, and returns the new entry's id.
+    pub async fn store(
+        &self,
+        scope: &str,
+        request: &LLMRequest,
+        embedding: Vec<f32>,
+        response: &LLMResponse,
+    ) -> Result<String> {
+        let text = cache_text(request);
+        
+        // Qdrant only accepts UUIDs and integers as point ids
+        let id = uuid::Uuid::new_v4().to_string();
+        
+        let metadata = serde_json::json!({
+            "scope": scope,
+            "model": request.model,
+            "response": response,
+            "created_at": chrono::Utc::now().timestamp(),
+        });
+        
+        self.store.store(&id, &text, embedding, metadata).await?;
+        
+        Ok(id)
+    }
+    
+    pub async fn invalidate(&self, id: &str) -> Result<()> {
+        self.store.delete(id).await
+    }
+    
+    /// Removes every entry similar to `prompt`, optionally limited to one scope.
+    /// Returns the ids that were removed.
+    pub async fn invalidate_similar(&self, prompt: &str, scope: Option<&str>) -> Result<Vec<String>> {
+        let embedding = self.embeddings.embed_query(prompt).await?;
+        
+        let mut filter = serde_json::Map::new();
+        if let Some(scope) = scope {
+            filter.insert("scope".to_string(), serde_json::json!(scope));
+        }
+        
+        let candidates = self.store
+            .search_filtered(embedding, SEARCH_CANDIDATES, self.similarity_threshold, &filter)
+            .await?;
+        
+        let mut removed = Vec::new();
+        
+        for candidate in candidates {
+            self.store.delete(&candidate.id).await?;
+            removed.push(candidate.id);
+        }
+        
+        Ok(removed)
+    }
+}
+
+// The text that gets embedded: every message of the conversation, so the
+// system prompt and history take part in the similarity
+fn cache_text(request: &LLMRequest) -> String {
+    request
+        .chat_messages()
+        .iter()
+        .map(|m| format!("{}: {}", m.role.as_str(), m.content))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Provider decorator created by `SemanticCache::wrap`.
+///
+/// Cache failures are logged and treated as misses.
+struct SemanticCachedProvider {
+    cache: Arc<SemanticCache>,
+    scope: String,
+    inner: Arc<dyn LLMProvider>,
+}
+
+impl SemanticCachedProvider {
+    // Tool calls and schema-bound replies depend on exact arguments, so those
+    // requests are never cached
+    fn is_cacheable(request: &LLMRequest) -> bool {
+        request.tools.is_empty() && request.response_format.is_none()
+    }
+    
+    // `None` when the lookup failed, in which case nothing is stored either
+    async fn lookup(&self, request: &LLMRequest) -> Option<Lookup> {
+        self.cache.lookup(&self.scope, request).await.map(Some).unwrap_or_else(|e| {
+            tracing::warn!("Semantic cache lookup failed in '{}': {}", self.scope, e);
+            None
+        })
+    }
+}
+
+#[async_trait]
+impl LLMProvider for SemanticCachedProvider {
+    async fn generate(&self, request: &LLMRequest) -> Result<LLMResponse> {
+        if !Self::is_cacheable(request) {
+            return self.inner.generate(request).await;
+        }
+        
+        let start = Instant::now();
+        
+        let embedding = match self.lookup(request).await {
+            Some(Lookup::Hit(mut response)) => {
+                response.latency_ms = start.elapsed().as_millis() as u64;
+                return Ok(response);
+            }
+            Some(Lookup::Miss(embedding)) => Some(embedding),
+            None => None,
+        };
+        
+        let response = self.inner.generate(request).await?;
+        
+        if let Some(embedding) = embedding {
+            if let Err(e) = self.cache.store(&self.scope, request, embedding, &response).await {
+                tracing::warn!("Semantic cache store failed in '{}': {}", self.scope, e);
+            }
+        }
+        
+        Ok(response)
+    }
+    
+    async fn stream_generate(&self, request: &LLMRequest) -> Result<LLMStream> {
+        if !Self::is_cacheable(request) {
+            return self.inner.stream_generate(request).await;
+        }
+        
+        let embedding = match self.lookup(request).await {
+            Some(Lookup::Hit(response)) => return Ok(streaming::replay(response)),
+            Some(Lookup::Miss(embedding)) => embedding,
+            None => return self.inner.stream_generate(request).await,
+        };
+        
+        let start = Instant::now();
+        let cache = self.cache.clone();
+        let scope = self.scope.clone();
+        let request = request.clone();
+        
+        let stream = self.inner.stream_generate(&request).await?;
+        Ok(streaming::on_complete(stream, start, move |response| {
+            tokio::spawn(async move {
+                if let Err(e) = cache.store(&scope, &request, embedding, &response).await {
+                    tracing::warn!("Semantic cache store failed in '{}': {}", scope, e);
+                }
+            });
+        }))
+    }
+    
+    fn count_tokens(&self, text: &str) -> Result<usize> {
+        self.inner.count_tokens(text)
+    }
+    
+    fn count_prompt_tokens(&self, request: &LLMRequest) -> Result<usize> {
+        self.inner.count_prompt_tokens(request)
+    }
+    
+    fn estimate_cost(&self, request: &LLMRequest, usage: &TokenUsage) -> f64 {
+        self.inner.estimate_cost(request, usage)
+    }
+}
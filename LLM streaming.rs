@@ -0,0 +1,246 @@
+use super::pricing::PricingTable;
+use super::{LLMResponse, LLMStream, StreamChunk, TokenUsage, ToolCall};
+use anyhow::Result;
+use futures::stream::{BoxStream, StreamExt};
+use std::sync::Arc;
+
+/// A single event decoded from a provider's wire format.
+pub enum UpstreamEvent {
+    Delta(String),
+    /// A tool call whose arguments have fully arrived.
+    ToolCall(ToolCall),
+    Finish {
+        reason: Option<String>,
+        usage: Option<TokenUsage>,
+    },
+    Skip,
+}
+
+/// Splits an HTTP response body into lines, buffering partial lines across chunks.
+pub fn lines(response: reqwest::Response) -> BoxStream<'static, Result<String>> {
+    let state = (response.bytes_stream().boxed(), Vec::<u8>::new(), false);
+    
+    futures::stream::unfold(state, |(mut bytes, mut buffer, mut done)| async move {
+        loop {
+            if let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+                return Some((Ok(line), (bytes, buffer, done)));
+            }
+            
+            if done {
+                if buffer.is_empty() {
+                    return None;
+                }
+                let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+                buffer.clear();
+                return Some((Ok(line), (bytes, buffer, done)));
+            }
+            
+            match bytes.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    buffer.clear();
+                    return Some((Err(e.into()), (bytes, buffer, true)));
+                }
+                None => done = true,
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Extracts the `data:` payloads of a Server-Sent Events body.
+pub fn sse_data(response: reqwest::Response) -> BoxStream<'static, Result<String>> {
+    lines(response)
+        .filter_map(|line| async move {
+            match line {
+                Ok(line) => {
+                    let data = line.strip_prefix("data:")?.trim();
+                    if data.is_empty() {
+                        None
+                    } else {
+                        Some(Ok(data.to_string()))
+                    }
+                }
+                Err(e) => Some(Err(e)),
+            }
+        })
+        .boxed()
+}
+
+/// Turns provider events into an `LLMStream` that ends with a `StreamChunk::Done`.
+///
+/// When the backend does not report usage, it is estimated with `count_tokens`
+/// over the prompt and the accumulated completion text. The usage is priced
+/// with `provider`'s entries in `pricing`.
+pub fn finalize<S, F>(
+    upstream: S,
+    provider: String,
+    model: String,
+    prompt_tokens: usize,
+    count_tokens: F,
+    pricing: Arc<PricingTable>,
+) -> LLMStream
+where
+    S: futures::Stream<Item = Result<UpstreamEvent>> + Send + 'static,
+    F: Fn(&str) -> usize + Send + 'static,
+{
+    struct State<F> {
+        upstream: BoxStream<'static, Result<UpstreamEvent>>,
+        provider: String,
+        model: String,
+        text: String,
+        reason: Option<String>,
+        usage: Option<TokenUsage>,
+        tool_calls: Vec<ToolCall>,
+        prompt_tokens: usize,
+        count_tokens: F,
+        pricing: Arc<PricingTable>,
+        done: bool,
+    }
+    
+    let state = State {
+        upstream: upstream.boxed(),
+        provider,
+        model,
+        text: String::new(),
+        reason: None,
+        usage: None,
+        tool_calls: Vec::new(),
+        prompt_tokens,
+        count_tokens,
+        pricing,
+        done: false,
+    };
+    
+    futures::stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+        
+        loop {
+            match state.upstream.next().await {
+                Some(Ok(UpstreamEvent::Delta(delta))) => {
+                    if delta.is_empty() {
+                        continue;
+                    }
+                    state.text.push_str(&delta);
+                    return Some((Ok(StreamChunk::Delta(delta)), state));
+                }
+                Some(Ok(UpstreamEvent::Finish { reason, usage })) => {
+                    state.reason = reason.or(state.reason.take());
+                    state.usage = usage.or(state.usage.take());
+                }
+                Some(Ok(UpstreamEvent::ToolCall(call))) => state.tool_calls.push(call),
+                Some(Ok(UpstreamEvent::Skip)) => continue,
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+                None => {
+                    state.done = true;
+                    
+                    let tokens_used = state.usage.take().unwrap_or_else(|| {
+                        TokenUsage::new(state.prompt_tokens, (state.count_tokens)(&state.text))
+                    });
+                    
+                    let chunk = StreamChunk::Done {
+                        model: state.model.clone(),
+                        cost: state.pricing.cost(&state.provider, &state.model, &tokens_used),
+                        tokens_used,
+                        finish_reason: state.reason.take().unwrap_or_else(|| "stop".to_string()),
+                        tool_calls: std::mem::take(&mut state.tool_calls),
+                        cached: false,
+                    };
+                    return Some((Ok(chunk), state));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Drains a stream into an `LLMResponse`, handing each delta to `on_delta`.
+pub async fn collect(
+    mut stream: LLMStream,
+    start: std::time::Instant,
+    mut on_delta: impl FnMut(&str) + Send,
+) -> Result<LLMResponse> {
+    let mut text = String::new();
+    
+    while let Some(chunk) = stream.next().await {
+        match chunk? {
+            StreamChunk::Delta(delta) => {
+                on_delta(&delta);
+                text.push_str(&delta);
+            }
+            StreamChunk::Done { model, tokens_used, finish_reason, tool_calls, cost, cached } => {
+                return Ok(LLMResponse {
+                    text,
+                    model,
+                    tokens_used,
+                    finish_reason,
+                    latency_ms: start.elapsed().as_millis() as u64,
+                    tool_calls,
+                    cost,
+                    cached,
+                });
+            }
+        }
+    }
+    
+    Err(anyhow::anyhow!("Stream ended without a final chunk"))
+}
+
+/// Replays a finished response as a single delta followed by the final chunk.
+pub fn replay(response: LLMResponse) -> LLMStream {
+    let chunks = vec![
+        Ok(StreamChunk::Delta(response.text)),
+        Ok(StreamChunk::Done {
+            model: response.model,
+            tokens_used: response.tokens_used,
+            finish_reason: response.finish_reason,
+            tool_calls: response.tool_calls,
+            cost: response.cost,
+            cached: response.cached,
+        }),
+    ];
+    
+    futures::stream::iter(chunks).boxed()
+}
+
+/// Passes `stream` through unchanged and hands the assembled response to
+/// `on_complete` once the final chunk arrives; failed streams are not reported.
+pub fn on_complete<F>(stream: LLMStream, start: std::time::Instant, on_complete: F) -> LLMStream
+where
+    F: FnOnce(LLMResponse) + Send + 'static,
+{
+    let mut text = String::new();
+    let mut on_complete = Some(on_complete);
+    
+    stream
+        .map(move |chunk| {
+            match &chunk {
+                Ok(StreamChunk::Delta(delta)) => text.push_str(delta),
+                Ok(StreamChunk::Done { model, tokens_used, finish_reason, tool_calls, cost, cached }) => {
+                    if let Some(on_complete) = on_complete.take() {
+                        on_complete(LLMResponse {
+                            text: std::mem::take(&mut text),
+                            model: model.clone(),
+                            tokens_used: tokens_used.clone(),
+                            finish_reason: finish_reason.clone(),
+                            latency_ms: start.elapsed().as_millis() as u64,
+                            tool_calls: tool_calls.clone(),
+                            cost: *cost,
+                            cached: *cached,
+                        });
+                    }
+                }
+                Err(_) => {}
+            }
+            
+            chunk
+        })
+        .boxed()
+}
@@ -0,0 +1,202 @@
+use super::{ChatMessage, LLMProvider, LLMRequest, LLMResponse, TokenUsage};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Repair attempts `generate` makes after the first reply by default.
+pub const DEFAULT_MAX_REPAIRS: usize = 2;
+
+/// Asks the model for JSON matching `schema`.
+///
+/// OpenAI-compatible providers send it as `response_format`, Ollama as
+/// `format`; the others describe it in the prompt (see `with_text_protocol`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseFormat {
+    pub name: String,
+    pub schema: serde_json::Value,
+    /// Ask OpenAI to enforce the schema exactly (requires a strict-compatible schema)
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl ResponseFormat {
+    pub fn json_schema(name: impl Into<String>, schema: serde_json::Value) -> Self {
+        Self {
+            name: name.into(),
+            schema,
+            strict: false,
+        }
+    }
+    
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Model output did not match schema '{name}' after {attempts} attempt(s): {}", errors.join("; "))]
+pub struct StructuredOutputError {
+    pub name: String,
+    pub attempts: usize,
+    pub errors: Vec<String>,
+    pub last_output: String,
+    /// Spent on every attempt, so callers can still bill the failure
+    pub tokens_used: TokenUsage,
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructuredResponse {
+    pub value: serde_json::Value,
+    /// The last reply, with token usage and cost summed over every attempt
+    pub response: LLMResponse,
+    pub attempts: usize,
+}
+
+/// Generates a reply for a request with a `response_format` and validates it,
+/// feeding validation errors back to the model up to `max_repairs` times.
+///
+/// Requests without a `response_format` only need to contain some JSON.
+pub async fn generate(llm: &dyn LLMProvider, request: &LLMRequest, max_repairs: usize) -> Result<StructuredResponse> {
+    let name = request
+        .response_format
+        .as_ref()
+        .map(|f| f.name.clone())
+        .unwrap_or_else(|| "json".to_string());
+    
+    let mut attempt_request = request.clone();
+    let mut tokens_used = TokenUsage::new(0, 0);
+    let mut cost = 0.0;
+    let mut latency_ms = 0;
+    let mut attempts = 0;
+    
+    loop {
+        attempts += 1;
+        
+        let mut response = llm.generate(&attempt_request).await?;
+        tokens_used += &response.tokens_used;
+        cost += response.cost;
+        latency_ms += response.latency_ms;
+        
+        let errors = match extract_json(&response.text) {
+            Some(value) => match request.response_format.as_ref().map(|f| validate(&value, &f.schema)) {
+                Some(Err(errors)) => errors,
+                _ => {
+                    response.tokens_used = tokens_used;
+                    response.cost = cost;
+                    response.latency_ms = latency_ms;
+                    
+                    return Ok(StructuredResponse { value, response, attempts });
+                }
+            },
+            None => vec!["the reply does not contain a JSON value".to_string()],
+        };
+        
+        if attempts > max_repairs {
+            return Err(StructuredOutputError {
+                name,
+                attempts,
+                errors,
+                last_output: response.text,
+                tokens_used,
+                cost,
+            }
+            .into());
+        }
+        
+        tracing::debug!("Structured output '{}' invalid (attempt {}): {:?}", name, attempts, errors);
+        attempt_request = repair_request(&attempt_request, &response.text, &errors);
+    }
+}
+
+/// Checks `value` against a JSON Schema, returning one message per violation.
+pub fn validate(value: &serde_json::Value, schema: &serde_json::Value) -> Result<(), Vec<String>> {
+    let compiled = jsonschema::JSONSchema::compile(schema).map_err(|e| vec![format!("invalid schema: {}", e)])?;
+    
+    compiled.validate(value).map_err(|errors| {
+        errors
+            .map(|e| {
+                let path = e.instance_path.to_string();
+                if path.is_empty() {
+                    e.to_string()
+                } else {
+                    format!("{}: {}", path, e)
+                }
+            })
+            .collect()
+    })
+}
+
+/// Finds the JSON in a reply: the whole text, a fenced code block, or the
+/// outermost object or array.
+pub fn extract_json(text: &str) -> Option<serde_json::Value> {
+    let text = text.trim();
+    
+    if let Ok(value) = serde_json::from_str(text) {
+        return Some(value);
+    }
+    
+    if let Some(block) = fenced_block(text) {
+        if let Ok(value) = serde_json::from_str(block) {
+            return Some(value);
+        }
+    }
+    
+    [('{', '}'), ('[', ']')].iter().find_map(|(open, close)| {
+        let start = text.find(*open)?;
+        let end = text.rfind(*close)?;
+        (start < end).then(|| serde_json::from_str(&text[start..=end]).ok()).flatten()
+    })
+}
+
+/// Prompt-based structured output for backends without a native option:
+/// the schema is described in the system message and `response_format`
+/// is removed.
+pub fn with_text_protocol(request: &LLMRequest) -> LLMRequest {
+    let mut request = request.clone();
+    
+    let Some(format) = request.response_format.take() else {
+        return request;
+    };
+    
+    let instructions = format!(
+        "Respond only with a JSON value that matches this JSON Schema, without any other text:\n{}",
+        format.schema
+    );
+    
+    request.system_message = Some(match request.system_message.take() {
+        Some(system_msg) => format!("{}\n\n{}", system_msg, instructions),
+        None => instructions,
+    });
+    
+    request
+}
+
+fn fenced_block(text: &str) -> Option<&str> {
+    let start = text.find("```")?;
+    let rest = &text[start + 3..];
+    
+    // Skip the language tag on the opening fence, e.g. ```json
+    let body_start = rest.find('\n')? + 1;
+    let body = &rest[body_start..];
+    
+    Some(body[..body.find("```")?].trim())
+}
+
+// Continues the conversation with the invalid reply and what was wrong with it
+fn repair_request(request: &LLMRequest, reply: &str, errors: &[String]) -> LLMRequest {
+    let mut messages = request.chat_messages();
+    messages.push(ChatMessage::assistant(reply));
+    messages.push(ChatMessage::user(format!(
+        "Your reply was not valid:\n- {}\nReply again with only the corrected JSON.",
+        errors.join("\n- ")
+    )));
+    
+    LLMRequest {
+        prompt: String::new(),
+        messages,
+        system_message: None,
+        ..request.clone()
+    }
+}
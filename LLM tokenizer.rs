@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use tiktoken_rs::CoreBPE;
+
+/// Model-specific token counting used by `LLMProvider::count_tokens`.
+#[derive(Clone)]
+pub enum TokenCounter {
+    /// OpenAI BPE encodings via `tiktoken-rs`.
+    Tiktoken(Arc<CoreBPE>),
+    /// A Hugging Face `tokenizer.json` loaded with the `tokenizers` crate.
+    HuggingFace(Arc<tokenizers::Tokenizer>),
+    /// Roughly four characters per token, for models without a tokenizer.
+    Estimate,
+}
+
+impl TokenCounter {
+    pub fn for_openai_model(model: &str) -> Self {
+        let bpe = tiktoken_rs::get_bpe_from_model(model).or_else(|_| {
+            tracing::debug!("No tiktoken encoding for model {}, using cl100k_base", model);
+            tiktoken_rs::cl100k_base()
+        });
+        
+        match bpe {
+            Ok(bpe) => TokenCounter::Tiktoken(Arc::new(bpe)),
+            Err(e) => {
+                tracing::warn!("Failed to load tiktoken encoding: {}", e);
+                TokenCounter::Estimate
+            }
+        }
+    }
+    
+    pub fn from_tokenizer_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let tokenizer = tokenizers::Tokenizer::from_file(path)
+            .map_err(|e| anyhow::anyhow!("{}", e))
+            .with_context(|| format!("Failed to load tokenizer from {}", path.display()))?;
+        
+        Ok(TokenCounter::HuggingFace(Arc::new(tokenizer)))
+    }
+    
+    /// Loads `path` if configured, falling back to an estimate.
+    pub fn from_optional_file(path: Option<&Path>) -> Self {
+        match path {
+            Some(path) => Self::from_tokenizer_file(path).unwrap_or_else(|e| {
+                tracing::warn!("{:#}, estimating token counts instead", e);
+                TokenCounter::Estimate
+            }),
+            None => TokenCounter::Estimate,
+        }
+    }
+    
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            TokenCounter::Tiktoken(bpe) => bpe.encode_with_special_tokens(text).len(),
+            TokenCounter::HuggingFace(tokenizer) => match tokenizer.encode(text, false) {
+                Ok(encoding) => encoding.len(),
+                Err(_) => estimate(text),
+            },
+            TokenCounter::Estimate => estimate(text),
+        }
+    }
+}
+
+fn estimate(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
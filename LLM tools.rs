@@ -0,0 +1,96 @@
+use super::{LLMRequest, ToolCall, ToolDefinition};
+use crate::memory::MessageRole;
+
+/// Prompt-based tool calling for backends without a native tools API.
+///
+/// Tool definitions are described in the system message and the model is
+/// asked to answer with a JSON object when it wants to call one.
+pub fn with_text_protocol(request: &LLMRequest) -> LLMRequest {
+    let mut request = request.clone();
+    
+    if request.tools.is_empty() {
+        return request;
+    }
+    
+    let instructions = render_instructions(&request.tools);
+    request.system_message = Some(match request.system_message.take() {
+        Some(system_msg) => format!("{}\n\n{}", system_msg, instructions),
+        None => instructions,
+    });
+    
+    // Earlier tool calls only exist as structured data; replay them as text
+    for message in &mut request.messages {
+        if message.role == MessageRole::Assistant && !message.tool_calls.is_empty() {
+            message.content = render_calls(&message.tool_calls);
+            message.tool_calls.clear();
+        }
+    }
+    
+    request.tools.clear();
+    request
+}
+
+/// Extracts tool calls from a reply produced under `with_text_protocol`.
+///
+/// Returns an empty list when the reply is a plain answer or names an unknown tool.
+pub fn parse_tool_calls(text: &str, tools: &[ToolDefinition]) -> Vec<ToolCall> {
+    let (Some(start), Some(end)) = (text.find('{'), text.rfind('}')) else {
+        return Vec::new();
+    };
+    
+    if end < start {
+        return Vec::new();
+    }
+    
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(&text[start..=end]) else {
+        return Vec::new();
+    };
+    
+    let calls = match value["tool_calls"].as_array() {
+        Some(calls) => calls.clone(),
+        None => vec![value],
+    };
+    
+    calls
+        .iter()
+        .enumerate()
+        .filter_map(|(i, call)| {
+            let name = call["name"].as_str()?;
+            tools.iter().find(|t| t.name == name)?;
+            
+            Some(ToolCall {
+                id: format!("call_{}", i),
+                name: name.to_string(),
+                arguments: call["arguments"].clone(),
+            })
+        })
+        .collect()
+}
+
+fn render_instructions(tools: &[ToolDefinition]) -> String {
+    let tools_desc = tools
+        .iter()
+        .map(|t| format!("- {}: {}\n  Arguments schema: {}", t.name, t.description, t.parameters))
+        .collect::<Vec<_>>()
+        .join("\n");
+    
+    format!(
+        r#"You have access to the following tools:
+{}
+
+To call tools, reply with only a JSON object in this format:
+{{"tool_calls": [{{"name": "<tool name>", "arguments": {{...}}}}]}}
+
+Otherwise, answer normally."#,
+        tools_desc
+    )
+}
+
+fn render_calls(calls: &[ToolCall]) -> String {
+    let calls: Vec<serde_json::Value> = calls
+        .iter()
+        .map(|c| serde_json::json!({ "name": c.name, "arguments": c.arguments }))
+        .collect();
+    
+    serde_json::json!({ "tool_calls": calls }).to_string()
+}
@@ -14,11 +14,24 @@ pub struct Message {
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MessageRole {
     User,
     Assistant,
     System,
+    Tool,
+}
+
+impl MessageRole {
+    /// Role name as used by chat completion APIs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+            MessageRole::System => "system",
+            MessageRole::Tool => "tool",
+        }
+    }
 }
 
 impl Message {
@@ -45,6 +58,29 @@ pub trait VectorMemory: Send + Sync {
     async fn store(&self, id: &str, text: &str, embedding: Vec<f32>, metadata: serde_json::Value) -> Result<()>;
     async fn search(&self, query_embedding: Vec<f32>, top_k: usize, threshold: f32) -> Result<Vec<SearchResult>>;
     async fn delete(&self, id: &str) -> Result<()>;
+    
+    /// Like `search`, over only the entries whose metadata has every field of
+    /// `filter` with an equal value (`null` also matches a missing field).
+    ///
+    /// The default filters the results of `search`, so entries that don't
+    /// match can crowd out ones that do; backends should filter while searching.
+    async fn search_filtered(
+        &self,
+        query_embedding: Vec<f32>,
+        top_k: usize,
+        threshold: f32,
+        filter: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Vec<SearchResult>> {
+        let results = self.search(query_embedding, top_k, threshold).await?;
+        Ok(results.into_iter().filter(|result| matches_filter(&result.metadata, filter)).collect())
+    }
+}
+
+/// Whether `metadata` passes a `VectorMemory::search_filtered` filter.
+pub fn matches_filter(metadata: &serde_json::Value, filter: &serde_json::Map<String, serde_json::Value>) -> bool {
+    filter
+        .iter()
+        .all(|(key, value)| metadata.get(key).unwrap_or(&serde_json::Value::Null) == value)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
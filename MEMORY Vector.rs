@@ -1,4 +1,4 @@
-use super::{VectorMemory, SearchResult};
+use super::{matches_filter, VectorMemory, SearchResult};
 use anyhow::Result;
 use async_trait::async_trait;
 use qdrant_client::{
@@ -8,6 +8,7 @@ use qdrant_client::{
         SearchPoints, Filter, Condition, FieldCondition, Match,
     },
 };
+use parking_lot::RwLock;
 use std::sync::Arc;
 
 pub struct QdrantVectorMemory {
@@ -80,10 +81,40 @@ impl VectorMemory for QdrantVectorMemory {
     }
     
     async fn search(&self, query_embedding: Vec<f32>, top_k: usize, threshold: f32) -> Result<Vec<SearchResult>> {
+        self.search_filtered(query_embedding, top_k, threshold, &serde_json::Map::new()).await
+    }
+    
+    async fn search_filtered(
+        &self,
+        query_embedding: Vec<f32>,
+        top_k: usize,
+        threshold: f32,
+        filter: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Vec<SearchResult>> {
+        let mut conditions = Vec::with_capacity(filter.len());
+        
+        for (key, value) in filter {
+            // Metadata is stored under the `metadata` payload key
+            let field = format!("metadata.{}", key);
+            
+            conditions.push(match value {
+                serde_json::Value::Null => {
+                    Filter::should([Condition::is_null(field.clone()), Condition::is_empty(field)]).into()
+                }
+                serde_json::Value::String(text) => Condition::matches(field, text.clone()),
+                serde_json::Value::Bool(flag) => Condition::matches(field, *flag),
+                serde_json::Value::Number(number) if number.is_i64() => {
+                    Condition::matches(field, number.as_i64().unwrap_or_default())
+                }
+                other => anyhow::bail!("Can't filter Qdrant points on '{}' = {}", key, other),
+            });
+        }
+        
         let search_result = self.client
             .search_points(&SearchPoints {
                 collection_name: self.collection_name.clone(),
                 vector: query_embedding,
+                filter: (!conditions.is_empty()).then(|| Filter::must(conditions)),
                 limit: top_k as u64,
                 score_threshold: Some(threshold),
                 with_payload: Some(true.into()),
@@ -133,3 +164,102 @@ impl VectorMemory for QdrantVectorMemory {
         Ok(())
     }
 }
+
+/// Vectors kept in process memory and searched by brute-force cosine
+/// similarity. Suited to small sets such as few-shot examples and tests.
+#[derive(Default)]
+pub struct InMemoryVectorMemory {
+    // Insertion order breaks ties between equal scores
+    entries: RwLock<Vec<InMemoryEntry>>,
+}
+
+struct InMemoryEntry {
+    id: String,
+    text: String,
+    embedding: Vec<f32>,
+    metadata: serde_json::Value,
+}
+
+impl InMemoryVectorMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().is_empty()
+    }
+}
+
+#[async_trait]
+impl VectorMemory for InMemoryVectorMemory {
+    async fn store(&self, id: &str, text: &str, embedding: Vec<f32>, metadata: serde_json::Value) -> Result<()> {
+        let entry = InMemoryEntry {
+            id: id.to_string(),
+            text: text.to_string(),
+            embedding,
+            metadata,
+        };
+        
+        let mut entries = self.entries.write();
+        
+        match entries.iter_mut().find(|e| e.id == id) {
+            Some(existing) => *existing = entry,
+            None => entries.push(entry),
+        }
+        
+        Ok(())
+    }
+    
+    async fn search(&self, query_embedding: Vec<f32>, top_k: usize, threshold: f32) -> Result<Vec<SearchResult>> {
+        self.search_filtered(query_embedding, top_k, threshold, &serde_json::Map::new()).await
+    }
+    
+    async fn search_filtered(
+        &self,
+        query_embedding: Vec<f32>,
+        top_k: usize,
+        threshold: f32,
+        filter: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Vec<SearchResult>> {
+        let entries = self.entries.read();
+        
+        let mut results: Vec<SearchResult> = entries
+            .iter()
+            .filter(|entry| matches_filter(&entry.metadata, filter))
+            .map(|entry| SearchResult {
+                id: entry.id.clone(),
+                text: entry.text.clone(),
+                score: cosine_similarity(&query_embedding, &entry.embedding),
+                metadata: entry.metadata.clone(),
+            })
+            .filter(|result| result.score >= threshold)
+            .collect();
+        
+        // Stable sort, so equal scores keep insertion order
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(top_k);
+        
+        Ok(results)
+    }
+    
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.entries.write().retain(|entry| entry.id != id);
+        Ok(())
+    }
+}
+
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
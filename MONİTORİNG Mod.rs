@@ -3,6 +3,7 @@ use lazy_static::lazy_static;
 use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
+pub mod budget;
 pub mod logger;
 
 lazy_static! {
@@ -27,6 +28,16 @@ lazy_static! {
         "chainforge_tokens_used",
         "Total tokens used"
     ).unwrap();
+    
+    pub static ref CACHE_HITS: IntCounter = IntCounter::new(
+        "chainforge_llm_cache_hits",
+        "LLM responses served from the cache"
+    ).unwrap();
+    
+    pub static ref CACHE_MISSES: IntCounter = IntCounter::new(
+        "chainforge_llm_cache_misses",
+        "LLM requests not found in the cache"
+    ).unwrap();
 }
 
 pub struct MetricsCollector {
@@ -34,6 +45,8 @@ pub struct MetricsCollector {
     pub llm_latency: Arc<Histogram>,
     pub chain_executions: Arc<IntCounter>,
     pub token_usage: Arc<IntCounter>,
+    pub cache_hits: Arc<IntCounter>,
+    pub cache_misses: Arc<IntCounter>,
 }
 
 impl MetricsCollector {
@@ -42,12 +55,16 @@ impl MetricsCollector {
         REGISTRY.register(Box::new(LLM_LATENCY.clone())).ok();
         REGISTRY.register(Box::new(CHAIN_EXECUTIONS.clone())).ok();
         REGISTRY.register(Box::new(TOKEN_USAGE.clone())).ok();
+        REGISTRY.register(Box::new(CACHE_HITS.clone())).ok();
+        REGISTRY.register(Box::new(CACHE_MISSES.clone())).ok();
         
         Self {
             total_requests: Arc::new(TOTAL_REQUESTS.clone()),
             llm_latency: Arc::new(LLM_LATENCY.clone()),
             chain_executions: Arc::new(CHAIN_EXECUTIONS.clone()),
             token_usage: Arc::new(TOKEN_USAGE.clone()),
+            cache_hits: Arc::new(CACHE_HITS.clone()),
+            cache_misses: Arc::new(CACHE_MISSES.clone()),
         }
     }
     
@@ -67,6 +84,14 @@ impl MetricsCollector {
         self.token_usage.inc_by(tokens as u64);
     }
     
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.inc();
+    }
+    
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.inc();
+    }
+    
     pub fn get_metrics(&self) -> String {
         let encoder = TextEncoder::new();
         let metric_families = REGISTRY.gather();
@@ -80,6 +105,8 @@ impl MetricsCollector {
             total_requests: self.total_requests.get(),
             total_chain_executions: self.chain_executions.get(),
             total_tokens_used: self.token_usage.get(),
+            cache_hits: self.cache_hits.get(),
+            cache_misses: self.cache_misses.get(),
         }
     }
 }
@@ -95,4 +122,6 @@ pub struct MetricsStats {
     pub total_requests: u64,
     pub total_chain_executions: u64,
     pub total_tokens_used: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
 }
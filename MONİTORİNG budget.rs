@@ -0,0 +1,347 @@
+use anyhow::{Context, Result};
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use crate::config::{BudgetConfig, BudgetLimits, SpendLimit, TenantBudgetConfig};
+
+/// Tenant id for callers without an API key.
+pub const ANONYMOUS_TENANT: &str = "anonymous";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    Daily,
+    Monthly,
+}
+
+impl Period {
+    // Ledger days are ISO dates, so a period is everything from its first day on
+    fn start(&self, today: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Daily => today,
+            Period::Monthly => today.with_day(1).unwrap_or(today),
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Period::Daily => "daily",
+            Period::Monthly => "monthly",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitKind {
+    Cost,
+    Tokens,
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LimitKind::Cost => "cost",
+            LimitKind::Tokens => "token",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum BudgetError {
+    #[error("Missing or unknown API key")]
+    UnknownApiKey,
+    
+    #[error("{scope} has reached its {period} {kind} limit ({spent} of {limit})")]
+    Exceeded {
+        scope: String,
+        period: Period,
+        kind: LimitKind,
+        spent: f64,
+        limit: f64,
+    },
+}
+
+/// A caller the budgets apply to.
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub id: String,
+    pub limits: BudgetLimits,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Spend {
+    pub requests: u64,
+    pub tokens: u64,
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainSpend {
+    pub chain: String,
+    pub daily: Spend,
+    pub monthly: Spend,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpendReport {
+    pub tenant: String,
+    pub daily: Spend,
+    pub monthly: Spend,
+    pub limits: BudgetLimits,
+    pub chains: Vec<ChainSpend>,
+}
+
+/// Tracks tokens and cost per tenant and chain and enforces their limits.
+///
+/// Limits are checked before a request is dispatched, so the call that
+/// crosses a hard limit still completes; the next one is rejected.
+pub struct BudgetManager {
+    pool: SqlitePool,
+    config: BudgetConfig,
+    // API key -> tenant id
+    api_keys: HashMap<String, String>,
+}
+
+impl BudgetManager {
+    /// Opens the spend ledger and resolves each tenant's API key from its
+    /// environment variable. Tenants whose variable is unset are skipped.
+    pub async fn connect(url: &str, max_connections: u32, config: &BudgetConfig) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)
+            .with_context(|| format!("Invalid SQLite URL: {}", url))?
+            .create_if_missing(true);
+        
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await
+            .with_context(|| format!("Failed to open budget ledger at {}", url))?;
+        
+        // `chain` is empty for direct LLM calls
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS usage_ledger (
+                tenant TEXT NOT NULL,
+                chain TEXT NOT NULL,
+                day TEXT NOT NULL,
+                requests INTEGER NOT NULL,
+                tokens INTEGER NOT NULL,
+                cost REAL NOT NULL,
+                PRIMARY KEY (tenant, chain, day)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        
+        let mut api_keys = HashMap::new();
+        
+        for (id, tenant) in &config.tenants {
+            match std::env::var(&tenant.api_key_env) {
+                Ok(key) => {
+                    api_keys.insert(key, id.clone());
+                }
+                Err(_) => tracing::warn!("Skipping tenant '{}': {} is not set", id, tenant.api_key_env),
+            }
+        }
+        
+        Ok(Self {
+            pool,
+            config: config.clone(),
+            api_keys,
+        })
+    }
+    
+    /// Registers a tenant directly, without going through the environment.
+    pub fn with_tenant(mut self, api_key: impl Into<String>, id: impl Into<String>, limits: Option<BudgetLimits>) -> Self {
+        let id = id.into();
+        
+        self.config.tenants.insert(id.clone(), TenantBudgetConfig {
+            api_key_env: String::new(),
+            limits,
+        });
+        self.api_keys.insert(api_key.into(), id);
+        self
+    }
+    
+    /// Name of the header the API reads the caller's key from.
+    pub fn api_key_header(&self) -> &str {
+        &self.config.api_key_header
+    }
+    
+    pub fn resolve(&self, api_key: Option<&str>) -> Result<Tenant, BudgetError> {
+        let id = match api_key.and_then(|key| self.api_keys.get(key)) {
+            Some(id) => id.clone(),
+            None if self.config.require_api_key => return Err(BudgetError::UnknownApiKey),
+            None => ANONYMOUS_TENANT.to_string(),
+        };
+        
+        let limits = self.config.tenants
+            .get(&id)
+            .and_then(|tenant| tenant.limits.clone())
+            .unwrap_or_else(|| self.config.default_limits.clone());
+        
+        Ok(Tenant { id, limits })
+    }
+    
+    /// Fails with `BudgetError::Exceeded` if the tenant, or the chain when
+    /// given, has reached a hard limit.
+    pub async fn check(&self, tenant: &Tenant, chain: Option<&str>) -> Result<()> {
+        let today = today();
+        
+        self.enforce(&format!("Tenant '{}'", tenant.id), &tenant.limits, "tenant", &tenant.id, today)
+            .await?;
+        
+        if let Some(chain) = chain {
+            if let Some(limits) = self.config.chains.get(chain) {
+                self.enforce(&format!("Chain '{}'", chain), limits, "chain", chain, today).await?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn record(&self, tenant: &Tenant, chain: Option<&str>, tokens: usize, cost_usd: f64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO usage_ledger (tenant, chain, day, requests, tokens, cost) VALUES (?, ?, ?, 1, ?, ?)
+             ON CONFLICT (tenant, chain, day) DO UPDATE SET
+                requests = requests + 1,
+                tokens = tokens + excluded.tokens,
+                cost = cost + excluded.cost",
+        )
+        .bind(&tenant.id)
+        .bind(chain.unwrap_or_default())
+        .bind(today().to_string())
+        .bind(tokens as i64)
+        .bind(cost_usd)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    /// Current spend of a tenant, in total and per chain.
+    pub async fn report(&self, tenant: &Tenant) -> Result<SpendReport> {
+        let today = today();
+        
+        let mut chains: BTreeMap<String, ChainSpend> = BTreeMap::new();
+        
+        for period in [Period::Daily, Period::Monthly] {
+            let rows: Vec<(String, i64, i64, f64)> = sqlx::query_as(
+                "SELECT chain, SUM(requests), SUM(tokens), SUM(cost) FROM usage_ledger
+                 WHERE tenant = ? AND day >= ? AND chain != '' GROUP BY chain",
+            )
+            .bind(&tenant.id)
+            .bind(period.start(today).to_string())
+            .fetch_all(&self.pool)
+            .await?;
+            
+            for (chain, requests, tokens, cost_usd) in rows {
+                let entry = chains.entry(chain.clone()).or_insert_with(|| ChainSpend {
+                    chain,
+                    daily: Spend::default(),
+                    monthly: Spend::default(),
+                });
+                
+                let spend = Spend {
+                    requests: requests as u64,
+                    tokens: tokens as u64,
+                    cost_usd,
+                };
+                
+                match period {
+                    Period::Daily => entry.daily = spend,
+                    Period::Monthly => entry.monthly = spend,
+                }
+            }
+        }
+        
+        Ok(SpendReport {
+            tenant: tenant.id.clone(),
+            daily: self.spend("tenant", &tenant.id, Period::Daily.start(today)).await?,
+            monthly: self.spend("tenant", &tenant.id, Period::Monthly.start(today)).await?,
+            limits: tenant.limits.clone(),
+            chains: chains.into_values().collect(),
+        })
+    }
+    
+    async fn enforce(&self, scope: &str, limits: &BudgetLimits, column: &str, value: &str, today: NaiveDate) -> Result<()> {
+        for (period, limit) in [(Period::Daily, &limits.daily), (Period::Monthly, &limits.monthly)] {
+            if is_unlimited(limit) {
+                continue;
+            }
+            
+            let spend = self.spend(column, value, period.start(today)).await?;
+            check_limit(scope, period, limit, &spend)?;
+        }
+        
+        Ok(())
+    }
+    
+    // `column` is always "tenant" or "chain", never user input
+    async fn spend(&self, column: &str, value: &str, since: NaiveDate) -> Result<Spend> {
+        let (requests, tokens, cost_usd): (i64, i64, f64) = sqlx::query_as(&format!(
+            "SELECT COALESCE(SUM(requests), 0), COALESCE(SUM(tokens), 0), COALESCE(SUM(cost), 0.0)
+             FROM usage_ledger WHERE {} = ? AND day >= ?",
+            column
+        ))
+        .bind(value)
+        .bind(since.to_string())
+        .fetch_one(&self.pool)
+        .await?;
+        
+        Ok(Spend {
+            requests: requests as u64,
+            tokens: tokens as u64,
+            cost_usd,
+        })
+    }
+}
+
+fn today() -> NaiveDate {
+    chrono::Utc::now().date_naive()
+}
+
+fn is_unlimited(limit: &SpendLimit) -> bool {
+    limit.soft_cost_usd.is_none()
+        && limit.hard_cost_usd.is_none()
+        && limit.soft_tokens.is_none()
+        && limit.hard_tokens.is_none()
+}
+
+fn check_limit(scope: &str, period: Period, limit: &SpendLimit, spend: &Spend) -> Result<(), BudgetError> {
+    let exceeded = |kind, spent, limit| BudgetError::Exceeded {
+        scope: scope.to_string(),
+        period,
+        kind,
+        spent,
+        limit,
+    };
+    
+    if let Some(hard) = limit.hard_cost_usd.filter(|hard| spend.cost_usd >= *hard) {
+        return Err(exceeded(LimitKind::Cost, spend.cost_usd, hard));
+    }
+    
+    if let Some(hard) = limit.hard_tokens.filter(|hard| spend.tokens >= *hard) {
+        return Err(exceeded(LimitKind::Tokens, spend.tokens as f64, hard as f64));
+    }
+    
+    let soft_cost = limit.soft_cost_usd.is_some_and(|soft| spend.cost_usd >= soft);
+    let soft_tokens = limit.soft_tokens.is_some_and(|soft| spend.tokens >= soft);
+    
+    if soft_cost || soft_tokens {
+        tracing::warn!(
+            "{} is over its {} soft limit: {} tokens, ${:.4}",
+            scope,
+            period,
+            spend.tokens,
+            spend.cost_usd
+        );
+    }
+    
+    Ok(())
+}
@@ -21,7 +21,7 @@ impl TextChunker {
         let mut chunk_index = 0;
         
         while start < text.len() {
-            let end = std::cmp::min(start + self.chunk_size, text.len());
+            let end = char_boundary(text, std::cmp::min(start + self.chunk_size, text.len()));
             let chunk_text = &text[start..end];
             
             let chunk = Chunk {
@@ -38,7 +38,7 @@ impl TextChunker {
                 break;
             }
             
-            start += self.chunk_size - self.chunk_overlap;
+            start = char_boundary(text, start + self.chunk_size - self.chunk_overlap);
             chunk_index += 1;
         }
         
@@ -91,3 +91,12 @@ impl TextChunker {
         Ok(chunks)
     }
 }
+
+// Sizes are in bytes; moves `index` forward to the next character boundary
+// so multi-byte characters aren't split
+fn char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
@@ -1,28 +1,1622 @@
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use chain_forge::agents::executor::AgentExecutor;
+    use chain_forge::agents::{Tool, ToolOutput, ToolParameters};
+    use chain_forge::chains::parser::{EnumOutputParser, JsonOutputParser, ListOutputParser, OutputParser, RegexOutputParser};
+    use chain_forge::chains::definition::{self, ChainLoader};
+    use chain_forge::chains::graph::GraphChain;
+    use chain_forge::chains::few_shot::{
+        Example, ExampleSelector, FewShotChatMessages, FewShotPromptTemplate, LengthBasedExampleSelector,
+        SemanticSimilarityExampleSelector,
+    };
+    use chain_forge::chains::manager::ChainManager;
+    use chain_forge::chains::pipeline::RAGPipeline;
+    use chain_forge::chains::router::RouterChain;
+    use chain_forge::chains::prompt::{ChatPromptTemplate, PromptError, PromptTemplate};
+    use chain_forge::chains::sequential::{SequentialChain, SequentialStep};
+    use chain_forge::chains::simple::SimpleChain;
+    use chain_forge::chains::summarize::{MapReduceChain, RefineChain};
+    use chain_forge::chains::{Chain, ChainInput, ChainSpend};
+    use chain_forge::config::{
+        BudgetConfig, BudgetLimits, CacheConfig, CassetteConfig, CassetteMode, CircuitBreakerConfig, HttpConfig, RoutingStrategy,
+        SpendLimit,
+    };
+    use chain_forge::embeddings::EmbeddingProvider;
+    use chain_forge::llm::anthropic::AnthropicProvider;
+    use chain_forge::llm::cache::{CachedProvider, InMemoryCache};
+    use chain_forge::llm::error::LLMError;
+    use chain_forge::llm::huggingface::HuggingFaceProvider;
+    use chain_forge::llm::mock::{MockProvider, MockReply};
+    use chain_forge::llm::ollama::OllamaProvider;
+    use chain_forge::llm::openai::OpenAIProvider;
+    use chain_forge::llm::pricing::{ModelPrice, PricingTable};
+    use chain_forge::llm::router::{RouteTarget, RouterProvider};
+    use chain_forge::llm::semantic_cache::SemanticCache;
+    use chain_forge::llm::structured::{self, ResponseFormat, StructuredOutputError};
+    use chain_forge::llm::{streaming, tools, ChatMessage, GenerationParams, LLMProvider, LLMRequest, TokenUsage, ToolCall, ToolDefinition};
+    use chain_forge::memory::vector::InMemoryVectorMemory;
+    use chain_forge::memory::{MessageRole, SearchResult, VectorMemory};
+    use chain_forge::monitoring::budget::{BudgetError, BudgetManager, LimitKind, ANONYMOUS_TENANT};
+    use chain_forge::rag::retriever::Retriever;
+    use chain_forge::rag::Document;
+    use parking_lot::Mutex;
+    use regex::Regex;
+    use std::sync::Arc;
     
     #[tokio::test]
     async fn test_chain_execution() {
-        // TODO: Add chain execution tests
-        assert!(true);
+        let mock = Arc::new(
+            MockProvider::new()
+                .on_prompt("Answer the following question: What is Rust?", MockReply::text("A systems language.")),
+        );
+        
+        let chain = SimpleChain::new(
+            "qa_chain",
+            "Simple question-answering chain",
+            mock.clone(),
+            PromptTemplate::new("Answer the following question: {question}").unwrap(),
+        );
+        
+        let input = ChainInput::new().with_variable("question", serde_json::json!("What is Rust?"));
+        let output = chain.execute(input).await.unwrap();
+        
+        assert_eq!(output.result["output"], "A systems language.");
+        assert_eq!(output.metadata.steps.len(), 1);
+        assert_eq!(
+            mock.last_request().unwrap().prompt,
+            "Answer the following question: What is Rust?"
+        );
     }
     
     #[tokio::test]
     async fn test_llm_provider() {
-        // TODO: Add LLM provider tests
-        assert!(true);
+        let mock = MockProvider::new()
+            .on_prompt("ping", MockReply::text("pong"))
+            .on_regex(Regex::new("(?i)weather").unwrap(), MockReply::text("Sunny"))
+            .with_default_reply(MockReply::text("fallback"))
+            .with_sequence([MockReply::text("first"), MockReply::text("second")]);
+        
+        // Queued replies win over every rule until they run out
+        assert_eq!(mock.generate(&LLMRequest::new("ping")).await.unwrap().text, "first");
+        assert_eq!(mock.generate(&LLMRequest::new("ping")).await.unwrap().text, "second");
+        
+        assert_eq!(mock.generate(&LLMRequest::new("ping")).await.unwrap().text, "pong");
+        assert_eq!(mock.generate(&LLMRequest::new("What's the Weather?")).await.unwrap().text, "Sunny");
+        assert_eq!(mock.generate(&LLMRequest::new("anything else")).await.unwrap().text, "fallback");
+        
+        let response = mock.generate(&LLMRequest::new("ping").with_model("mock-large")).await.unwrap();
+        assert_eq!(response.model, "mock-large");
+        assert!(response.tokens_used.prompt_tokens > 0);
+        assert_eq!(
+            response.tokens_used.total_tokens,
+            response.tokens_used.prompt_tokens + response.tokens_used.completion_tokens
+        );
+        
+        assert_eq!(mock.requests().len(), 6);
+    }
+    
+    #[tokio::test]
+    async fn test_llm_provider_errors() {
+        let mock = MockProvider::new().with_sequence([MockReply::Error(LLMError::RateLimited {
+            provider: "mock".to_string(),
+            message: "slow down".to_string(),
+            retry_after: None,
+        })]);
+        
+        let error = mock.generate(&LLMRequest::new("hello")).await.unwrap_err();
+        
+        assert!(matches!(LLMError::find(&error), Some(LLMError::RateLimited { .. })));
+        assert!(mock.generate(&LLMRequest::new("hello")).await.is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_llm_provider_streaming() {
+        let mock = MockProvider::new().with_default_reply(MockReply::text("one two three"));
+        
+        let stream = mock.stream_generate(&LLMRequest::new("count")).await.unwrap();
+        
+        let mut deltas = Vec::new();
+        let response = streaming::collect(stream, std::time::Instant::now(), |delta| deltas.push(delta.to_string()))
+            .await
+            .unwrap();
+        
+        assert_eq!(deltas, vec!["one ", "two ", "three"]);
+        assert_eq!(response.text, "one two three");
+        assert_eq!(response.finish_reason, "stop");
+    }
+    
+    #[tokio::test]
+    async fn test_response_cache() {
+        let pricing = Arc::new(PricingTable::new(vec![ModelPrice {
+            provider: "mock".to_string(),
+            model: "mock".to_string(),
+            input: 1.0,
+            output: 1.0,
+            cached_input: None,
+            effective_from: None,
+        }]));
+        let mock = Arc::new(MockProvider::new().with_default_reply(MockReply::text("cached")).with_pricing(pricing));
+        
+        let cache = CachedProvider::new("mock", mock.clone(), Arc::new(InMemoryCache::new(1)));
+        let request = LLMRequest::new("hello").with_temperature(0.0);
+        
+        let first = cache.generate(&request).await.unwrap();
+        assert!(!first.cached);
+        assert!(first.cost > 0.0);
+        
+        // A hit is served without calling the provider and costs nothing
+        let hit = cache.generate(&request).await.unwrap();
+        assert_eq!(hit.text, "cached");
+        assert!(hit.cached);
+        assert_eq!(hit.cost, 0.0);
+        assert_eq!(hit.billed_tokens(), 0);
+        assert_eq!(mock.requests().len(), 1);
+        
+        let stream = cache.stream_generate(&request).await.unwrap();
+        let replayed = streaming::collect(stream, std::time::Instant::now(), |_| {}).await.unwrap();
+        assert!(replayed.cached);
+        assert_eq!(replayed.cost, 0.0);
+        assert_eq!(mock.requests().len(), 1);
+        
+        // Capacity 1: a second prompt evicts the first
+        cache.generate(&LLMRequest::new("other").with_temperature(0.0)).await.unwrap();
+        assert!(!cache.generate(&request).await.unwrap().cached);
+        assert_eq!(mock.requests().len(), 3);
+        
+        // Sampled requests (temperature unset or above zero) bypass the cache
+        cache.generate(&LLMRequest::new("hello")).await.unwrap();
+        assert!(!cache.generate(&LLMRequest::new("hello").with_temperature(0.7)).await.unwrap().cached);
+        assert_eq!(mock.requests().len(), 5);
+        
+        // Entries expire after the configured ttl
+        let expiring = CachedProvider::new("mock", mock.clone(), Arc::new(InMemoryCache::new(10)))
+            .with_config(&CacheConfig { ttl_seconds: 0, ..CacheConfig::default() });
+        expiring.generate(&request).await.unwrap();
+        assert!(!expiring.generate(&request).await.unwrap().cached);
+        assert_eq!(mock.requests().len(), 7);
+    }
+    
+    fn unavailable() -> MockReply {
+        MockReply::Error(LLMError::Transient {
+            provider: "mock".to_string(),
+            message: "unavailable".to_string(),
+            retry_after: None,
+        })
+    }
+    
+    #[tokio::test]
+    async fn test_router_fallback() {
+        let primary = Arc::new(MockProvider::new().with_default_reply(MockReply::text("primary")).with_sequence([unavailable()]));
+        let secondary = Arc::new(MockProvider::new().with_default_reply(MockReply::text("secondary")));
+        
+        let router = RouterProvider::new("chain", RoutingStrategy::Fallback, vec![
+            RouteTarget::new("primary", primary.clone(), None, 1),
+            RouteTarget::new("secondary", secondary.clone(), None, 1),
+        ]);
+        
+        let response = router.generate(&LLMRequest::new("hello")).await.unwrap();
+        assert_eq!(response.text, "secondary");
+        assert_eq!(response.model, "secondary/mock");
+        
+        // One failure is below the threshold, so the primary is tried first again
+        assert_eq!(router.generate(&LLMRequest::new("hello")).await.unwrap().text, "primary");
+        assert_eq!(primary.requests().len(), 2);
+        assert_eq!(secondary.requests().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_router_round_robin() {
+        let first = Arc::new(MockProvider::new().with_default_reply(MockReply::text("first")));
+        let second = Arc::new(MockProvider::new().with_default_reply(MockReply::text("second")));
+        
+        let router = RouterProvider::new("balanced", RoutingStrategy::RoundRobin, vec![
+            RouteTarget::new("first", first.clone(), None, 1),
+            RouteTarget::new("second", second.clone(), None, 1),
+        ]);
+        
+        let mut texts = Vec::new();
+        for _ in 0..4 {
+            texts.push(router.generate(&LLMRequest::new("hello")).await.unwrap().text);
+        }
+        
+        assert_eq!(texts, vec!["first", "second", "first", "second"]);
+    }
+    
+    #[tokio::test]
+    async fn test_router_circuit_breaker() {
+        let primary = Arc::new(
+            MockProvider::new()
+                .with_default_reply(MockReply::text("primary"))
+                .with_sequence([unavailable(), unavailable(), unavailable()]),
+        );
+        let secondary = Arc::new(MockProvider::new().with_default_reply(MockReply::text("secondary")));
+        
+        let router = RouterProvider::new("chain", RoutingStrategy::Fallback, vec![
+            RouteTarget::new("primary", primary.clone(), None, 1),
+            RouteTarget::new("secondary", secondary.clone(), None, 1),
+        ])
+        .with_circuit_breaker(CircuitBreakerConfig { failure_threshold: 2, cooldown_seconds: 1 });
+        
+        let request = LLMRequest::new("hello");
+        
+        // Two failures in a row open the breaker: the primary is skipped
+        for _ in 0..3 {
+            assert_eq!(router.generate(&request).await.unwrap().text, "secondary");
+        }
+        assert_eq!(primary.requests().len(), 2);
+        
+        // Half-open after the cooldown: one more failure ejects it again
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(router.generate(&request).await.unwrap().text, "secondary");
+        assert_eq!(router.generate(&request).await.unwrap().text, "secondary");
+        assert_eq!(primary.requests().len(), 3);
+        
+        // A success while half-open closes the breaker
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(router.generate(&request).await.unwrap().text, "primary");
+        assert_eq!(router.generate(&request).await.unwrap().text, "primary");
+        assert_eq!(primary.requests().len(), 5);
+    }
+    
+    #[tokio::test]
+    async fn test_cassette_replay() {
+        let cassette = cassette_file(serde_json::json!([{
+            "method": "POST",
+            "url": "http://localhost:11434/api/chat",
+            // Key order differs from what the provider sends
+            "request_body": {
+                "stream": false,
+                "options": { "num_predict": 2048, "temperature": 0.7f32 },
+                "messages": [{ "content": "Why is the sky blue?", "role": "user" }],
+                "model": "llama2",
+            },
+            "status": 200,
+            "content_type": "application/json",
+            "response_body": serde_json::json!({
+                "message": { "role": "assistant", "content": "Rayleigh scattering." },
+                "prompt_eval_count": 12,
+                "eval_count": 4,
+                "done": true,
+            })
+            .to_string(),
+        }]));
+        
+        let ollama = OllamaProvider::new("http://localhost:11434".to_string(), "llama2".to_string())
+            .unwrap()
+            .with_http_config(&replay_config(&cassette))
+            .unwrap();
+        
+        let response = ollama.generate(&LLMRequest::new("Why is the sky blue?")).await.unwrap();
+        
+        assert_eq!(response.text, "Rayleigh scattering.");
+        assert_eq!(response.tokens_used.prompt_tokens, 12);
+        assert_eq!(response.tokens_used.completion_tokens, 4);
+        
+        // Each interaction answers once
+        assert!(ollama.generate(&LLMRequest::new("Why is the sky blue?")).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_cassette_unmatched_request() {
+        let cassette = cassette_file(serde_json::json!([]));
+        
+        let ollama = OllamaProvider::new("http://localhost:11434".to_string(), "llama2".to_string())
+            .unwrap()
+            .with_http_config(&replay_config(&cassette))
+            .unwrap();
+        
+        let error = ollama.generate(&LLMRequest::new("hello")).await.unwrap_err();
+        
+        assert!(LLMError::find(&error).is_none());
+        assert!(error.to_string().contains("POST http://localhost:11434/api/chat"));
+    }
+    
+    #[tokio::test]
+    async fn test_anthropic_provider() {
+        let request_body = |stream: bool| {
+            serde_json::json!({
+                "model": "claude-3-5-haiku-latest",
+                "messages": [{ "role": "user", "content": [{ "type": "text", "text": "Weather in Paris?" }] }],
+                "max_tokens": 256,
+                "stream": stream,
+                "tools": [{
+                    "name": "get_weather",
+                    "description": "Current weather for a city",
+                    "input_schema": { "type": "object", "properties": { "city": { "type": "string" } } },
+                }],
+            })
+        };
+        
+        let events = [
+            serde_json::json!({
+                "type": "message_start",
+                "message": {
+                    "usage": { "input_tokens": 8, "cache_read_input_tokens": 8, "cache_creation_input_tokens": 4, "output_tokens": 1 },
+                },
+            }),
+            serde_json::json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } }),
+            serde_json::json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "Checking." } }),
+            serde_json::json!({ "type": "content_block_stop", "index": 0 }),
+            serde_json::json!({
+                "type": "content_block_start",
+                "index": 1,
+                "content_block": { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {} },
+            }),
+            serde_json::json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "{\"city\": " } }),
+            serde_json::json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "\"Paris\"}" } }),
+            serde_json::json!({ "type": "content_block_stop", "index": 1 }),
+            serde_json::json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" }, "usage": { "output_tokens": 10 } }),
+            serde_json::json!({ "type": "message_stop" }),
+        ];
+        let sse_body: String = events
+            .iter()
+            .map(|event| format!("event: {}\ndata: {}\n\n", event["type"].as_str().unwrap(), event))
+            .collect();
+        
+        let cassette = cassette_file(serde_json::json!([
+            {
+                "method": "POST",
+                "url": "https://api.anthropic.com/v1/messages",
+                "request_body": request_body(false),
+                "status": 200,
+                "content_type": "application/json",
+                "response_body": serde_json::json!({
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude-3-5-haiku-latest",
+                    "content": [
+                        { "type": "text", "text": "Checking." },
+                        { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": { "city": "Paris" } },
+                    ],
+                    "stop_reason": "tool_use",
+                    "usage": { "input_tokens": 8, "cache_read_input_tokens": 8, "cache_creation_input_tokens": 4, "output_tokens": 10 },
+                })
+                .to_string(),
+            },
+            {
+                "method": "POST",
+                "url": "https://api.anthropic.com/v1/messages",
+                "request_body": request_body(true),
+                "status": 200,
+                "content_type": "text/event-stream",
+                "response_body": sse_body,
+            },
+            {
+                "method": "POST",
+                "url": "https://api.anthropic.com/v1/messages",
+                "request_body": request_body(true),
+                "status": 200,
+                "content_type": "text/event-stream",
+                "response_body": "event: error\ndata: {\"type\": \"error\", \"error\": {\"type\": \"overloaded_error\", \"message\": \"Overloaded\"}}\n\n",
+            },
+        ]));
+        
+        let anthropic = AnthropicProvider::new("sk-ant-test".to_string(), "claude-3-5-haiku-latest".to_string(), 256)
+            .unwrap()
+            .with_http_config(&replay_config(&cassette))
+            .unwrap();
+        let request = LLMRequest::new("Weather in Paris?").with_tools(vec![ToolDefinition {
+            name: "get_weather".to_string(),
+            description: "Current weather for a city".to_string(),
+            parameters: serde_json::json!({ "type": "object", "properties": { "city": { "type": "string" } } }),
+        }]);
+        
+        let response = anthropic.generate(&request).await.unwrap();
+        let stream = anthropic.stream_generate(&request).await.unwrap();
+        let streamed = streaming::collect(stream, std::time::Instant::now(), |_| {}).await.unwrap();
+        
+        for response in [response, streamed] {
+            assert_eq!(response.text, "Checking.");
+            assert_eq!(response.finish_reason, "tool_calls");
+            assert_eq!(response.tokens_used.prompt_tokens, 20);
+            assert_eq!(response.tokens_used.cached_tokens, 8);
+            assert_eq!(response.tokens_used.completion_tokens, 10);
+            assert_eq!(response.tool_calls.len(), 1);
+            assert_eq!(response.tool_calls[0].id, "toolu_1");
+            assert_eq!(response.tool_calls[0].name, "get_weather");
+            assert_eq!(response.tool_calls[0].arguments, serde_json::json!({ "city": "Paris" }));
+        }
+        
+        // An error event mid-stream is classified like the HTTP status it stands for
+        let stream = anthropic.stream_generate(&request).await.unwrap();
+        let error = streaming::collect(stream, std::time::Instant::now(), |_| {}).await.unwrap_err();
+        assert!(matches!(LLMError::find(&error), Some(LLMError::Transient { .. })));
+    }
+    
+    #[tokio::test]
+    async fn test_openai_streamed_tool_calls() {
+        let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| {
+            serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "gpt-4o-mini",
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+            })
+        };
+        let arguments = |index: u32, fragment: &str| {
+            serde_json::json!({ "tool_calls": [{ "index": index, "function": { "arguments": fragment } }] })
+        };
+        
+        // Two calls, their argument fragments interleaved; the last chunk
+        // carries text along with the finish reason
+        let chunks = [
+            chunk(serde_json::json!({
+                "role": "assistant",
+                "tool_calls": [{ "index": 0, "id": "call_1", "type": "function", "function": { "name": "get_weather", "arguments": "" } }],
+            }), None),
+            chunk(arguments(0, "{\"city\": "), None),
+            chunk(serde_json::json!({
+                "tool_calls": [{ "index": 1, "id": "call_2", "type": "function", "function": { "name": "get_weather", "arguments": "{\"city\"" } }],
+            }), None),
+            chunk(arguments(0, "\"Paris\"}"), None),
+            chunk(arguments(1, ": \"Rome\"}"), None),
+            chunk(serde_json::json!({ "content": "Checking both." }), Some("tool_calls")),
+        ];
+        let sse_body: String = chunks
+            .iter()
+            .map(|chunk| format!("data: {}\n\n", chunk))
+            .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+            .collect();
+        
+        let cassette = cassette_file(serde_json::json!([{
+            "method": "POST",
+            "url": "https://api.openai.com/v1/chat/completions",
+            "request_body": {
+                "model": "gpt-4o-mini",
+                "messages": [{ "role": "user", "content": "Weather in Paris and Rome?" }],
+                "temperature": 0.0,
+                "max_tokens": 64,
+                "stream": true,
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "get_weather",
+                        "description": "Current weather for a city",
+                        "parameters": weather_tool().parameters,
+                    },
+                }],
+            },
+            "status": 200,
+            "content_type": "text/event-stream",
+            "response_body": sse_body,
+        }]));
+        
+        let openai = OpenAIProvider::new("sk-test".to_string(), "gpt-4o-mini".to_string(), 0.0, 64)
+            .unwrap()
+            .with_http_config(&replay_config(&cassette))
+            .unwrap();
+        let request = LLMRequest::new("Weather in Paris and Rome?").with_tools(vec![weather_tool()]);
+        
+        let stream = openai.stream_generate(&request).await.unwrap();
+        let response = streaming::collect(stream, std::time::Instant::now(), |_| {}).await.unwrap();
+        
+        assert_eq!(response.text, "Checking both.");
+        assert_eq!(response.finish_reason, "tool_calls");
+        assert_eq!(response.tool_calls.len(), 2);
+        assert_eq!(response.tool_calls[0].id, "call_1");
+        assert_eq!(response.tool_calls[0].arguments, serde_json::json!({ "city": "Paris" }));
+        assert_eq!(response.tool_calls[1].id, "call_2");
+        assert_eq!(response.tool_calls[1].arguments, serde_json::json!({ "city": "Rome" }));
+    }
+    
+    #[tokio::test]
+    async fn test_ollama_streamed_tool_calls() {
+        let lines = [
+            serde_json::json!({
+                "model": "llama3.1",
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{ "function": { "name": "get_weather", "arguments": { "city": "Paris" } } }],
+                },
+                "done": false,
+            }),
+            serde_json::json!({
+                "model": "llama3.1",
+                "message": { "role": "assistant", "content": "" },
+                "done_reason": "stop",
+                "done": true,
+                "prompt_eval_count": 20,
+                "eval_count": 10,
+            }),
+        ];
+        
+        let cassette = cassette_file(serde_json::json!([{
+            "method": "POST",
+            "url": "http://localhost:11434/api/chat",
+            "request_body": {
+                "model": "llama3.1",
+                "messages": [{ "role": "user", "content": "Weather in Paris?" }],
+                "stream": true,
+                "options": { "num_predict": 2048, "temperature": 0.7f32 },
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "get_weather",
+                        "description": "Current weather for a city",
+                        "parameters": weather_tool().parameters,
+                    },
+                }],
+            },
+            "status": 200,
+            "content_type": "application/x-ndjson",
+            "response_body": lines.iter().map(|line| format!("{}\n", line)).collect::<String>(),
+        }]));
+        
+        let ollama = OllamaProvider::new("http://localhost:11434".to_string(), "llama3.1".to_string())
+            .unwrap()
+            .with_http_config(&replay_config(&cassette))
+            .unwrap();
+        let request = LLMRequest::new("Weather in Paris?").with_tools(vec![weather_tool()]);
+        
+        let stream = ollama.stream_generate(&request).await.unwrap();
+        let response = streaming::collect(stream, std::time::Instant::now(), |_| {}).await.unwrap();
+        
+        assert_eq!(response.finish_reason, "tool_calls");
+        assert_eq!(response.tokens_used.prompt_tokens, 20);
+        assert_eq!(response.tool_calls.len(), 1);
+        assert_eq!(response.tool_calls[0].name, "get_weather");
+        assert_eq!(response.tool_calls[0].arguments, serde_json::json!({ "city": "Paris" }));
+    }
+    
+    #[tokio::test]
+    async fn test_huggingface_streamed_tool_calls() {
+        let request = LLMRequest::new("Weather in Paris?").with_tools(vec![weather_tool()]);
+        
+        // Tools are described in the system prompt, rendered as ChatML
+        let system = tools::with_text_protocol(&request).system_message.unwrap();
+        let inputs = format!(
+            "<|im_start|>system\n{}<|im_end|>\n<|im_start|>user\nWeather in Paris?<|im_end|>\n<|im_start|>assistant\n",
+            system
+        );
+        
+        let tokens = ["{\"name\": ", "\"get_weather\", ", "\"arguments\": ", "{\"city\": \"Paris\"}}"];
+        let sse_body: String = tokens
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                // The last frame carries the finish reason
+                let details = if i == tokens.len() - 1 {
+                    serde_json::json!({ "finish_reason": "eos_token", "generated_tokens": tokens.len() })
+                } else {
+                    serde_json::Value::Null
+                };
+                let frame = serde_json::json!({
+                    "token": { "id": i, "text": text, "logprob": 0.0, "special": false },
+                    "details": details,
+                });
+                format!("data: {}\n\n", frame)
+            })
+            .collect();
+        
+        let cassette = cassette_file(serde_json::json!([{
+            "method": "POST",
+            "url": "https://api-inference.huggingface.co/models/HuggingFaceH4/zephyr-7b-beta",
+            "request_body": {
+                "inputs": inputs,
+                "stream": true,
+                "parameters": {
+                    "temperature": 0.7f32,
+                    "max_new_tokens": 2048,
+                    "top_p": 1.0f32,
+                    "return_full_text": false,
+                },
+            },
+            "status": 200,
+            "content_type": "text/event-stream",
+            "response_body": sse_body,
+        }]));
+        
+        let huggingface = HuggingFaceProvider::new("hf-test".to_string(), "HuggingFaceH4/zephyr-7b-beta".to_string())
+            .unwrap()
+            .with_http_config(&replay_config(&cassette))
+            .unwrap();
+        
+        let stream = huggingface.stream_generate(&request).await.unwrap();
+        let response = streaming::collect(stream, std::time::Instant::now(), |_| {}).await.unwrap();
+        
+        assert_eq!(response.finish_reason, "tool_calls");
+        assert_eq!(response.tool_calls.len(), 1);
+        assert_eq!(response.tool_calls[0].name, "get_weather");
+        assert_eq!(response.tool_calls[0].arguments, serde_json::json!({ "city": "Paris" }));
+    }
+    
+    #[tokio::test]
+    async fn test_unreadable_response() {
+        let cassette = cassette_file(serde_json::json!([{
+            "method": "POST",
+            "url": "http://localhost:11434/api/chat",
+            "request_body": {
+                "model": "llama2",
+                "messages": [{ "role": "user", "content": "hello" }],
+                "stream": false,
+                "options": { "num_predict": 2048, "temperature": 0.7f32 },
+            },
+            "status": 200,
+            "content_type": "application/json",
+            "response_body": "{\"message\": ",
+        }]));
+        
+        let ollama = OllamaProvider::new("http://localhost:11434".to_string(), "llama2".to_string())
+            .unwrap()
+            .with_http_config(&replay_config(&cassette))
+            .unwrap();
+        
+        // Not retried: a second attempt would have found no interaction
+        let error = ollama.generate(&LLMRequest::new("hello")).await.unwrap_err();
+        assert!(matches!(LLMError::find(&error), Some(LLMError::InvalidResponse { .. })));
+    }
+    
+    #[tokio::test]
+    async fn test_openai_error_classification() {
+        let request_body = serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [{ "role": "user", "content": "hello" }],
+            "temperature": 0.0,
+            "max_tokens": 16,
+        });
+        let interaction = |status: u16, response_body: serde_json::Value| {
+            serde_json::json!({
+                "method": "POST",
+                "url": "https://api.openai.com/v1/chat/completions",
+                "request_body": request_body,
+                "status": status,
+                "content_type": "application/json",
+                "response_body": response_body.to_string(),
+            })
+        };
+        let openai_error = |code: &str| {
+            serde_json::json!({ "error": { "message": code, "type": code, "code": code } })
+        };
+        
+        let cassette = cassette_file(serde_json::json!([
+            interaction(429, openai_error("insufficient_quota")),
+            interaction(429, openai_error("rate_limit_exceeded")),
+            interaction(200, serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "gpt-4o-mini",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "hi" },
+                    "finish_reason": "stop",
+                }],
+                "usage": { "prompt_tokens": 8, "completion_tokens": 1, "total_tokens": 9 },
+            })),
+        ]));
+        
+        let openai = OpenAIProvider::new("sk-test".to_string(), "gpt-4o-mini".to_string(), 0.0, 16)
+            .unwrap()
+            .with_http_config(&replay_config(&cassette))
+            .unwrap();
+        
+        // An exhausted quota is final; a retry would have found no interaction
+        let error = openai.generate(&LLMRequest::new("hello")).await.unwrap_err();
+        assert!(matches!(LLMError::find(&error), Some(LLMError::InvalidRequest { status: 429, .. })));
+        
+        // A rate limit is retried
+        let response = openai.generate(&LLMRequest::new("hello")).await.unwrap();
+        assert_eq!(response.text, "hi");
+        assert_eq!(response.finish_reason, "stop");
+    }
+    
+    #[tokio::test]
+    async fn test_openai_compatible_server() {
+        let cassette = cassette_file(serde_json::json!([{
+            "method": "POST",
+            "url": "http://localhost:8000/v1/chat/completions",
+            "request_body": {
+                "model": "local-model",
+                "messages": [
+                    { "role": "system", "content": "Answer briefly." },
+                    { "role": "user", "content": "Capital of France?" },
+                    { "role": "assistant", "content": "Paris." },
+                    { "role": "user", "content": "And of Italy?" },
+                ],
+                "temperature": 0.0,
+                "max_tokens": 32,
+            },
+            "status": 200,
+            "content_type": "application/json",
+            // No `usage`, as some servers leave it out
+            "response_body": serde_json::json!({
+                "id": "cmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "local-model",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "Rome." },
+                    "finish_reason": "stop",
+                }],
+            })
+            .to_string(),
+        }]));
+        
+        // The name survives rebuilding the client, so the cassette still applies
+        let local = OpenAIProvider::new("none".to_string(), "local-model".to_string(), 0.0, 32)
+            .unwrap()
+            .with_api_base("http://localhost:8000/v1/")
+            .with_http_config(&replay_config(&cassette))
+            .unwrap()
+            .with_name("local")
+            .unwrap();
+        
+        let request = LLMRequest::new("And of Italy?")
+            .with_system_message("Answer briefly.")
+            .with_messages(vec![ChatMessage::user("Capital of France?"), ChatMessage::assistant("Paris.")]);
+        
+        let response = local.generate(&request).await.unwrap();
+        assert_eq!(response.text, "Rome.");
+        assert_eq!(response.finish_reason, "stop");
+        
+        // Usage is estimated with the tokenizer instead
+        assert_eq!(response.tokens_used.prompt_tokens, local.count_prompt_tokens(&request).unwrap());
+        assert_eq!(response.tokens_used.completion_tokens, local.count_tokens("Rome.").unwrap());
+        assert!(response.tokens_used.prompt_tokens > 0);
+        
+        // Errors name the server rather than OpenAI
+        let error = local.generate(&request).await.unwrap_err();
+        assert!(error.to_string().contains("Failed to call local API"));
+    }
+    
+    #[tokio::test]
+    async fn test_structured_output() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" }, "age": { "type": "integer" } },
+            "required": ["name", "age"],
+        });
+        
+        let pricing = PricingTable::new(vec![ModelPrice {
+            provider: "mock".to_string(),
+            model: "mock".to_string(),
+            input: 1.0,
+            output: 1.0,
+            cached_input: None,
+            effective_from: None,
+        }]);
+        
+        let mock = MockProvider::new()
+            .with_pricing(Arc::new(pricing))
+            .with_sequence([
+                MockReply::text(r#"{"name": "Ada"}"#),
+                MockReply::text("Here you go:\n```json\n{\"name\": \"Ada\", \"age\": 36}\n```"),
+            ]);
+        
+        let request = LLMRequest::new("Extract the person from: Ada, 36")
+            .with_response_format(ResponseFormat::json_schema("person", schema.clone()));
+        
+        let structured = structured::generate(&mock, &request, 1).await.unwrap();
+        
+        assert_eq!(structured.value, serde_json::json!({ "name": "Ada", "age": 36 }));
+        assert_eq!(structured.attempts, 2);
+        
+        // The repair turn carries the invalid reply and the validation error
+        let repair = mock.last_request().unwrap();
+        assert_eq!(repair.messages.len(), 3);
+        assert!(repair.messages[2].content.contains("age"));
+        
+        let first = &mock.requests()[0];
+        assert_eq!(
+            structured.response.tokens_used.prompt_tokens,
+            mock.count_prompt_tokens(first).unwrap() + mock.count_prompt_tokens(&repair).unwrap()
+        );
+        
+        // Every attempt is paid for, at $1 per 1M tokens
+        let per_token = 1.0 / 1_000_000.0;
+        assert!((structured.response.cost - structured.response.tokens_used.total_tokens as f64 * per_token).abs() < 1e-12);
+        
+        // Failed attempts keep their spend for billing
+        mock.push_reply(MockReply::text("no JSON here"));
+        mock.push_reply(MockReply::text("still none"));
+        let error = structured::generate(&mock, &request, 1).await.unwrap_err();
+        let failed = error.downcast_ref::<StructuredOutputError>().unwrap();
+        assert_eq!(failed.attempts, 2);
+        assert!(failed.cost > 0.0);
+        assert!((failed.cost - failed.tokens_used.total_tokens as f64 * per_token).abs() < 1e-12);
+    }
+    
+    #[test]
+    fn test_prompt_template() {
+        let template = PromptTemplate::new(
+            "{#if persona}You are {persona}. {#else}Hi. {/if}{#each docs as doc}[{@index}] {doc.title} {/each}{{json}}: {meta}",
+        )
+        .unwrap()
+        .with_partial("persona", serde_json::json!(""));
+        
+        assert_eq!(template.input_variables(), vec!["docs", "meta"]);
+        
+        let variables = ChainInput::new()
+            .with_variable("docs", serde_json::json!([{ "title": "a" }, { "title": "b" }]))
+            .with_variable("meta", serde_json::json!(3))
+            .variables;
+        
+        assert_eq!(template.render(&variables).unwrap(), "Hi. [0] a [1] b {json}: 3");
+        
+        let mut with_persona = variables.clone();
+        with_persona.insert("persona".to_string(), serde_json::json!("a pirate"));
+        assert!(template.render(&with_persona).unwrap().starts_with("You are a pirate. [0]"));
+        
+        let missing = template.validate(&serde_json::Map::new()).unwrap_err();
+        assert_eq!(missing, PromptError::MissingVariables(vec!["docs".to_string(), "meta".to_string()]));
+        
+        // Extra variables are ignored unless the template is strict
+        let mut extra = variables.clone();
+        extra.insert("typo".to_string(), serde_json::json!("x"));
+        assert!(template.validate(&extra).is_ok());
+        assert_eq!(
+            template.clone().strict().validate(&extra).unwrap_err(),
+            PromptError::UnexpectedVariables(vec!["typo".to_string()])
+        );
+        
+        // Unescaped braces and unclosed blocks are rejected up front
+        assert!(PromptTemplate::new(r#"Reply with {"a": 1}"#).is_err());
+        assert!(PromptTemplate::new("{#if x}never closed").is_err());
+        assert!(PromptTemplate::new("{a} {b}").unwrap().with_input_variables(["a"]).is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_few_shot_prompts() {
+        let example = |input: &str, output: &str| -> Example {
+            ChainInput::new()
+                .with_variable("input", serde_json::json!(input))
+                .with_variable("output", serde_json::json!(output))
+                .variables
+        };
+        
+        // Each rendered example is 12 characters, about 3 tokens
+        let by_length = Arc::new(LengthBasedExampleSelector::new(
+            vec![example("1+1", "2"), example("2+2", "4"), example("3+3", "6")],
+            PromptTemplate::new("Q: {input}\nA: {output}").unwrap(),
+            8,
+        ));
+        
+        let short = ChainInput::new().with_variable("question", serde_json::json!("5+5")).variables;
+        let long = ChainInput::new().with_variable("question", serde_json::json!("what is 5 plus 5?")).variables;
+        assert_eq!(by_length.select(&short).await.unwrap().len(), 2);
+        assert_eq!(by_length.select(&long).await.unwrap().len(), 1);
+        
+        let few_shot = FewShotPromptTemplate::new(
+            by_length,
+            PromptTemplate::new("Q: {input}\nA: {output}").unwrap(),
+            PromptTemplate::new("Q: {question}\nA:").unwrap(),
+        )
+        .with_prefix(PromptTemplate::new("Do the sums.").unwrap());
+        
+        assert_eq!(
+            few_shot.format(&short).await.unwrap(),
+            "Do the sums.\n\nQ: 1+1\nA: 2\n\nQ: 2+2\nA: 4\n\nQ: 5+5\nA:"
+        );
+        
+        let by_topic = Arc::new(
+            SemanticSimilarityExampleSelector::new(
+                Arc::new(TopicEmbeddings),
+                Arc::new(InMemoryVectorMemory::new()),
+                ["input"],
+            )
+            .with_k(1),
+        );
+        by_topic
+            .add_examples([example("My cat purrs", "pets"), example("Stocks fell today", "finance")])
+            .await
+            .unwrap();
+        
+        let chat = ChatPromptTemplate::from_messages([(MessageRole::System, "Classify the topic.")])
+            .unwrap()
+            .with_examples(FewShotChatMessages::new(
+                by_topic,
+                PromptTemplate::new("{input}").unwrap(),
+                PromptTemplate::new("{output}").unwrap(),
+            ))
+            .with_message(MessageRole::User, PromptTemplate::new("{input}").unwrap());
+        
+        let mock = Arc::new(MockProvider::new().with_default_reply(MockReply::text("pets")));
+        let chain = SimpleChain::new("topic", "Topic classification", mock.clone(), chat);
+        
+        let input = ChainInput::new().with_variable("input", serde_json::json!("The cat knocked a cup over"));
+        chain.execute(input).await.unwrap();
+        
+        let request = mock.last_request().unwrap();
+        let contents: Vec<&str> = request.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Classify the topic.", "My cat purrs", "pets", "The cat knocked a cup over"]);
+        assert_eq!(request.messages[2].role, MessageRole::Assistant);
+    }
+    
+    #[tokio::test]
+    async fn test_chain_definitions() {
+        let dir = tempfile::tempdir().unwrap();
+        
+        std::fs::write(
+            dir.path().join("colors.yaml"),
+            r#"
+id: colors
+type: simple
+description: Lists colours
+llm: { model: mock-large, temperature: 0.0 }
+template: "Name colours of {thing}"
+"#,
+        )
+        .unwrap();
+        
+        std::fs::write(
+            dir.path().join("pipeline.yml"),
+            r#"
+- id: count
+  type: simple
+  template: "Count: {previous_output.parsed}"
+- id: colors_then_count
+  type: sequential
+  steps:
+    - chain: colors
+      output_parser: { type: comma_list }
+    - chain: count
+"#,
+        )
+        .unwrap();
+        
+        let mock = Arc::new(MockProvider::new().with_sequence([MockReply::text("red, green"), MockReply::text("2")]));
+        
+        let llm = mock.clone();
+        let loader = ChainLoader::new(move |_chain_id, provider| match provider {
+            None => Ok(llm.clone() as Arc<dyn LLMProvider>),
+            Some(name) => Err(anyhow::anyhow!("Unknown LLM provider: {}", name)),
+        });
+        
+        let manager = ChainManager::new();
+        let definitions = definition::read_dir(dir.path()).unwrap();
+        let ids = loader.load(&definitions, &manager).unwrap();
+        assert_eq!(ids, ["colors", "count", "colors_then_count"]);
+        
+        let input = ChainInput::new().with_variable("thing", serde_json::json!("a rainbow"));
+        let output = manager.get_chain("colors_then_count").unwrap().execute(input).await.unwrap();
+        
+        assert_eq!(output.result["output"], "2");
+        assert_eq!(mock.requests()[0].model.as_deref(), Some("mock-large"));
+        assert!(mock.last_request().unwrap().prompt.contains("\"green\""));
+        
+        // Cycles and unknown providers are reported before anything is registered
+        let invalid = |yaml: &str| {
+            let file = dir.path().join("invalid.yaml");
+            std::fs::write(&file, yaml).unwrap();
+            let definitions = definition::read_file(&file).unwrap();
+            format!("{:#}", loader.build(&definitions, &ChainManager::new()).map(|_| ()).unwrap_err())
+        };
+        
+        let cycle = invalid("[{id: a, type: sequential, steps: [{chain: b}]}, {id: b, type: sequential, steps: [{chain: a}]}]");
+        assert!(cycle.contains("cycle: a -> b -> a"));
+        
+        let provider = invalid("{id: x, type: simple, llm: {provider: nope}, template: hi}");
+        assert!(provider.contains("chain 'x'") && provider.contains("nope"));
+        
+        assert!(definition::read_file(&dir.path().join("missing.yaml")).is_err());
+        
+        // Misspelled LLM settings are rejected rather than ignored
+        let typo = dir.path().join("typo.yaml");
+        std::fs::write(&typo, "{id: t, type: simple, llm: {temprature: 0.5}, template: hi}").unwrap();
+        assert!(definition::read_file(&typo).is_err());
+        
+        // YAML can't replace a chain that is already registered
+        let taken = format!("{:#}", loader.load(&definitions, &manager).unwrap_err());
+        assert!(taken.contains("chain 'colors'") && taken.contains("already registered"));
+    }
+    
+    #[tokio::test]
+    async fn test_graph_chain() {
+        let mock = Arc::new(
+            MockProvider::new()
+                .on_prompt("Outline: Rust", MockReply::text("ownership, traits"))
+                .on_prompt("Pros of ownership, traits", MockReply::text("safe"))
+                .on_prompt("Cons of ownership, traits", MockReply::text("strict"))
+                .on_prompt("Summarise Rust: safe but strict", MockReply::text("Worth it.")),
+        );
+        
+        let node = |name: &str, template: &str| -> Arc<dyn Chain> {
+            Arc::new(SimpleChain::new(name, "", mock.clone(), PromptTemplate::new(template).unwrap()))
+        };
+        
+        // outline -> (pros, cons) -> summary
+        let graph = GraphChain::new("review", "Reviews a topic")
+            .add_node("outline", node("outline", "Outline: {topic}")).unwrap()
+            .add_node("pros", node("pros", "Pros of {points}")).unwrap()
+            .add_node("cons", node("cons", "Cons of {points}")).unwrap()
+            .add_node("summary", node("summary", "Summarise {topic}: {good} but {bad}")).unwrap()
+            .add_edge("outline", "pros", [("output", "points")]).unwrap()
+            .add_edge("outline", "cons", [("output", "points")]).unwrap()
+            .add_edge("pros", "summary", [("output", "good")]).unwrap()
+            .add_edge("cons", "summary", [("output", "bad")]).unwrap()
+            .map_input("summary", "topic", "topic").unwrap();
+        
+        let input = ChainInput::new().with_variable("topic", serde_json::json!("Rust"));
+        let output = graph.execute(input).await.unwrap();
+        
+        assert_eq!(output.result["output"], "Worth it.");
+        assert_eq!(output.result["nodes"]["cons"]["output"], "strict");
+        assert_eq!(output.metadata.steps.len(), 4);
+        assert_eq!(output.metadata.steps[0].name, "outline");
+        assert!(output.metadata.total_tokens > 0);
+        
+        // A root node's mappings replace the graph input
+        let renamed = GraphChain::new("renamed", "")
+            .add_node("outline", node("outline", "Outline: {topic}")).unwrap()
+            .map_input("outline", "subject", "topic").unwrap();
+        let input = ChainInput::new()
+            .with_variable("subject", serde_json::json!("Rust"))
+            .with_variable("topic", serde_json::json!("Go"));
+        assert_eq!(renamed.execute(input).await.unwrap().result["output"], "ownership, traits");
+        
+        let graph = graph.add_edge("summary", "outline", [("output", "topic")]);
+        assert!(graph.err().unwrap().to_string().contains("cycle"));
+        
+        let unknown = GraphChain::new("empty", "").add_edge("a", "b", Vec::<(&str, &str)>::new());
+        assert!(unknown.is_err());
+        
+        let duplicate = GraphChain::new("duplicate", "")
+            .add_node("outline", node("outline", "Outline: {topic}")).unwrap()
+            .add_node("summary", node("summary", "Summarise: {points}")).unwrap()
+            .add_edge("outline", "summary", [("output", "points")]).unwrap()
+            .add_edge("outline", "summary", [("output", "text")]);
+        assert!(duplicate.err().unwrap().to_string().contains("already has an edge"));
+    }
+    
+    #[tokio::test]
+    async fn test_router_chain() {
+        let mock = Arc::new(
+            MockProvider::new()
+                .on_regex(Regex::new("^Pick the assistant").unwrap(), MockReply::text("Assistant: `pets`."))
+                .on_prompt("Pets: my cat sneezes", MockReply::text("See a vet.")),
+        );
+        
+        let manager = Arc::new(ChainManager::new());
+        for (id, template) in [("pets", "Pets: {input}"), ("finance", "Finance: {input}"), ("general", "General: {input}")] {
+            let chain = SimpleChain::new(id, format!("Answers {} questions", id), mock.clone(), PromptTemplate::new(template).unwrap());
+            manager.register_chain(id, Arc::new(chain));
+        }
+        
+        let router = RouterChain::llm("triage", "Routes questions", manager.clone(), mock.clone())
+            .with_destinations(["pets", "finance"])
+            .with_default("general");
+        
+        let input = ChainInput::new().with_variable("input", serde_json::json!("my cat sneezes"));
+        let output = router.execute(input).await.unwrap();
+        
+        assert_eq!(output.result["output"], "See a vet.");
+        assert_eq!(output.result["destination"], "pets");
+        assert_eq!(output.metadata.steps[0].name, "route");
+        assert!(output.metadata.steps[0].output.starts_with("pets"));
+        
+        let classification = &mock.requests()[0].prompt;
+        assert!(classification.contains("- finance: finance. Answers finance questions"));
+        assert!(!classification.contains("general"));
+        
+        // Embedding routing falls back to the default below `min_score`
+        let router = RouterChain::embedding("triage", "", manager.clone(), Arc::new(TopicEmbeddings))
+            .with_destinations(["pets", "finance"])
+            .with_default("general")
+            .with_min_score(0.5);
+        router.add_utterances("pets", ["My cat is sick"]).await.unwrap();
+        router.add_utterances("finance", ["Stock prices"]).await.unwrap();
+        
+        let route = |text: &str| ChainInput::new().with_variable("input", serde_json::json!(text));
+        assert_eq!(router.execute(route("Buy this stock?")).await.unwrap().result["destination"], "finance");
+        assert_eq!(router.execute(route("Nice weather")).await.unwrap().result["destination"], "general");
+        
+        let strict = RouterChain::embedding("strict", "", manager, Arc::new(TopicEmbeddings)).with_min_score(0.5);
+        assert!(strict.execute(route("Nice weather")).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_summarization_chains() {
+        let mock = Arc::new(
+            MockProvider::new()
+                .on_regex(Regex::new("^Summarize").unwrap(), MockReply::text("A part."))
+                .on_regex(Regex::new("^Combine").unwrap(), MockReply::text("The whole."))
+                .on_regex(Regex::new("^Update").unwrap(), MockReply::text("Refined.")),
+        );
+        
+        let text = (1..=60).map(|n| format!("Sentence number {} of a long document.", n)).collect::<Vec<_>>().join(" ");
+        let params = GenerationParams { max_tokens: Some(50), ..Default::default() };
+        
+        let map_reduce = MapReduceChain::new(
+            "summarize",
+            "",
+            mock.clone(),
+            PromptTemplate::new("Summarize: {text}").unwrap(),
+            PromptTemplate::new("Combine: {text}").unwrap(),
+        )
+        .with_params(params.clone())
+        .with_token_budget(200)
+        .with_max_concurrency(2);
+        
+        let input = ChainInput::new().with_variable("text", serde_json::json!(text));
+        let output = map_reduce.execute(input.clone()).await.unwrap();
+        
+        let chunks = output.result["chunks"].as_u64().unwrap() as usize;
+        assert!(chunks > 1);
+        assert_eq!(output.result["output"], "The whole.");
+        assert_eq!(mock.requests().len(), chunks + 1);
+        
+        // Every call leaves room for the reply within the budget
+        for request in mock.requests() {
+            assert!(mock.count_tokens(&request.prompt).unwrap() + 50 <= 200);
+        }
+        
+        // A text that fits in one chunk takes a single call
+        mock.clear_requests();
+        let short = ChainInput::new().with_variable("text", serde_json::json!("A short note."));
+        assert_eq!(map_reduce.execute(short).await.unwrap().result["output"], "A part.");
+        assert_eq!(mock.requests().len(), 1);
+        
+        mock.clear_requests();
+        
+        let refine = RefineChain::new(
+            "refine",
+            "",
+            mock.clone(),
+            PromptTemplate::new("Summarize: {text}").unwrap(),
+            PromptTemplate::new("Update {summary} with: {text}").unwrap(),
+        )
+        .with_params(params)
+        .with_token_budget(300);
+        
+        let output = refine.execute(input).await.unwrap();
+        
+        assert_eq!(output.result["output"], "Refined.");
+        assert_eq!(output.metadata.steps.len(), output.result["chunks"].as_u64().unwrap() as usize);
+        assert!(mock.requests()[1].prompt.starts_with("Update A part. with: "));
+        
+        // No room for text once the prompt and reply are counted
+        let tiny = MapReduceChain::new(
+            "tiny",
+            "",
+            mock.clone(),
+            PromptTemplate::new("Summarize: {text}").unwrap(),
+            PromptTemplate::new("Combine: {text}").unwrap(),
+        )
+        .with_token_budget(10);
+        assert!(tiny.execute(ChainInput::new().with_variable("text", serde_json::json!("hi"))).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_sequential_chain_variables() {
+        let mock = Arc::new(
+            MockProvider::new()
+                .on_prompt("Outline an essay on Rust", MockReply::text("safety, speed"))
+                .on_prompt("Write about Rust covering safety, speed", MockReply::text("Rust is safe and fast.")),
+        );
+        
+        let outline = SimpleChain::new("outline", "", mock.clone(), PromptTemplate::new("Outline an essay on {topic}").unwrap());
+        let essay = SimpleChain::new("essay", "", mock.clone(), PromptTemplate::new("Write about {topic} covering {points}").unwrap());
+        
+        // The second step still sees `topic`; `audience` is used by no step
+        let chain = SequentialChain::new("essay_writer", "")
+            .add_step(SequentialStep::new(Arc::new(outline)).with_output("output", "outline"))
+            .add_step(SequentialStep::new(Arc::new(essay)).with_input("topic", "topic").with_input("outline", "points"))
+            .with_output_variables(["outline", "output"]);
+        
+        let input = ChainInput::new()
+            .with_variable("topic", serde_json::json!("Rust"))
+            .with_variable("audience", serde_json::json!("students"));
+        let output = chain.execute(input).await.unwrap();
+        
+        assert_eq!(output.result, serde_json::json!({ "outline": "safety, speed", "output": "Rust is safe and fast." }));
+        assert_eq!(output.metadata.steps.len(), 2);
+        
+        // Without mappings a step gets the variables its prompt uses and the
+        // result is the last step's
+        let chain = SequentialChain::new("plain", "")
+            .add_chain(Arc::new(SimpleChain::new("a", "", mock.clone(), PromptTemplate::new("Outline an essay on {topic}").unwrap())))
+            .add_chain(Arc::new(SimpleChain::new("b", "", mock.clone(), PromptTemplate::new("Write about {topic} covering {output}").unwrap())));
+        
+        let output = chain.execute(ChainInput::new().with_variable("topic", serde_json::json!("Rust"))).await.unwrap();
+        assert_eq!(output.result["output"], "Rust is safe and fast.");
+        
+        let missing = SequentialChain::new("missing", "")
+            .add_chain(Arc::new(SimpleChain::new("a", "", mock.clone(), PromptTemplate::new("{topic}").unwrap())))
+            .with_output_variables(["summary"]);
+        assert!(missing.execute(ChainInput::new().with_variable("topic", serde_json::json!("x"))).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_failed_chain_spend() {
+        let mock = Arc::new(
+            MockProvider::new()
+                .on_prompt("Write about safety, speed", unavailable())
+                .with_default_reply(MockReply::text("safety, speed")),
+        );
+        
+        let outline = Arc::new(SimpleChain::new("outline", "", mock.clone(), PromptTemplate::new("Outline an essay on {topic}").unwrap()));
+        let input = ChainInput::new().with_variable("topic", serde_json::json!("Rust"));
+        let first = outline.execute(input.clone()).await.unwrap();
+        
+        let chain = SequentialChain::new("essay_writer", "")
+            .add_chain(outline)
+            .add_chain(Arc::new(SimpleChain::new("essay", "", mock.clone(), PromptTemplate::new("Write about {output}").unwrap())));
+        
+        let error = chain.execute(input).await.unwrap_err();
+        
+        // The finished step is still billable; the cause keeps its message and class
+        assert!(first.metadata.total_tokens > 0);
+        assert_eq!(ChainSpend::of(&error).tokens, first.metadata.total_tokens);
+        assert!(matches!(LLMError::find(&error), Some(LLMError::Transient { .. })));
+        assert_eq!(error.to_string(), "mock transient failure: unavailable");
+    }
+    
+    #[test]
+    fn test_chain_structure() {
+        let mock = Arc::new(MockProvider::new());
+        let node = |name: &str| -> Arc<dyn Chain> {
+            Arc::new(SimpleChain::new(name, "", mock.clone(), PromptTemplate::new("{text}").unwrap()))
+        };
+        
+        let graph = GraphChain::new("fan_out", "")
+            .add_node("left", node("left")).unwrap()
+            .add_node("right", node("right")).unwrap()
+            .add_edge("left", "right", [("output", "text")]).unwrap();
+        
+        let chain = SequentialChain::new("review", "")
+            .add_chain(node("draft"))
+            .add_chain(Arc::new(graph));
+        
+        let structure = serde_json::to_value(chain.structure()).unwrap();
+        assert_eq!(structure["kind"], "sequential");
+        assert_eq!(structure["nodes"][1]["kind"], "graph");
+        assert_eq!(structure["nodes"][1]["edges"][0]["fields"][0], "output as text");
+        assert_eq!(structure["edges"][0], serde_json::json!({ "from": "step_1", "to": "step_2", "fields": ["previous_output"] }));
+        
+        let dot = chain.structure().to_dot();
+        assert!(dot.contains("subgraph \"cluster_n_1\""));
+        assert!(dot.contains("\"n_0\" -> \"n_1_0\" [lhead=\"cluster_n_1\", label=\"previous_output\"];"));
+        
+        let mermaid = chain.structure().to_mermaid();
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        assert!(mermaid.contains("n_1_0 -->|\"output as text\"| n_1_1"));
+    }
+    
+    #[tokio::test]
+    async fn test_output_parsers() {
+        let list = ListOutputParser::line_separated();
+        assert_eq!(list.parse("1. red\n- green\n\n* blue").unwrap(), serde_json::json!(["red", "green", "blue"]));
+        assert_eq!(ListOutputParser::comma_separated().parse("a, b ,c,").unwrap(), serde_json::json!(["a", "b", "c"]));
+        
+        let sentiment = EnumOutputParser::new(["positive", "negative"]);
+        assert_eq!(sentiment.parse(" \"Positive.\" ").unwrap(), "positive");
+        assert!(sentiment.parse("neutral").is_err());
+        
+        let regex = RegexOutputParser::new(Regex::new(r"Score: (?P<score>\d+)").unwrap());
+        assert_eq!(regex.parse("Score: 7 out of 10").unwrap(), serde_json::json!({ "score": "7" }));
+        
+        let schema = serde_json::json!({ "type": "object", "required": ["city"] });
+        let json = JsonOutputParser::new().with_schema(schema);
+        assert!(json.parse(r#"{"country": "France"}"#).is_err());
+        
+        let mock = Arc::new(MockProvider::new().with_default_reply(MockReply::text(r#"Sure! {"city": "Paris"}"#)));
+        
+        let chain = SimpleChain::new(
+            "capital_chain",
+            "Finds a capital",
+            mock.clone(),
+            PromptTemplate::new("What is the capital of {country}?\n{format_instructions}").unwrap(),
+        )
+        .with_output_parser(Arc::new(json.clone()));
+        
+        let input = ChainInput::new().with_variable("country", serde_json::json!("France"));
+        let output = chain.execute(input).await.unwrap();
+        
+        assert_eq!(output.result["parsed"], serde_json::json!({ "city": "Paris" }));
+        
+        let prompt = mock.last_request().unwrap().prompt;
+        assert!(prompt.starts_with("What is the capital of France?\n"));
+        assert!(prompt.ends_with(&json.format_instructions().unwrap()));
+    }
+    
+    #[test]
+    fn test_pricing_table() {
+        let price = |model: &str, input: f64, effective_from: Option<&str>| ModelPrice {
+            provider: "openai".to_string(),
+            model: model.to_string(),
+            input,
+            output: 2.0 * input,
+            cached_input: Some(input / 2.0),
+            effective_from: effective_from.map(|d| d.parse().unwrap()),
+        };
+        
+        let table = PricingTable::new(vec![
+            price("gpt-4", 30.0, None),
+            price("gpt-4o", 5.0, Some("2024-05-13")),
+            price("gpt-4o", 2.5, Some("2024-10-01")),
+            price("gpt-5", 1.0, Some("2999-01-01")),
+        ]);
+        
+        let date = "2025-01-01".parse().unwrap();
+        
+        // Longest prefix wins, then the newest price already in effect
+        assert_eq!(table.lookup(None, "gpt-4o-2024-08-06", date).unwrap().input, 2.5);
+        assert_eq!(table.lookup(None, "gpt-4-0613", date).unwrap().input, 30.0);
+        assert_eq!(table.lookup(None, "gpt-4o", "2024-06-01".parse().unwrap()).unwrap().input, 5.0);
+        assert!(table.lookup(None, "gpt-5", date).is_none());
+        assert!(table.lookup(Some("anthropic"), "gpt-4o", date).is_none());
+        
+        let usage = TokenUsage::new(1_000_000, 1_000_000).with_cached_tokens(500_000);
+        assert_eq!(table.cost("openai", "gpt-4o", &usage), 0.5 * 2.5 + 0.5 * 1.25 + 5.0);
+        assert_eq!(table.cost("openai", "llama2", &usage), 0.0);
+        
+        // Self-hosted models are free even when named like a listed one
+        assert_eq!(table.cost("ollama", "gpt-4o", &usage), 0.0);
+        
+        assert!(PricingTable::builtin().cost("anthropic", "claude-3-5-sonnet-20241022", &usage) > 0.0);
+    }
+    
+    #[tokio::test]
+    async fn test_semantic_cache() {
+        let cache = Arc::new(SemanticCache::new(Arc::new(TopicEmbeddings), Arc::new(InMemoryVectorMemory::new())));
+        let mock = Arc::new(MockProvider::new().with_default_reply(MockReply::text("Cats purr.")));
+        
+        // Close matches in another scope must not crowd out this scope's entry
+        let response = mock.generate(&LLMRequest::new("cat facts")).await.unwrap();
+        for _ in 0..6 {
+            cache.store("other", &LLMRequest::new("cat facts"), vec![1.0, 0.0], &response).await.unwrap();
+        }
+        
+        let llm = cache.wrap("qa", mock.clone());
+        
+        assert_eq!(llm.generate(&LLMRequest::new("Tell me about cats")).await.unwrap().text, "Cats purr.");
+        assert_eq!(llm.generate(&LLMRequest::new("Any cat trivia?")).await.unwrap().text, "Cats purr.");
+        assert_eq!(mock.requests().len(), 2);
+        
+        // A different topic misses
+        llm.generate(&LLMRequest::new("stock prices")).await.unwrap();
+        assert_eq!(mock.requests().len(), 3);
+    }
+    
+    #[tokio::test]
+    async fn test_budgets() {
+        let ledger = tempfile::NamedTempFile::new().unwrap();
+        let url = format!("sqlite://{}", ledger.path().display());
+        
+        let limits = BudgetLimits {
+            daily: SpendLimit {
+                hard_cost_usd: Some(1.0),
+                ..SpendLimit::default()
+            },
+            monthly: SpendLimit {
+                hard_tokens: Some(1_000),
+                ..SpendLimit::default()
+            },
+        };
+        
+        let config = BudgetConfig {
+            enabled: true,
+            chains: [("summarize".to_string(), limits.clone())].into(),
+            ..BudgetConfig::default()
+        };
+        
+        let budgets = BudgetManager::connect(&url, 1, &config)
+            .await
+            .unwrap()
+            .with_tenant("key-a", "team-a", Some(limits));
+        
+        let tenant = budgets.resolve(Some("key-a")).unwrap();
+        assert_eq!(tenant.id, "team-a");
+        assert_eq!(budgets.resolve(Some("unknown")).unwrap().id, ANONYMOUS_TENANT);
+        
+        budgets.check(&tenant, Some("summarize")).await.unwrap();
+        budgets.record(&tenant, Some("summarize"), 400, 0.6).await.unwrap();
+        budgets.record(&tenant, None, 100, 0.5).await.unwrap();
+        
+        // Cost limits apply per tenant, not per chain
+        let error = budgets.check(&tenant, None).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<BudgetError>(),
+            Some(BudgetError::Exceeded { kind: LimitKind::Cost, .. })
+        ));
+        
+        let report = budgets.report(&tenant).await.unwrap();
+        assert_eq!(report.daily.requests, 2);
+        assert_eq!(report.monthly.tokens, 500);
+        assert_eq!(report.chains.len(), 1);
+        assert_eq!(report.chains[0].chain, "summarize");
+        assert_eq!(report.chains[0].daily.tokens, 400);
+        
+        // The chain's own limits count every tenant's use of it
+        let other = budgets.resolve(None).unwrap();
+        budgets.record(&other, Some("summarize"), 700, 0.0).await.unwrap();
+        let error = budgets.check(&other, Some("summarize")).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<BudgetError>(),
+            Some(BudgetError::Exceeded { kind: LimitKind::Tokens, .. })
+        ));
     }
     
     #[tokio::test]
     async fn test_rag_pipeline() {
-        // TODO: Add RAG pipeline tests
-        assert!(true);
+        let retriever = Arc::new(Retriever::new(
+            Arc::new(InMemoryVectors::default()),
+            Arc::new(LengthEmbeddings),
+            512,
+            50,
+            3,
+            0.0,
+        ));
+        
+        retriever
+            .index_document(&Document::new(
+                "ChainForge is written in Rust.".to_string(),
+                "readme".to_string(),
+            ))
+            .await
+            .unwrap();
+        
+        let mock = Arc::new(MockProvider::new().with_default_reply(MockReply::text("It uses Rust.")));
+        
+        let pipeline = RAGPipeline::new(
+            "rag",
+            "RAG pipeline",
+            mock.clone(),
+            retriever,
+            PromptTemplate::new("Context:\n{context}\n\nQuestion: {query}").unwrap(),
+        );
+        
+        let input = ChainInput::new().with_variable("query", serde_json::json!("What language?"));
+        let output = pipeline.execute(input).await.unwrap();
+        
+        assert_eq!(output.result["output"], "It uses Rust.");
+        assert_eq!(output.metadata.steps.len(), 2);
+        
+        let prompt = mock.last_request().unwrap().prompt;
+        assert!(prompt.contains("ChainForge is written in Rust."));
+        assert!(prompt.ends_with("Question: What language?"));
     }
     
     #[tokio::test]
     async fn test_agent_executor() {
-        // TODO: Add agent executor tests
-        assert!(true);
+        let mock = Arc::new(MockProvider::new().with_sequence([
+            MockReply::ToolCalls(vec![ToolCall {
+                id: "call_0".to_string(),
+                name: "uppercase".to_string(),
+                arguments: serde_json::json!({ "text": "hello" }),
+            }]),
+            MockReply::text("Thought: done\nAction: final_answer\nAction Input: HELLO"),
+        ]));
+        
+        let mut executor = AgentExecutor::new(mock.clone(), 5);
+        executor.add_tool(Arc::new(UppercaseTool));
+        
+        let result = executor.execute("Shout hello").await.unwrap();
+        
+        assert_eq!(result.final_answer, "HELLO");
+        assert_eq!(result.total_iterations, 2);
+        assert_eq!(result.steps[0].action, "uppercase");
+        assert_eq!(result.steps[0].observation, "HELLO");
+        
+        // The tool was offered natively on every turn
+        assert!(mock.requests().iter().all(|r| r.tools.iter().any(|t| t.name == "uppercase")));
+    }
+    
+    fn cassette_file(interactions: serde_json::Value) -> tempfile::NamedTempFile {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), interactions.to_string()).unwrap();
+        file
+    }
+    
+    fn replay_config(cassette: &tempfile::NamedTempFile) -> HttpConfig {
+        HttpConfig {
+            cassette: Some(CassetteConfig {
+                mode: CassetteMode::Replay,
+                path: cassette.path().to_path_buf(),
+            }),
+            ..HttpConfig::default()
+        }
+    }
+    
+    fn weather_tool() -> ToolDefinition {
+        ToolDefinition {
+            name: "get_weather".to_string(),
+            description: "Current weather for a city".to_string(),
+            parameters: serde_json::json!({ "type": "object", "properties": { "city": { "type": "string" } } }),
+        }
+    }
+    
+    struct UppercaseTool;
+    
+    #[async_trait]
+    impl Tool for UppercaseTool {
+        async fn execute(&self, input: &str) -> Result<ToolOutput> {
+            Ok(ToolOutput::success(input.to_uppercase()))
+        }
+        
+        fn name(&self) -> &str {
+            "uppercase"
+        }
+        
+        fn description(&self) -> &str {
+            "Converts text to upper case"
+        }
+        
+        fn parameters(&self) -> ToolParameters {
+            ToolParameters {
+                required: vec!["text".to_string()],
+                optional: vec![],
+                schema: serde_json::json!({ "text": "string" }),
+            }
+        }
+    }
+    
+    struct LengthEmbeddings;
+    
+    #[async_trait]
+    impl EmbeddingProvider for LengthEmbeddings {
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            Ok(texts.iter().map(|t| vec![t.len() as f32, 1.0]).collect())
+        }
+        
+        async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+            Ok(vec![text.len() as f32, 1.0])
+        }
+        
+        fn dimension(&self) -> usize {
+            2
+        }
+    }
+    
+    // One dimension per topic keyword
+    struct TopicEmbeddings;
+    
+    #[async_trait]
+    impl EmbeddingProvider for TopicEmbeddings {
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            let mut embeddings = Vec::new();
+            for text in texts {
+                embeddings.push(self.embed_query(text).await?);
+            }
+            Ok(embeddings)
+        }
+        
+        async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+            let text = text.to_lowercase();
+            Ok(["cat", "stock"].iter().map(|word| if text.contains(word) { 1.0 } else { 0.0 }).collect())
+        }
+        
+        fn dimension(&self) -> usize {
+            2
+        }
+    }
+    
+    // Returns every stored entry; enough for pipelines over a handful of chunks
+    #[derive(Default)]
+    struct InMemoryVectors {
+        entries: Mutex<Vec<(String, String, serde_json::Value)>>,
+    }
+    
+    #[async_trait]
+    impl VectorMemory for InMemoryVectors {
+        async fn store(&self, id: &str, text: &str, _embedding: Vec<f32>, metadata: serde_json::Value) -> Result<()> {
+            self.entries.lock().push((id.to_string(), text.to_string(), metadata));
+            Ok(())
+        }
+        
+        async fn search(&self, _query_embedding: Vec<f32>, top_k: usize, _threshold: f32) -> Result<Vec<SearchResult>> {
+            Ok(self
+                .entries
+                .lock()
+                .iter()
+                .take(top_k)
+                .map(|(id, text, metadata)| SearchResult {
+                    id: id.clone(),
+                    text: text.clone(),
+                    score: 1.0,
+                    metadata: metadata.clone(),
+                })
+                .collect())
+        }
+        
+        async fn delete(&self, id: &str) -> Result<()> {
+            self.entries.lock().retain(|(entry_id, _, _)| entry_id != id);
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,12 @@
+//! ChainForge library: everything the server binary is built from, exposed
+//! so integration tests can drive chains, agents and providers directly.
+
+pub mod config;
+pub mod llm;
+pub mod embeddings;
+pub mod memory;
+pub mod rag;
+pub mod chains;
+pub mod agents;
+pub mod monitoring;
+pub mod api;
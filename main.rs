@@ -1,17 +1,8 @@
 use anyhow::Result;
+use chain_forge::{api, chains, config, embeddings, llm, memory, monitoring, rag};
 use std::sync::Arc;
 use tracing::info;
 
-mod config;
-mod llm;
-mod embeddings;
-mod memory;
-mod rag;
-mod chains;
-mod agents;
-mod monitoring;
-mod api;
-
 #[tokio::main]
 async fn main() -> Result<()> {
     // Load configuration
@@ -28,15 +19,24 @@ async fn main() -> Result<()> {
     info!(" Metrics collector initialized");
     
     // Initialize LLM providers
-    let provider_manager = Arc::new(llm::provider::ProviderManager::new(&config).await?);
+    let provider_manager = Arc::new(llm::provider::ProviderManager::new(&config, metrics.clone()).await?);
     info!(" LLM providers initialized: {:?}", provider_manager.list_providers());
     
+    // Initialize semantic cache
+    let semantic_cache = setup_semantic_cache(&config).await?;
+    
+    // Initialize budgets
+    let budgets = setup_budgets(&config).await?;
+    
     // Initialize chain manager
     let chain_manager = Arc::new(chains::manager::ChainManager::new());
     info!(" Chain manager initialized");
     
     // Setup default chains
-    setup_default_chains(&config, &provider_manager, &chain_manager).await?;
+    setup_default_chains(&config, &provider_manager, &chain_manager, semantic_cache.as_ref()).await?;
+    
+    // Register chains declared in YAML
+    setup_chain_definitions(&config, provider_manager.clone(), &chain_manager, semantic_cache.clone()).await?;
     
     // Create API state
     let app_state = api::AppState {
@@ -44,6 +44,8 @@ async fn main() -> Result<()> {
         provider_manager,
         chain_manager,
         metrics,
+        semantic_cache,
+        budgets,
     };
     
     // Create router
@@ -62,13 +64,19 @@ async fn main() -> Result<()> {
     info!("  GET  /health               - Health check");
     info!("  GET  /status               - System status");
     info!("  POST /llm/generate         - Generate text");
+    info!("  POST /llm/generate/stream  - Generate text (SSE)");
     info!("  GET  /llm/providers        - List providers");
     info!("  GET  /chains               - List chains");
     info!("  POST /chains/:id/execute   - Execute chain");
+    info!("  POST /chains/:id/stream    - Execute chain (SSE)");
+    info!("  GET  /chains/:id/graph     - Chain structure (JSON, DOT or Mermaid)");
     info!("  POST /rag/index            - Index document");
     info!("  POST /rag/query            - Query with RAG");
     info!("  POST /agent/execute        - Execute agent");
     info!("  GET  /metrics              - Prometheus metrics");
+    info!("  GET  /budget/spend         - Spend of the calling tenant");
+    info!("  POST   /admin/semantic-cache/invalidate - Invalidate similar cached prompts");
+    info!("  DELETE /admin/semantic-cache/:id        - Delete a cached entry");
     info!("");
     info!("Ready to process requests!");
     
@@ -77,34 +85,166 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+async fn setup_budgets(
+    config: &config::AppConfig,
+) -> Result<Option<Arc<monitoring::budget::BudgetManager>>> {
+    if !config.budgets.enabled {
+        return Ok(None);
+    }
+    
+    let budgets = monitoring::budget::BudgetManager::connect(
+        &config.database.url,
+        config.database.max_connections,
+        &config.budgets,
+    )
+    .await?;
+    
+    info!(" Budgets enabled for {} tenant(s)", config.budgets.tenants.len());
+    
+    Ok(Some(Arc::new(budgets)))
+}
+
+async fn setup_semantic_cache(
+    config: &config::AppConfig,
+) -> Result<Option<Arc<llm::semantic_cache::SemanticCache>>> {
+    use embeddings::EmbeddingProvider;
+    
+    let semantic_config = &config.llm.semantic_cache;
+    if !semantic_config.enabled {
+        return Ok(None);
+    }
+    
+    let embeddings = Arc::new(embeddings::fastembed_provider::FastEmbedProvider::new(&config.embeddings.model)?);
+    let store = Arc::new(
+        memory::vector::QdrantVectorMemory::new(
+            &config.memory.qdrant.url,
+            semantic_config.collection_name.clone(),
+            embeddings.dimension(),
+        )
+        .await?,
+    );
+    
+    info!(" Semantic cache initialized for chains: {:?}", semantic_config.chains);
+    
+    Ok(Some(Arc::new(
+        llm::semantic_cache::SemanticCache::new(embeddings, store).with_config(semantic_config),
+    )))
+}
+
+async fn setup_chain_definitions(
+    config: &config::AppConfig,
+    provider_manager: Arc<llm::provider::ProviderManager>,
+    chain_manager: &chains::manager::ChainManager,
+    semantic_cache: Option<Arc<llm::semantic_cache::SemanticCache>>,
+) -> Result<()> {
+    use embeddings::EmbeddingProvider;
+    
+    let Some(dir) = &config.chains.definitions_dir else {
+        return Ok(());
+    };
+    
+    let definitions = chains::definition::read_dir(dir)?;
+    
+    let cached_chains = config.llm.semantic_cache.chains.clone();
+    let mut loader = chains::definition::ChainLoader::new(move |chain_id, provider| {
+        let llm = provider_manager.get_provider(provider)?;
+        
+        Ok(match &semantic_cache {
+            Some(cache) if cached_chains.iter().any(|c| c == chain_id) => cache.wrap(chain_id, llm),
+            _ => llm,
+        })
+    });
+    
+    // RAG chains retrieve from Qdrant collections embedded with the configured model
+    let mut collections: Vec<&str> = definitions.iter().filter_map(|d| d.retriever()).collect();
+    collections.sort();
+    collections.dedup();
+    
+    if !collections.is_empty() {
+        let embeddings = Arc::new(embeddings::fastembed_provider::FastEmbedProvider::new(&config.embeddings.model)?);
+        
+        for collection in collections {
+            let store = memory::vector::QdrantVectorMemory::new(
+                &config.memory.qdrant.url,
+                collection.to_string(),
+                embeddings.dimension(),
+            )
+            .await?;
+            
+            let retriever = rag::retriever::Retriever::new(
+                Arc::new(store),
+                embeddings.clone(),
+                config.rag.chunk_size,
+                config.rag.chunk_overlap,
+                config.rag.retrieval_top_k,
+                config.rag.similarity_threshold,
+            );
+            
+            loader = loader.with_retriever(collection, Arc::new(retriever));
+        }
+    }
+    
+    let ids = loader.load(&definitions, chain_manager)?;
+    info!("✅ Chains loaded from {}: {}", dir.display(), ids.join(", "));
+    
+    Ok(())
+}
+
 async fn setup_default_chains(
     config: &config::AppConfig,
     provider_manager: &llm::provider::ProviderManager,
     chain_manager: &chains::manager::ChainManager,
+    semantic_cache: Option<&Arc<llm::semantic_cache::SemanticCache>>,
 ) -> Result<()> {
-    // Create a simple Q&A chain
     let llm = provider_manager.get_provider(None)?;
     
+    // Chains listed under `llm.semantic_cache.chains` get their own cache scope
+    let llm_for = |chain_id: &str| match semantic_cache {
+        Some(cache) if config.llm.semantic_cache.chains.iter().any(|c| c == chain_id) => {
+            cache.wrap(chain_id, llm.clone())
+        }
+        _ => llm.clone(),
+    };
+    
+    // Create a simple Q&A chain
     let qa_chain = chains::simple::SimpleChain::new(
         "qa_chain",
         "Simple question-answering chain",
-        llm.clone(),
-        "Answer the following question: {question}",
+        llm_for("qa"),
+        chains::prompt::PromptTemplate::new("Answer the following question: {question}")?,
     );
     
     chain_manager.register_chain("qa", Arc::new(qa_chain));
     
-    // Create a summarization chain
-    let summarize_chain = chains::simple::SimpleChain::new(
+    // Create summarization chains; long texts are split to fit the token budget,
+    // short ones take a single call as before
+    let summarize_chain = chains::summarize::MapReduceChain::new(
         "summarize_chain",
-        "Text summarization chain",
-        llm.clone(),
-        "Summarize the following text in 2-3 sentences:\n\n{text}",
-    );
+        "Text summarization chain (map-reduce)",
+        llm_for("summarize"),
+        chains::prompt::PromptTemplate::new("Summarize the following text in 2-3 sentences:\n\n{text}")?,
+        chains::prompt::PromptTemplate::new("Combine these summaries into one summary of 2-3 sentences:\n\n{text}")?,
+    )
+    .with_token_budget(config.chains.summarize_token_budget)
+    .with_chunk_overlap(config.rag.chunk_overlap);
     
     chain_manager.register_chain("summarize", Arc::new(summarize_chain));
     
-    info!("✅ Default chains registered: qa, summarize");
+    let refine_chain = chains::summarize::RefineChain::new(
+        "summarize_refine_chain",
+        "Text summarization chain (refine)",
+        llm_for("summarize_refine"),
+        chains::prompt::PromptTemplate::new("Summarize the following text in 2-3 sentences:\n\n{text}")?,
+        chains::prompt::PromptTemplate::new(
+            "Here is a summary so far:\n\n{summary}\n\nUpdate it in 2-3 sentences with this additional text:\n\n{text}",
+        )?,
+    )
+    .with_token_budget(config.chains.summarize_token_budget)
+    .with_chunk_overlap(config.rag.chunk_overlap);
+    
+    chain_manager.register_chain("summarize_refine", Arc::new(refine_chain));
+    
+    info!("✅ Default chains registered: qa, summarize, summarize_refine");
     
     Ok(())
 }